@@ -5,6 +5,7 @@ use raft::{storage::MemStorage, Config, Raft};
 pub fn bench_raft(c: &mut Criterion) {
     bench_raft_new(c);
     bench_raft_campaign(c);
+    bench_raft_bcast_append(c);
 }
 
 fn quick_raft(voters: usize, learners: usize) -> Raft<MemStorage> {
@@ -67,3 +68,30 @@ pub fn bench_raft_campaign(c: &mut Criterion) {
             }
         });
 }
+
+pub fn bench_raft_bcast_append(c: &mut Criterion) {
+    let bench = |voters: u64, snapshotting: u64| {
+        move |b: &mut Bencher| {
+            let storage =
+                MemStorage::new_with_conf_state(((1..=voters).collect::<Vec<_>>(), vec![]));
+            let mut raft = Raft::new(&Config::new(1), storage).unwrap();
+            raft.become_candidate();
+            raft.become_leader();
+            for id in 2..=voters - snapshotting {
+                raft.mut_prs().get_mut(id).unwrap().become_replicate();
+            }
+            for id in voters - snapshotting + 1..=voters {
+                raft.set_peer_snapshot_pending(id, 1).unwrap();
+            }
+            b.iter(|| {
+                raft.bcast_append();
+                raft.msgs.clear();
+            })
+        }
+    };
+
+    c.bench_function(
+        "Raft::bcast_append (50 voters, 40 snapshotting)",
+        bench(50, 40),
+    );
+}
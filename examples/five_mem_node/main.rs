@@ -274,7 +274,7 @@ fn on_ready(
         }
     }
     // Call `RawNode::advance` interface to update position flags in the raft.
-    raft_group.advance(ready);
+    raft_group.advance(ready).unwrap();
 }
 
 fn example_config() -> Config {
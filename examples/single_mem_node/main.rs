@@ -171,7 +171,7 @@ fn on_ready(r: &mut RawNode<MemStorage>, cbs: &mut HashMap<u8, ProposeCallback>)
     }
 
     // Advance the Raft
-    r.advance(ready);
+    r.advance(ready).unwrap();
 }
 
 fn send_propose(sender: mpsc::Sender<Msg>) {
@@ -25,12 +25,61 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::{Arc, Mutex};
+
+use super::eraftpb::Message;
+use super::progress::ProgressState;
 pub use super::read_only::{ReadOnlyOption, ReadState};
 use super::{
     errors::{Error, Result},
     INVALID_ID,
 };
 
+/// A codec used to compress the data of entries sent in `MsgAppend`, see `Config::entry_codec`.
+///
+/// It's purely a transport optimization: a follower decompresses the entries before appending
+/// them, so the log only ever holds the original data. `decompress` must return exactly the
+/// bytes given to `compress`.
+pub trait EntryCodec: Send + Sync {
+    /// Compresses the data of an entry.
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Restores the data compressed by `compress`.
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// A strategy picking the randomized election timeout, see
+/// `Config::election_timeout_strategy`.
+pub trait ElectionTimeoutStrategy: Send {
+    /// Returns the next election timeout of the node `node_id`, in ticks. It must be in
+    /// `[min, max)`, values outside of it are clamped.
+    fn next_election_timeout(&mut self, min: usize, max: usize, node_id: u64) -> usize;
+}
+
+/// A callback receiving read states as soon as they are ready, see
+/// `Config::read_state_callback`.
+pub type ReadStateCallback = Arc<dyn Fn(ReadState) + Send + Sync>;
+
+/// A hook receiving the id of a peer and the old and new state of its progress whenever the
+/// leader moves it to another state, see `Config::progress_state_hook`.
+pub type ProgressStateHook = Arc<dyn Fn(u64, ProgressState, ProgressState) + Send + Sync>;
+
+/// A hook receiving the id of a peer and the index of the snapshot the leader stopped sending
+/// it because the peer caught up through the log, see `Config::snapshot_abort_hook`.
+pub type SnapshotAbortHook = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+/// A hook receiving the buffered proposals which were dropped when stepped again, see
+/// `Config::dropped_proposal_hook`.
+pub type DroppedProposalHook = Arc<dyn Fn(Message) + Send + Sync>;
+
+/// A hook receiving the term and the leader, possibly `INVALID_ID`, whenever the node becomes a
+/// follower or learns the leader it follows, see `Config::become_follower_hook`.
+pub type BecomeFollowerHook = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+/// A hook receiving the old and new commit index whenever the commit index advances, see
+/// `Config::commit_hook`.
+pub type CommitHook = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
 /// Config contains the parameters to start a raft.
 #[derive(Clone)]
 pub struct Config {
@@ -40,7 +89,7 @@ pub struct Config {
     /// The number of node.tick invocations that must pass between
     /// elections. That is, if a follower does not receive any message from the
     /// leader of current term before ElectionTick has elapsed, it will become
-    /// candidate and start an election. election_tick must be greater than
+    /// candidate and start an election. election_tick must be at least twice
     /// HeartbeatTick. We suggest election_tick = 10 * HeartbeatTick to avoid
     /// unnecessary leader switching
     pub election_tick: usize,
@@ -89,11 +138,16 @@ pub struct Config {
     /// Choose the linearizability mode or the lease mode to read data. If you don’t care about the read consistency and want a higher read performance, you can use the lease mode.
     ///
     /// Setting this to `LeaseBased` requires `check_quorum = true`.
+    ///
+    /// The option only takes effect on the leader. Followers and learners always forward
+    /// `MsgReadIndex` to the leader, so a learner can't serve reads on its own.
     pub read_only_option: ReadOnlyOption,
 
     /// Don't broadcast an empty raft entry to notify follower to commit an entry.
     /// This may make follower wait a longer time to apply an entry. This configuration
     /// May affect proposal forwarding and follower read.
+    ///
+    /// Caught up followers still learn the commit index from the next heartbeat.
     pub skip_bcast_commit: bool,
 
     /// A human-friendly tag used for logging.
@@ -101,6 +155,193 @@ pub struct Config {
 
     /// Batches every append msg if any append msg already exists
     pub batch_append: bool,
+
+    /// The maximum number of entries the last index of a transfer target may lag behind the
+    /// leader's commit index carried by `MsgTimeoutNow` for the target to still campaign.
+    /// A target which is in the middle of receiving a snapshot never campaigns.
+    pub timeout_now_max_lag: u64,
+
+    /// The cap, in ticks, of the exponential backoff applied to the election timeout after
+    /// consecutive failed pre-vote rounds. The backoff is cleared once a leader is heard from.
+    ///
+    /// 0 disables the backoff.
+    pub prevote_backoff_cap_ticks: usize,
+
+    /// The maximum number of entries a pre-candidate's last index may be behind the voter's
+    /// committed index for the voter to grant it a pre-vote, even if its log is otherwise up
+    /// to date. A briefly reachable node lagging that far couldn't lead usefully, so it's kept
+    /// from disrupting the cluster. Real votes are not affected. 0 disables the check.
+    pub prevote_max_lag: u64,
+
+    /// The minimum number of entries the commit index must advance by since it was last
+    /// broadcast before the leader broadcasts it again with empty appends. Smaller advances
+    /// reach the followers with the next append or heartbeat, unless a configuration change
+    /// is pending. 0 or 1 broadcasts every advance.
+    pub min_commit_bcast_delta: u64,
+
+    /// The maximum number of terms a message's term may be ahead of the local term. Messages
+    /// further ahead are treated as corrupt and dropped instead of having their term adopted.
+    ///
+    /// This guards against a faulty peer or corrupted storage inflating the term. Setting it
+    /// too low can prevent a node from rejoining after a long partition in which the rest of
+    /// the cluster went through many elections, so it should be generous. 0 disables the check.
+    pub max_term_gap: u64,
+
+    /// Makes `step` return `Error::WrongDestination` for a message whose `to` isn't this node,
+    /// to catch transports which route messages to the wrong raft group or peer. Local
+    /// messages, which have no `to`, are not checked.
+    pub strict_message_routing: bool,
+
+    /// The codec used to compress the data of entries sent to followers. Every peer of the
+    /// group must be configured with the same codec, since followers without it drop
+    /// compressed appends. `None` disables compression.
+    pub entry_codec: Option<Arc<dyn EntryCodec>>,
+
+    /// Only entries whose data is larger than this many bytes are compressed, and only if
+    /// the compressed data is smaller. Has no effect without an `entry_codec`.
+    pub compression_threshold: usize,
+
+    /// Receives every `ReadState` as soon as it's produced, instead of it being queued in
+    /// `read_states` until the next `Ready`. `None` keeps the queueing.
+    ///
+    /// The callback runs synchronously while the raft is stepping a message, so it must not
+    /// call back into the same `Raft` or `RawNode`, and should only hand the state over, for
+    /// example by completing the waiting read.
+    pub read_state_callback: Option<ReadStateCallback>,
+
+    /// Makes followers drop `MsgPropose` and `MsgReadIndex` with `Error::ProposalDropped`
+    /// instead of forwarding them to the leader, for deployments where clients route to the
+    /// leader themselves. Forwarding would otherwise hide such routing bugs.
+    pub disable_proposal_forwarding: bool,
+
+    /// Makes the leader delegate its lease to followers in heartbeats, so they can serve reads
+    /// locally, see `Raft::follower_read_index`. Requires `check_quorum`.
+    ///
+    /// A follower only knows what was committed as of the last append or heartbeat it
+    /// received, so such reads may miss the latest writes. Like `ReadOnlyOption::LeaseBased`,
+    /// it also relies on bounded clock drift, and the delegated lease is one heartbeat
+    /// interval shorter than the leader's to cover the heartbeat's delivery.
+    pub delegate_read_lease: bool,
+
+    /// Makes the leader skip a `MsgAppend` identical to the last one it sent to the same peer
+    /// since its last heartbeat, for example the empty appends regenerated after a brief
+    /// stall. A peer which lost the first one still gets it again after the next heartbeat.
+    pub suppress_duplicate_appends: bool,
+
+    /// The maximum number of proposals a node keeps while it knows of no leader, e.g. while
+    /// campaigning, instead of dropping them with `Error::ProposalDropped`. They are forwarded
+    /// once a leader is known, or proposed locally if the node wins the election. Proposals
+    /// beyond the limit are still dropped. 0 disables the buffering, and so does
+    /// `disable_proposal_forwarding`.
+    pub buffer_proposals_without_leader: usize,
+
+    /// Called with each buffered proposal, see `buffer_proposals_without_leader`, which is
+    /// dropped when stepped again once a leader is known, as the caller was already told it
+    /// was accepted. Like `progress_state_hook`, it must not call back into the same `Raft` or
+    /// `RawNode`.
+    pub dropped_proposal_hook: Option<DroppedProposalHook>,
+
+    /// Called whenever the leader moves the progress of a peer between `Probe`, `Replicate`
+    /// and `Snapshot`, e.g. to build a timeline of replication per peer. The progress of every
+    /// peer is reset to `Probe` when a node becomes leader, which isn't reported. `None` by
+    /// default.
+    ///
+    /// Like `read_state_callback`, the hook runs synchronously while the raft is stepping a
+    /// message, so it must not call back into the same `Raft` or `RawNode`.
+    pub progress_state_hook: Option<ProgressStateHook>,
+
+    /// Starts the node as a learner even if the initial conf state doesn't list it, e.g. when
+    /// bootstrapping a node which is only added as a learner later, so that it never campaigns
+    /// meanwhile. Creating the raft fails if the initial conf state lists the node as a voter.
+    pub is_learner: bool,
+
+    /// The number of ticks after which the leader considers a peer unresponsive if it hasn't
+    /// answered a heartbeat, see `Raft::unresponsive_peers`. It allows reacting to a failed
+    /// peer well before `check_quorum` would notice. 0 disables the detection.
+    pub heartbeat_response_timeout_ticks: usize,
+
+    /// Makes a regression of `applied` a hard failure for state machines which persist
+    /// `applied` and can't apply an entry twice. It regressed if it's set but behind the
+    /// snapshot of the storage. Creating the raft then fails with `Error::ConfigInvalid`,
+    /// rather than panicking inside the library, instead of logging a warning and resuming
+    /// from the snapshot. An unset `applied` isn't checked.
+    pub panic_on_applied_regression: bool,
+
+    /// The ticks the randomized election timeout is biased by around leadership transfers:
+    /// a leader stepping down waits that much longer before campaigning again, and a
+    /// transfer target campaigning on `MsgTimeoutNow` retries that much sooner if its
+    /// election fails, so transfers converge instead of bouncing back. 0 disables the bias.
+    pub transfer_election_bias_ticks: usize,
+
+    /// Broadcasts appends, heartbeats and vote requests to the peers in ascending id order
+    /// instead of the arbitrary order of the progress set, so the queued messages are
+    /// reproducible, e.g. for tests. It costs sorting the peer ids on every broadcast.
+    pub deterministic_broadcast_order: bool,
+
+    /// Lets the leader persist its own entries asynchronously: it only counts the entries it
+    /// appended towards the commit quorum once `Raft::advance_persisted`, or
+    /// `RawNode::on_persist_ready`, reports them durable, rather than as soon as they are
+    /// appended to its log.
+    ///
+    /// This only covers the leader's self-acknowledgement. Followers still acknowledge appends
+    /// as soon as they are stepped, so they must persist the entries of a `Ready` before
+    /// sending its messages as usual.
+    pub async_storage_writes: bool,
+
+    /// The ticks during which the leader doesn't send a follower a snapshot again if the new
+    /// one isn't more recent than the last one it sent, e.g. to a follower flapping in and out
+    /// of connectivity. It probes whether the follower retained the previous snapshot instead.
+    /// 0 disables the cooldown.
+    pub snapshot_cooldown_ticks: usize,
+
+    /// Picks the randomized election timeout instead of sampling it uniformly from
+    /// `[min_election_tick, max_election_tick)`, e.g. to let the nodes with lower ids time
+    /// out sooner and be preferred as leader, or to make tests reproducible. A strategy shared
+    /// by several rafts is called by all of them.
+    pub election_timeout_strategy: Option<Arc<Mutex<dyn ElectionTimeoutStrategy>>>,
+
+    /// Called when the leader abandons a pending snapshot because the follower acknowledged
+    /// entries up to the snapshot index through regular appends, or reported it already
+    /// committed them, i.e. the snapshot wasn't needed. Frequent aborts suggest keeping more
+    /// log before compacting. Like `progress_state_hook`, it must not call back into the same
+    /// `Raft` or `RawNode`.
+    pub snapshot_abort_hook: Option<SnapshotAbortHook>,
+
+    /// Called with the old and new commit index whenever the commit index advances, on the
+    /// leader as entries are acknowledged and on followers as appends and heartbeats carry
+    /// the leader's commit index, e.g. for change data capture or to measure commit latency
+    /// without diffing `HardState` across `Ready`s. Commits implied by restoring a snapshot
+    /// aren't reported. Like `progress_state_hook`, it must not call back into the same `Raft`
+    /// or `RawNode`.
+    pub commit_hook: Option<CommitHook>,
+
+    /// Called with the term and the leader whenever the node becomes a follower, or learns the
+    /// leader of its term, e.g. to redirect clients to the new leader. Being told again to
+    /// follow the leader it already follows, e.g. by each of its appends, isn't reported. Like
+    /// `progress_state_hook`, it must not call back into the same `Raft` or `RawNode`.
+    pub become_follower_hook: Option<BecomeFollowerHook>,
+
+    /// **Unsafe, opt-in.** Counts the heartbeat acknowledgements of learners towards the
+    /// quorum confirming a `ReadOnlyOption::Safe` read index, as if they were voters, to
+    /// lower read latency when learners are as available as voters.
+    ///
+    /// This breaks the linearizability of reads: the acknowledging set may miss a majority of
+    /// the voters, so a deposed leader partitioned with learners can still serve a stale read
+    /// after a new leader committed newer writes. Only consider it if learners are co-located
+    /// with voters and such partitions can't happen. Elections and commitment never count
+    /// learners. A warning is logged when a raft starts with it set.
+    pub count_learners_in_read_quorum: bool,
+
+    /// Makes a candidate step down and grant its vote to a candidate of the same term with a
+    /// lower id and an equally up-to-date log, instead of both splitting the votes, e.g. with
+    /// an even number of voters during a joint membership change. The deferring candidate
+    /// only gives up its vote for itself, which no one else counted.
+    pub prefer_lower_id_candidate: bool,
+
+    /// Records the index ranges of uncommitted entries overwritten by appends from a new
+    /// leader, for `Raft::take_overwritten_entries`. The ranges accumulate until taken, so
+    /// only enable it if the application takes them regularly.
+    pub record_overwritten_entries: bool,
 }
 
 impl Default for Config {
@@ -121,11 +362,47 @@ impl Default for Config {
             skip_bcast_commit: false,
             tag: "".into(),
             batch_append: false,
+            timeout_now_max_lag: 0,
+            prevote_backoff_cap_ticks: 0,
+            prevote_max_lag: 0,
+            min_commit_bcast_delta: 0,
+            max_term_gap: 0,
+            strict_message_routing: false,
+            entry_codec: None,
+            compression_threshold: 0,
+            read_state_callback: None,
+            disable_proposal_forwarding: false,
+            delegate_read_lease: false,
+            suppress_duplicate_appends: false,
+            buffer_proposals_without_leader: 0,
+            dropped_proposal_hook: None,
+            progress_state_hook: None,
+            is_learner: false,
+            heartbeat_response_timeout_ticks: 0,
+            panic_on_applied_regression: false,
+            transfer_election_bias_ticks: 0,
+            deterministic_broadcast_order: false,
+            async_storage_writes: false,
+            snapshot_cooldown_ticks: 0,
+            election_timeout_strategy: None,
+            snapshot_abort_hook: None,
+            commit_hook: None,
+            become_follower_hook: None,
+            count_learners_in_read_quorum: false,
+            prefer_lower_id_candidate: false,
+            record_overwritten_entries: false,
         }
     }
 }
 
 impl Config {
+    /// Returns a builder which validates the config when built. This is the recommended
+    /// way to construct a `Config`.
+    #[inline]
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+
     /// Creates a new config.
     pub fn new(id: u64) -> Self {
         Self {
@@ -173,6 +450,16 @@ impl Config {
             ));
         }
 
+        // With fewer than two heartbeats per election timeout, a single delayed heartbeat
+        // triggers an election.
+        if self.election_tick < 2 * self.heartbeat_tick {
+            return Err(Error::ConfigInvalid(format!(
+                "election tick {} must be at least twice the heartbeat tick {}, \
+                 election_tick >= 10 * heartbeat_tick is recommended",
+                self.election_tick, self.heartbeat_tick
+            )));
+        }
+
         let min_timeout = self.min_election_tick();
         let max_timeout = self.max_election_tick();
         if min_timeout < self.election_tick {
@@ -201,6 +488,367 @@ impl Config {
             ));
         }
 
+        if self.delegate_read_lease && !self.check_quorum {
+            return Err(Error::ConfigInvalid(
+                "delegate_read_lease requires check_quorum == true".into(),
+            ));
+        }
+
         Ok(())
     }
 }
+
+/// A builder of `Config` which validates it when built.
+///
+/// ```rust
+/// use raft::ConfigBuilder;
+///
+/// let config = ConfigBuilder::new()
+///     .id(1)
+///     .election_tick(10)
+///     .heartbeat_tick(3)
+///     .build()
+///     .unwrap();
+/// assert_eq!(config.election_tick, 10);
+///
+/// // Invalid configurations are refused.
+/// assert!(ConfigBuilder::new().id(1).election_tick(1).build().is_err());
+/// ```
+#[derive(Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Creates a builder starting from the default config.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The identity of the local raft, see `Config::id`.
+    #[inline]
+    pub fn id(mut self, id: u64) -> Self {
+        self.config.id = id;
+        self
+    }
+
+    /// The number of ticks between elections, see `Config::election_tick`.
+    #[inline]
+    pub fn election_tick(mut self, election_tick: usize) -> Self {
+        self.config.election_tick = election_tick;
+        self
+    }
+
+    /// The number of ticks between heartbeats, see `Config::heartbeat_tick`.
+    #[inline]
+    pub fn heartbeat_tick(mut self, heartbeat_tick: usize) -> Self {
+        self.config.heartbeat_tick = heartbeat_tick;
+        self
+    }
+
+    /// The last applied index, see `Config::applied`.
+    #[inline]
+    pub fn applied(mut self, applied: u64) -> Self {
+        self.config.applied = applied;
+        self
+    }
+
+    /// The max size of each append message, see `Config::max_size_per_msg`.
+    #[inline]
+    pub fn max_size_per_msg(mut self, max_size_per_msg: u64) -> Self {
+        self.config.max_size_per_msg = max_size_per_msg;
+        self
+    }
+
+    /// The max number of in-flight append messages, see `Config::max_inflight_msgs`.
+    #[inline]
+    pub fn max_inflight_msgs(mut self, max_inflight_msgs: usize) -> Self {
+        self.config.max_inflight_msgs = max_inflight_msgs;
+        self
+    }
+
+    /// Whether the leader checks quorum activity, see `Config::check_quorum`.
+    #[inline]
+    pub fn check_quorum(mut self, check_quorum: bool) -> Self {
+        self.config.check_quorum = check_quorum;
+        self
+    }
+
+    /// Whether the Pre-Vote algorithm is enabled, see `Config::pre_vote`.
+    #[inline]
+    pub fn pre_vote(mut self, pre_vote: bool) -> Self {
+        self.config.pre_vote = pre_vote;
+        self
+    }
+
+    /// The lower bound of the election timeout, see `Config::min_election_tick`.
+    #[inline]
+    pub fn min_election_tick(mut self, min_election_tick: usize) -> Self {
+        self.config.min_election_tick = min_election_tick;
+        self
+    }
+
+    /// The upper bound of the election timeout, see `Config::max_election_tick`.
+    #[inline]
+    pub fn max_election_tick(mut self, max_election_tick: usize) -> Self {
+        self.config.max_election_tick = max_election_tick;
+        self
+    }
+
+    /// The read only mode, see `Config::read_only_option`.
+    #[inline]
+    pub fn read_only_option(mut self, read_only_option: ReadOnlyOption) -> Self {
+        self.config.read_only_option = read_only_option;
+        self
+    }
+
+    /// Whether to skip broadcasting commits, see `Config::skip_bcast_commit`.
+    #[inline]
+    pub fn skip_bcast_commit(mut self, skip_bcast_commit: bool) -> Self {
+        self.config.skip_bcast_commit = skip_bcast_commit;
+        self
+    }
+
+    /// A human-friendly tag used for logging, see `Config::tag`.
+    #[inline]
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.config.tag = tag.into();
+        self
+    }
+
+    /// Whether append messages are batched, see `Config::batch_append`.
+    #[inline]
+    pub fn batch_append(mut self, batch_append: bool) -> Self {
+        self.config.batch_append = batch_append;
+        self
+    }
+
+    /// The allowed lag of a transfer target, see `Config::timeout_now_max_lag`.
+    #[inline]
+    pub fn timeout_now_max_lag(mut self, timeout_now_max_lag: u64) -> Self {
+        self.config.timeout_now_max_lag = timeout_now_max_lag;
+        self
+    }
+
+    /// The cap of the pre-vote backoff, see `Config::prevote_backoff_cap_ticks`.
+    #[inline]
+    pub fn prevote_backoff_cap_ticks(mut self, prevote_backoff_cap_ticks: usize) -> Self {
+        self.config.prevote_backoff_cap_ticks = prevote_backoff_cap_ticks;
+        self
+    }
+
+    /// The allowed lag of a pre-candidate, see `Config::prevote_max_lag`.
+    #[inline]
+    pub fn prevote_max_lag(mut self, prevote_max_lag: u64) -> Self {
+        self.config.prevote_max_lag = prevote_max_lag;
+        self
+    }
+
+    /// The minimum commit advance to broadcast, see `Config::min_commit_bcast_delta`.
+    #[inline]
+    pub fn min_commit_bcast_delta(mut self, min_commit_bcast_delta: u64) -> Self {
+        self.config.min_commit_bcast_delta = min_commit_bcast_delta;
+        self
+    }
+
+    /// The maximum accepted term gap, see `Config::max_term_gap`.
+    #[inline]
+    pub fn max_term_gap(mut self, max_term_gap: u64) -> Self {
+        self.config.max_term_gap = max_term_gap;
+        self
+    }
+
+    /// Whether misrouted messages are refused, see `Config::strict_message_routing`.
+    #[inline]
+    pub fn strict_message_routing(mut self, strict_message_routing: bool) -> Self {
+        self.config.strict_message_routing = strict_message_routing;
+        self
+    }
+
+    /// The codec compressing sent entries, see `Config::entry_codec`.
+    #[inline]
+    pub fn entry_codec(mut self, entry_codec: Arc<dyn EntryCodec>) -> Self {
+        self.config.entry_codec = Some(entry_codec);
+        self
+    }
+
+    /// The size above which entries are compressed, see `Config::compression_threshold`.
+    #[inline]
+    pub fn compression_threshold(mut self, compression_threshold: usize) -> Self {
+        self.config.compression_threshold = compression_threshold;
+        self
+    }
+
+    /// The callback receiving read states, see `Config::read_state_callback`.
+    #[inline]
+    pub fn read_state_callback(mut self, read_state_callback: ReadStateCallback) -> Self {
+        self.config.read_state_callback = Some(read_state_callback);
+        self
+    }
+
+    /// Whether followers refuse to forward proposals, see
+    /// `Config::disable_proposal_forwarding`.
+    #[inline]
+    pub fn disable_proposal_forwarding(mut self, disable_proposal_forwarding: bool) -> Self {
+        self.config.disable_proposal_forwarding = disable_proposal_forwarding;
+        self
+    }
+
+    /// Whether the leader delegates its lease to followers, see
+    /// `Config::delegate_read_lease`.
+    #[inline]
+    pub fn delegate_read_lease(mut self, delegate_read_lease: bool) -> Self {
+        self.config.delegate_read_lease = delegate_read_lease;
+        self
+    }
+
+    /// Whether the leader skips resending identical appends, see
+    /// `Config::suppress_duplicate_appends`.
+    #[inline]
+    pub fn suppress_duplicate_appends(mut self, suppress_duplicate_appends: bool) -> Self {
+        self.config.suppress_duplicate_appends = suppress_duplicate_appends;
+        self
+    }
+
+    /// The number of proposals kept while there is no leader, see
+    /// `Config::buffer_proposals_without_leader`.
+    #[inline]
+    pub fn buffer_proposals_without_leader(
+        mut self,
+        buffer_proposals_without_leader: usize,
+    ) -> Self {
+        self.config.buffer_proposals_without_leader = buffer_proposals_without_leader;
+        self
+    }
+
+    /// The hook observing dropped buffered proposals, see `Config::dropped_proposal_hook`.
+    #[inline]
+    pub fn dropped_proposal_hook(mut self, dropped_proposal_hook: DroppedProposalHook) -> Self {
+        self.config.dropped_proposal_hook = Some(dropped_proposal_hook);
+        self
+    }
+
+    /// The hook observing progress state changes, see `Config::progress_state_hook`.
+    #[inline]
+    pub fn progress_state_hook(mut self, progress_state_hook: ProgressStateHook) -> Self {
+        self.config.progress_state_hook = Some(progress_state_hook);
+        self
+    }
+
+    /// Whether the node starts as a learner, see `Config::is_learner`.
+    #[inline]
+    pub fn is_learner(mut self, is_learner: bool) -> Self {
+        self.config.is_learner = is_learner;
+        self
+    }
+
+    /// The ticks a heartbeat may go unanswered, see
+    /// `Config::heartbeat_response_timeout_ticks`.
+    #[inline]
+    pub fn heartbeat_response_timeout_ticks(
+        mut self,
+        heartbeat_response_timeout_ticks: usize,
+    ) -> Self {
+        self.config.heartbeat_response_timeout_ticks = heartbeat_response_timeout_ticks;
+        self
+    }
+
+    /// Whether an applied index regression is fatal, see
+    /// `Config::panic_on_applied_regression`.
+    #[inline]
+    pub fn panic_on_applied_regression(mut self, panic_on_applied_regression: bool) -> Self {
+        self.config.panic_on_applied_regression = panic_on_applied_regression;
+        self
+    }
+
+    /// The election timeout bias around leadership transfers, see
+    /// `Config::transfer_election_bias_ticks`.
+    #[inline]
+    pub fn transfer_election_bias_ticks(mut self, transfer_election_bias_ticks: usize) -> Self {
+        self.config.transfer_election_bias_ticks = transfer_election_bias_ticks;
+        self
+    }
+
+    /// Whether peers are broadcast to in id order, see
+    /// `Config::deterministic_broadcast_order`.
+    #[inline]
+    pub fn deterministic_broadcast_order(mut self, deterministic_broadcast_order: bool) -> Self {
+        self.config.deterministic_broadcast_order = deterministic_broadcast_order;
+        self
+    }
+
+    /// Whether entries are persisted asynchronously, see `Config::async_storage_writes`.
+    #[inline]
+    pub fn async_storage_writes(mut self, async_storage_writes: bool) -> Self {
+        self.config.async_storage_writes = async_storage_writes;
+        self
+    }
+
+    /// The ticks before the same snapshot is sent again, see
+    /// `Config::snapshot_cooldown_ticks`.
+    #[inline]
+    pub fn snapshot_cooldown_ticks(mut self, snapshot_cooldown_ticks: usize) -> Self {
+        self.config.snapshot_cooldown_ticks = snapshot_cooldown_ticks;
+        self
+    }
+
+    /// The strategy picking the election timeout, see `Config::election_timeout_strategy`.
+    #[inline]
+    pub fn election_timeout_strategy(
+        mut self,
+        election_timeout_strategy: Arc<Mutex<dyn ElectionTimeoutStrategy>>,
+    ) -> Self {
+        self.config.election_timeout_strategy = Some(election_timeout_strategy);
+        self
+    }
+
+    /// The hook observing aborted snapshots, see `Config::snapshot_abort_hook`.
+    #[inline]
+    pub fn snapshot_abort_hook(mut self, snapshot_abort_hook: SnapshotAbortHook) -> Self {
+        self.config.snapshot_abort_hook = Some(snapshot_abort_hook);
+        self
+    }
+
+    /// The hook observing commit index advances, see `Config::commit_hook`.
+    #[inline]
+    pub fn commit_hook(mut self, commit_hook: CommitHook) -> Self {
+        self.config.commit_hook = Some(commit_hook);
+        self
+    }
+
+    /// The hook observing the node becoming a follower, see `Config::become_follower_hook`.
+    #[inline]
+    pub fn become_follower_hook(mut self, become_follower_hook: BecomeFollowerHook) -> Self {
+        self.config.become_follower_hook = Some(become_follower_hook);
+        self
+    }
+
+    /// Whether learner acknowledgements confirm read indexes, which is unsafe, see
+    /// `Config::count_learners_in_read_quorum`.
+    #[inline]
+    pub fn count_learners_in_read_quorum(mut self, count_learners_in_read_quorum: bool) -> Self {
+        self.config.count_learners_in_read_quorum = count_learners_in_read_quorum;
+        self
+    }
+
+    /// Whether split votes are broken in favor of the lower id candidate, see
+    /// `Config::prefer_lower_id_candidate`.
+    #[inline]
+    pub fn prefer_lower_id_candidate(mut self, prefer_lower_id_candidate: bool) -> Self {
+        self.config.prefer_lower_id_candidate = prefer_lower_id_candidate;
+        self
+    }
+
+    /// Whether overwritten entries are recorded, see `Config::record_overwritten_entries`.
+    #[inline]
+    pub fn record_overwritten_entries(mut self, record_overwritten_entries: bool) -> Self {
+        self.config.record_overwritten_entries = record_overwritten_entries;
+        self
+    }
+
+    /// Validates and returns the config.
+    pub fn build(self) -> Result<Config> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
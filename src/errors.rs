@@ -83,6 +83,39 @@ quick_error! {
         ViolatesContract(contract: String) {
             display("An argument violate a calling contract: {}", contract)
         }
+        /// An internal invariant of the raft state doesn't hold.
+        InvariantViolated(desc: String) {
+            display("A raft invariant is violated: {}", desc)
+        }
+        /// The persisted log and hard state don't match each other.
+        PersistedStateMismatch(desc: String) {
+            display("The persisted raft state is inconsistent: {}", desc)
+        }
+        /// The action is only allowed on the leader.
+        NotLeader {
+            description("raft: not leader")
+        }
+        /// The leadership transfer target is not a voter of the group.
+        TransferTargetNotVoter(id: u64) {
+            display("The leadership transfer target {} is not a voter.", id)
+        }
+        /// The leadership transfer target is a learner, which can't lead.
+        TransferTargetIsLearner(id: u64) {
+            display("The leadership transfer target {} is a learner.", id)
+        }
+        /// A learner was expected, but the node is a voter.
+        NotLearner(id: u64) {
+            display("The node {} is a voter, not a learner.", id)
+        }
+        /// The applied index given by the application is beyond the committed index, or behind
+        /// the previously applied index.
+        AppliedOutOfRange(applied: u64, prev_applied: u64, committed: u64) {
+            display("The applied index {} is out of range [{}, {}].", applied, prev_applied, committed)
+        }
+        /// A message was stepped on a node it is not addressed to.
+        WrongDestination(expected: u64, got: u64) {
+            display("The message is addressed to {} but stepped on {}.", got, expected)
+        }
     }
 }
 
@@ -95,7 +128,23 @@ impl cmp::PartialEq for Error {
             (&Error::Store(ref e1), &Error::Store(ref e2)) => e1 == e2,
             (&Error::Io(ref e1), &Error::Io(ref e2)) => e1.kind() == e2.kind(),
             (&Error::StepLocalMsg, &Error::StepLocalMsg) => true,
+            (&Error::NotLeader, &Error::NotLeader) => true,
+            (&Error::TransferTargetNotVoter(e1), &Error::TransferTargetNotVoter(e2)) => e1 == e2,
+            (&Error::TransferTargetIsLearner(e1), &Error::TransferTargetIsLearner(e2)) => e1 == e2,
+            (&Error::NotLearner(e1), &Error::NotLearner(e2)) => e1 == e2,
             (&Error::ConfigInvalid(ref e1), &Error::ConfigInvalid(ref e2)) => e1 == e2,
+            (&Error::PersistedStateMismatch(ref e1), &Error::PersistedStateMismatch(ref e2)) => {
+                e1 == e2
+            }
+            (&Error::InvariantViolated(ref e1), &Error::InvariantViolated(ref e2)) => e1 == e2,
+            (&Error::WrongDestination(e1, g1), &Error::WrongDestination(e2, g2)) => {
+                e1 == e2 && g1 == g2
+            }
+            (&Error::Exists(i1, s1), &Error::Exists(i2, s2)) => i1 == i2 && s1 == s2,
+            (&Error::NotExists(i1, s1), &Error::NotExists(i2, s2)) => i1 == i2 && s1 == s2,
+            (&Error::AppliedOutOfRange(a1, p1, c1), &Error::AppliedOutOfRange(a2, p2, c2)) => {
+                a1 == a2 && p1 == p2 && c1 == c2
+            }
             _ => false,
         }
     }
@@ -189,6 +238,8 @@ mod tests {
             Error::StepPeerNotFound,
             Error::Store(StorageError::Compacted)
         );
+        assert_eq!(Error::WrongDestination(1, 2), Error::WrongDestination(1, 2));
+        assert_ne!(Error::WrongDestination(1, 2), Error::WrongDestination(1, 3));
     }
 
     #[test]
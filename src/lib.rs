@@ -41,14 +41,12 @@ use raft::{
     raw_node::RawNode,
 };
 
-// Select some defaults, then change what we need.
-let config = Config {
-    id: 1,
-    ..Default::default()
-};
-// ... Make any configuration changes.
-// After, make sure it's valid!
-config.validate().unwrap();
+// Select some defaults, then change what we need. The builder makes sure the result is valid.
+let config = Config::builder()
+    .id(1)
+    // ... Make any configuration changes.
+    .build()
+    .unwrap();
 // We'll use the built-in `MemStorage`, but you will likely want your own.
 // Finally, create our Raft node!
 let storage = MemStorage::new_with_conf_state((vec![1], vec![]));
@@ -280,7 +278,7 @@ need to update the applied index and resume `apply` later:
 6. Call `advance` to prepare for the next `Ready` state.
 
     ```rust,ignore
-    node.advance(ready);
+    node.advance(ready).unwrap();
     ```
 
 For more information, check out an [example](examples/single_mem_node/main.rs#L113-L179).
@@ -345,7 +343,7 @@ assert!(node.raft.prs().voter_ids().contains(&3));
 # // We hide this since the user isn't really encouraged to blindly call this, but we'd like a short
 # // example.
 # node.raft.raft_log.commit_to(idx);
-# node.raft.commit_apply(idx);
+# node.raft.commit_apply(idx).unwrap();
 #
 # let idx = node.raft.raft_log.last_index();
 # let entry = &node.raft.raft_log.entries(idx, 1).unwrap()[0];
@@ -400,17 +398,26 @@ mod status;
 pub mod storage;
 pub mod util;
 
-pub use self::config::Config;
+pub use self::config::{
+    BecomeFollowerHook, CommitHook, Config, ConfigBuilder, DroppedProposalHook,
+    ElectionTimeoutStrategy, EntryCodec, ProgressStateHook, ReadStateCallback, SnapshotAbortHook,
+};
 pub use self::errors::{Error, Result, StorageError};
 pub use self::log_unstable::Unstable;
 pub use self::progress::inflights::Inflights;
 pub use self::progress::progress_set::{Configuration, ProgressSet};
 pub use self::progress::{Progress, ProgressState};
-pub use self::raft::{vote_resp_msg_type, Raft, SoftState, StateRole, INVALID_ID, INVALID_INDEX};
+#[cfg(feature = "testexport")]
+#[doc(hidden)]
+pub use self::raft::RaftDebugState;
+pub use self::raft::{
+    vote_resp_msg_type, MembershipChangeStatus, Raft, RestoreResult, SoftState, StateRole,
+    INVALID_ID, INVALID_INDEX,
+};
 pub use self::raft_log::{RaftLog, NO_LIMIT};
 pub use self::raw_node::{is_empty_snap, Peer, RawNode, Ready, SnapshotStatus};
 pub use self::read_only::{ReadOnlyOption, ReadState};
-pub use self::status::Status;
+pub use self::status::{LogGrowth, MessageMetrics, ProgressSnapshot, Status};
 pub use self::storage::{RaftState, Storage};
 
 pub mod prelude {
@@ -431,7 +438,7 @@ pub mod prelude {
         Snapshot, SnapshotMetadata,
     };
 
-    pub use crate::config::Config;
+    pub use crate::config::{Config, ConfigBuilder, EntryCodec};
     pub use crate::raft::Raft;
 
     pub use crate::storage::{RaftState, Storage};
@@ -440,7 +447,7 @@ pub mod prelude {
 
     pub use crate::progress::Progress;
 
-    pub use crate::status::Status;
+    pub use crate::status::{LogGrowth, MessageMetrics, ProgressSnapshot, Status};
 
     pub use crate::read_only::{ReadOnlyOption, ReadState};
 }
@@ -35,6 +35,9 @@ pub struct Inflights {
 
     // ring buffer
     buffer: Vec<u64>,
+
+    // the capacity to shrink to once few enough messages are in flight
+    incoming_cap: Option<usize>,
 }
 
 // The `buffer` must have it's capacity set correctly on clone, normally it does not.
@@ -46,6 +49,7 @@ impl Clone for Inflights {
             start: self.start,
             count: self.count,
             buffer,
+            incoming_cap: self.incoming_cap,
         }
     }
 }
@@ -57,13 +61,20 @@ impl Inflights {
             buffer: Vec::with_capacity(cap),
             start: 0,
             count: 0,
+            incoming_cap: None,
         }
     }
 
     /// Returns true if the inflights is full.
     #[inline]
     pub fn full(&self) -> bool {
-        self.count == self.cap()
+        self.count == self.cap() || self.incoming_cap.map_or(false, |cap| self.count >= cap)
+    }
+
+    /// The number of inflight messages.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.count
     }
 
     /// The buffer capacity.
@@ -118,6 +129,7 @@ impl Inflights {
         // free i inflights and set new start index
         self.count -= i;
         self.start = idx;
+        self.maybe_shrink();
     }
 
     /// Frees the first buffer entry.
@@ -132,6 +144,40 @@ impl Inflights {
     pub fn reset(&mut self) {
         self.count = 0;
         self.start = 0;
+        self.maybe_shrink();
+    }
+
+    /// Sets the buffer capacity, keeping the inflights in order.
+    ///
+    /// If more than `cap` messages are in flight, none of them is freed: no more can be added
+    /// and the buffer only shrinks once enough of them are freed.
+    pub fn set_cap(&mut self, cap: usize) {
+        if cap < self.count {
+            self.incoming_cap = Some(cap);
+            return;
+        }
+        self.incoming_cap = None;
+        self.realloc(cap);
+    }
+
+    // Applies the capacity deferred by `set_cap` once the inflights fit.
+    fn maybe_shrink(&mut self) {
+        match self.incoming_cap {
+            Some(cap) if self.count <= cap => {
+                self.incoming_cap = None;
+                self.realloc(cap);
+            }
+            _ => (),
+        }
+    }
+
+    fn realloc(&mut self, cap: usize) {
+        let mut buffer = Vec::with_capacity(cap);
+        for i in 0..self.count {
+            buffer.push(self.buffer[(self.start + i) % self.cap()]);
+        }
+        self.start = 0;
+        self.buffer = buffer;
     }
 }
 
@@ -152,6 +198,7 @@ mod tests {
             start: 0,
             count: 5,
             buffer: vec![0, 1, 2, 3, 4],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin);
@@ -164,6 +211,7 @@ mod tests {
             start: 0,
             count: 10,
             buffer: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin2);
@@ -180,6 +228,7 @@ mod tests {
             start: 5,
             count: 5,
             buffer: vec![0, 0, 0, 0, 0, 0, 1, 2, 3, 4],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight2, wantin21);
@@ -192,6 +241,7 @@ mod tests {
             start: 5,
             count: 10,
             buffer: vec![5, 6, 7, 8, 9, 0, 1, 2, 3, 4],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight2, wantin22);
@@ -211,6 +261,7 @@ mod tests {
             start: 5,
             count: 5,
             buffer: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin);
@@ -221,6 +272,7 @@ mod tests {
             start: 9,
             count: 1,
             buffer: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin2);
@@ -235,6 +287,7 @@ mod tests {
             start: 3,
             count: 2,
             buffer: vec![10, 11, 12, 13, 14, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin3);
@@ -245,6 +298,7 @@ mod tests {
             start: 5,
             count: 0,
             buffer: vec![10, 11, 12, 13, 14, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin4);
@@ -264,8 +318,51 @@ mod tests {
             start: 1,
             count: 9,
             buffer: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            incoming_cap: None,
         };
 
         assert_eq!(inflight, wantin);
     }
+
+    #[test]
+    fn test_inflight_set_cap() {
+        setup_for_test();
+        let mut inflight = Inflights::new(5);
+        for i in 0..5 {
+            inflight.add(i);
+        }
+        inflight.free_to(1);
+        inflight.add(5);
+        inflight.add(6);
+
+        inflight.set_cap(8);
+        let wantin = Inflights {
+            start: 0,
+            count: 5,
+            buffer: vec![2, 3, 4, 5, 6],
+            incoming_cap: None,
+        };
+        assert_eq!(inflight, wantin);
+        assert_eq!(inflight.cap(), 8);
+        assert!(!inflight.full());
+
+        // Shrinking below the inflights waits for enough of them to be freed.
+        inflight.set_cap(3);
+        assert_eq!(inflight.count(), 5);
+        assert_eq!(inflight.cap(), 8);
+        assert!(inflight.full());
+        inflight.free_to(2);
+        assert_eq!(inflight.cap(), 8);
+        assert!(inflight.full());
+        inflight.free_to(3);
+        let wantin2 = Inflights {
+            start: 0,
+            count: 3,
+            buffer: vec![4, 5, 6],
+            incoming_cap: None,
+        };
+        assert_eq!(inflight, wantin2);
+        assert_eq!(inflight.cap(), 3);
+        assert!(inflight.full());
+    }
 }
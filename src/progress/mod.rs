@@ -79,6 +79,21 @@ pub struct Progress {
     /// RecentActive can be reset to false after an election timeout.
     pub recent_active: bool,
 
+    /// The commit index last reported by the follower in an append response. Followers which
+    /// don't report it leave this at 0.
+    pub committed: u64,
+
+    /// The leader tick the oldest heartbeat not answered by the follower yet was sent at, see
+    /// `Raft::unresponsive_peers`.
+    pub heartbeat_sent_at: Option<u64>,
+
+    /// The index of the last snapshot sent to the follower, see
+    /// `Config::snapshot_cooldown_ticks`.
+    pub snapshot_sent_index: u64,
+
+    /// The leader tick the last snapshot was sent to the follower at.
+    pub snapshot_sent_at: Option<u64>,
+
     /// Inflights is a sliding window for the inflight messages.
     /// When inflights is full, no more message should be sent.
     /// When a leader sends out a message, the index of the last
@@ -99,6 +114,10 @@ impl Progress {
             paused: false,
             pending_snapshot: 0,
             recent_active: false,
+            committed: 0,
+            heartbeat_sent_at: None,
+            snapshot_sent_index: 0,
+            snapshot_sent_at: None,
             ins: Inflights::new(ins_size),
         }
     }
@@ -117,6 +136,10 @@ impl Progress {
         self.paused = false;
         self.pending_snapshot = 0;
         self.recent_active = false;
+        self.committed = 0;
+        self.heartbeat_sent_at = None;
+        self.snapshot_sent_index = 0;
+        self.snapshot_sent_at = None;
         debug_assert!(self.ins.cap() != 0);
         self.ins.reset();
     }
@@ -31,6 +31,7 @@ use crate::progress::Progress;
 use hashbrown::hash_map::DefaultHashBuilder;
 use hashbrown::{HashMap, HashSet};
 use std::cell::RefCell;
+use std::cmp;
 
 // Since it's an integer, it rounds for us.
 #[inline]
@@ -123,6 +124,32 @@ impl Configuration {
         self.voters.intersection(potential_quorum).count() >= majority(self.voters.len())
     }
 
+    fn has_quorum_counting_learners(&self, potential_quorum: &HashSet<u64>) -> bool {
+        let count = self.voters.intersection(potential_quorum).count()
+            + self.learners.intersection(potential_quorum).count();
+        count >= majority(self.voters.len())
+    }
+
+    /// Counts the granted and rejected votes cast by the voters of this configuration, and
+    /// returns them along with the number of votes needed for a quorum, as
+    /// `(granted, rejected, quorum)`.
+    pub fn vote_tally<'a>(
+        &self,
+        votes: impl IntoIterator<Item = (&'a u64, &'a bool)>,
+    ) -> (usize, usize, usize) {
+        let (granted, rejected) = votes
+            .into_iter()
+            .filter(|(id, _)| self.voters.contains(id))
+            .fold((0, 0), |(granted, rejected), (_, &accepted)| {
+                if accepted {
+                    (granted + 1, rejected)
+                } else {
+                    (granted, rejected + 1)
+                }
+            });
+        (granted, rejected, majority(self.voters.len()))
+    }
+
     /// Returns whether or not the given `id` is a member of this configuration.
     #[inline]
     pub fn contains(&self, id: u64) -> bool {
@@ -161,6 +188,22 @@ pub struct ProgressSet {
     sort_buffer: RefCell<Vec<u64>>,
 }
 
+fn is_voter(configuration: &Configuration, next: &Option<Configuration>, id: u64) -> bool {
+    configuration.voters.contains(&id)
+        || match *next {
+            Some(ref next) => next.voters.contains(&id),
+            None => false,
+        }
+}
+
+fn is_learner(configuration: &Configuration, next: &Option<Configuration>, id: u64) -> bool {
+    configuration.learners.contains(&id)
+        || match *next {
+            Some(ref next) => next.learners.contains(&id),
+            None => false,
+        }
+}
+
 impl ProgressSet {
     /// Creates a new ProgressSet.
     pub fn new() -> Self {
@@ -225,8 +268,10 @@ impl ProgressSet {
     /// transitioning to a new configuration and have two qourums. Use `has_quorum` instead.
     #[inline]
     pub fn voters(&self) -> impl Iterator<Item = (&u64, &Progress)> {
-        let set = self.voter_ids();
-        self.progress.iter().filter(move |(&k, _)| set.contains(&k))
+        let (configuration, next) = (&self.configuration, &self.next_configuration);
+        self.progress
+            .iter()
+            .filter(move |(id, _)| is_voter(configuration, next, **id))
     }
 
     /// Returns the status of learners.
@@ -235,8 +280,10 @@ impl ProgressSet {
     /// transitioning to a new configuration and have two qourums. Use `has_quorum` instead.
     #[inline]
     pub fn learners(&self) -> impl Iterator<Item = (&u64, &Progress)> {
-        let set = self.learner_ids();
-        self.progress.iter().filter(move |(&k, _)| set.contains(&k))
+        let (configuration, next) = (&self.configuration, &self.next_configuration);
+        self.progress
+            .iter()
+            .filter(move |(id, _)| is_learner(configuration, next, **id))
     }
 
     /// Returns the mutable status of voters.
@@ -245,10 +292,10 @@ impl ProgressSet {
     /// transitioning to a new configuration and have two qourums. Use `has_quorum` instead.
     #[inline]
     pub fn voters_mut(&mut self) -> impl Iterator<Item = (&u64, &mut Progress)> {
-        let ids = self.voter_ids();
+        let (configuration, next) = (&self.configuration, &self.next_configuration);
         self.progress
             .iter_mut()
-            .filter(move |(k, _)| ids.contains(k))
+            .filter(move |(id, _)| is_voter(configuration, next, **id))
     }
 
     /// Returns the mutable status of learners.
@@ -257,10 +304,38 @@ impl ProgressSet {
     /// transitioning to a new configuration and have two qourums. Use `has_quorum` instead.
     #[inline]
     pub fn learners_mut(&mut self) -> impl Iterator<Item = (&u64, &mut Progress)> {
-        let ids = self.learner_ids();
+        let (configuration, next) = (&self.configuration, &self.next_configuration);
         self.progress
             .iter_mut()
-            .filter(move |(k, _)| ids.contains(k))
+            .filter(move |(id, _)| is_learner(configuration, next, **id))
+    }
+
+    /// Iterates over the voters of both the current and, during a joint consensus transition,
+    /// the next configuration, each once, without allocating.
+    ///
+    /// **Note:** Do not use this for majority/quorum calculation. Use `has_quorum` instead.
+    #[inline]
+    pub fn iter_voters(&self) -> impl Iterator<Item = (u64, &Progress)> {
+        self.voters().map(|(&id, pr)| (id, pr))
+    }
+
+    /// Iterates over the learners of both the current and, during a joint consensus
+    /// transition, the next configuration, each once, without allocating.
+    #[inline]
+    pub fn iter_learners(&self) -> impl Iterator<Item = (u64, &Progress)> {
+        self.learners().map(|(&id, pr)| (id, pr))
+    }
+
+    /// The mutable variant of `iter_voters`.
+    #[inline]
+    pub fn iter_voters_mut(&mut self) -> impl Iterator<Item = (u64, &mut Progress)> {
+        self.voters_mut().map(|(&id, pr)| (id, pr))
+    }
+
+    /// The mutable variant of `iter_learners`.
+    #[inline]
+    pub fn iter_learners_mut(&mut self) -> impl Iterator<Item = (u64, &mut Progress)> {
+        self.learners_mut().map(|(&id, pr)| (id, pr))
     }
 
     /// Returns the ids of all known voters.
@@ -297,6 +372,13 @@ impl ProgressSet {
         }
     }
 
+    /// Whether `id` is a learner of the current or, during a joint consensus transition, the
+    /// next configuration.
+    #[inline]
+    pub fn is_learner(&self, id: u64) -> bool {
+        is_learner(&self.configuration, &self.next_configuration, id)
+    }
+
     /// Grabs a reference to the progress of a node.
     #[inline]
     pub fn get(&self, id: u64) -> Option<&Progress> {
@@ -455,31 +537,38 @@ impl ProgressSet {
     /// Returns the maximal committed index for the cluster.
     ///
     /// Eg. If the matched indexes are [2,2,2,4,5], it will return 2.
+    ///
+    /// During a joint consensus transition, this is the lower of the indexes allowed by the
+    /// quorums of the current and the next configuration, see `committed_index_detail`.
     pub fn maximal_committed_index(&self) -> u64 {
+        let (current, next) = self.committed_index_detail();
+        cmp::min(current, next)
+    }
+
+    /// Returns the committed index allowed by the quorum of the current configuration and by
+    /// the quorum of the next configuration, which tells which side blocks commit during a
+    /// joint consensus transition. Outside of one, both are the index allowed by the current
+    /// configuration.
+    pub fn committed_index_detail(&self) -> (u64, u64) {
+        let current = self.quorum_committed_index(self.configuration.voters());
+        let next = match self.next_configuration {
+            Some(ref next) => self.quorum_committed_index(next.voters()),
+            None => current,
+        };
+        (current, next)
+    }
+
+    /// Returns the highest index matched by a majority of `voters`.
+    fn quorum_committed_index(&self, voters: &HashSet<u64>) -> u64 {
         let mut matched = self.sort_buffer.borrow_mut();
         matched.clear();
-        self.configuration.voters().iter().for_each(|id| {
+        voters.iter().for_each(|id| {
             let peer = &self.progress[id];
             matched.push(peer.matched);
         });
         // Reverse sort.
         matched.sort_by(|a, b| b.cmp(a));
-        let mut mci = matched[matched.len() / 2];
-
-        if let Some(next) = &self.next_configuration {
-            matched.clear();
-            next.voters().iter().for_each(|id| {
-                let peer = &self.progress[id];
-                matched.push(peer.matched);
-            });
-            // Reverse sort.
-            matched.sort_by(|a, b| b.cmp(a));
-            let next_mci = matched[matched.len() / 2];
-            if next_mci < mci {
-                mci = next_mci;
-            }
-        }
-        mci
+        matched[matched.len() / 2]
     }
 
     /// Returns the Candidate's eligibility in the current election.
@@ -561,6 +650,22 @@ impl ProgressSet {
                 .unwrap_or(true)
     }
 
+    /// Like `has_quorum`, but the learners of a configuration in `potential_quorum` count
+    /// towards the majority of its voters as if they were voters.
+    ///
+    /// **This is unsafe**: a set passing this check may miss a majority of the voters, so it
+    /// must never be used for elections or commitment, see
+    /// `Config::count_learners_in_read_quorum`.
+    pub fn has_quorum_counting_learners(&self, potential_quorum: &HashSet<u64>) -> bool {
+        self.configuration
+            .has_quorum_counting_learners(potential_quorum)
+            && self
+                .next_configuration
+                .as_ref()
+                .map(|next| next.has_quorum_counting_learners(potential_quorum))
+                .unwrap_or(true)
+    }
+
     /// Determine if the ProgressSet is represented by a transition state under Joint Consensus.
     #[inline]
     pub fn is_in_membership_change(&self) -> bool {
@@ -633,11 +738,14 @@ impl ProgressSet {
             None => Err(Error::NoPendingMembershipChange)?,
             Some(next) => {
                 {
+                    // Only drop peers absent from the next configuration entirely, so a
+                    // promoted learner keeps its `matched` and inflights.
                     let pending = self
                         .configuration
                         .voters()
                         .difference(next.voters())
                         .chain(self.configuration.learners().difference(next.learners()))
+                        .filter(|id| !next.contains(**id))
                         .cloned();
                     for id in pending {
                         self.progress.remove(&id);
@@ -800,6 +908,101 @@ mod test_progress_set {
         check_membership_change_configuration((vec![1], vec![2]), (vec![1, 2], vec![]))
     }
 
+    #[test]
+    fn test_membership_change_swap_voter_with_learner() -> Result<()> {
+        check_membership_change_configuration((vec![1, 2, 3], vec![4]), (vec![1, 2, 4], vec![]))?;
+
+        let mut set = ProgressSet::default();
+        for id in 1..4 {
+            set.insert_voter(id, Progress::new(0, 10))?;
+        }
+        set.insert_learner(4, Progress::new(0, 10))?;
+        set.get_mut(4).unwrap().maybe_update(5);
+        set.begin_membership_change(
+            Configuration::new(vec![1, 2, 4], vec![]),
+            Progress::new(0, 10),
+        )?;
+        set.finalize_membership_change()?;
+        assert_eq!(set.get(4).map(|pr| pr.matched), Some(5));
+        assert!(set.get(3).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_learner() -> Result<()> {
+        let mut set = ProgressSet::default();
+        set.insert_voter(1, Progress::new(0, 10))?;
+        set.insert_learner(2, Progress::new(0, 10))?;
+        assert!(!set.is_learner(1));
+        assert!(set.is_learner(2));
+        assert!(!set.is_learner(3));
+
+        // A learner of the next configuration is a learner too.
+        set.begin_membership_change(
+            Configuration::new(vec![1], vec![2, 3]),
+            Progress::new(0, 10),
+        )?;
+        assert!(set.is_learner(3));
+        assert!(!set.is_learner(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_voters_in_joint() -> Result<()> {
+        let mut set = ProgressSet::default();
+        for id in 1..4 {
+            set.insert_voter(id, Progress::new(0, 10))?;
+        }
+        set.begin_membership_change(
+            Configuration::new(vec![2, 3, 4], vec![5]),
+            Progress::new(0, 10),
+        )?;
+
+        let mut voters = set.iter_voters().map(|(id, _)| id).collect::<Vec<_>>();
+        voters.sort();
+        assert_eq!(voters, vec![1, 2, 3, 4]);
+        let learners = set.iter_learners().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(learners, vec![5]);
+
+        for (_, pr) in set.iter_voters_mut() {
+            pr.maybe_update(3);
+        }
+        for (_, pr) in set.iter_learners_mut() {
+            pr.maybe_update(1);
+        }
+        assert!(set.iter_voters().all(|(_, pr)| pr.matched == 3));
+        assert_eq!(set.get(5).map(|pr| pr.matched), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_committed_index_detail() -> Result<()> {
+        let mut set = ProgressSet::default();
+        for (id, matched) in vec![(1, 5), (2, 5), (3, 1)] {
+            let mut pr = Progress::new(0, 10);
+            pr.maybe_update(matched);
+            set.insert_voter(id, pr)?;
+        }
+        assert_eq!(set.committed_index_detail(), (5, 5));
+        assert_eq!(set.maximal_committed_index(), 5);
+
+        // The next configuration, with the new voters 4 and 5 behind, blocks the commit.
+        set.begin_membership_change(
+            Configuration::new(vec![1, 4, 5], vec![]),
+            Progress::new(0, 10),
+        )?;
+        set.get_mut(4).unwrap().maybe_update(2);
+        assert_eq!(set.committed_index_detail(), (5, 2));
+        assert_eq!(set.maximal_committed_index(), 2);
+
+        // And the current one once they catch up.
+        set.get_mut(2).unwrap().matched = 1;
+        set.get_mut(4).unwrap().maybe_update(5);
+        assert_eq!(set.committed_index_detail(), (1, 5));
+        assert_eq!(set.maximal_committed_index(), 1);
+        Ok(())
+    }
+
     fn check_membership_change_configuration(
         start: (impl IntoIterator<Item = u64>, impl IntoIterator<Item = u64>),
         end: (impl IntoIterator<Item = u64>, impl IntoIterator<Item = u64>),
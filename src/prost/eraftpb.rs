@@ -24,6 +24,10 @@ pub struct Entry {
     /// TODO: remove it in the next major release.
     #[prost(bool, tag = "5")]
     pub sync_log: bool,
+    /// Set while the data is compressed for transport with the configured entry codec.
+    /// It is always false in the log.
+    #[prost(bool, tag = "7")]
+    pub compressed: bool,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct SnapshotMetadata {
@@ -57,6 +61,8 @@ pub struct Message {
     pub term: u64,
     #[prost(uint64, tag = "5")]
     pub log_term: u64,
+    /// On `MsgReadIndex`: the minimum acceptable read index, 0 if any is. A leader in lease
+    /// which already committed it serves the read without a heartbeat round.
     #[prost(uint64, tag = "6")]
     pub index: u64,
     #[prost(message, repeated, tag = "7")]
@@ -71,6 +77,28 @@ pub struct Message {
     pub reject_hint: u64,
     #[prost(bytes, tag = "12")]
     pub context: std::vec::Vec<u8>,
+    /// Used by a chunked `MsgSnapshot`: the byte offset of `snapshot.data` within the whole
+    /// snapshot data. A `MsgSnapshot` with a zero offset and `snapshot_chunk_more` unset carries
+    /// the whole snapshot at once.
+    #[prost(uint64, tag = "13")]
+    pub snapshot_chunk_offset: u64,
+    /// Used by a chunked `MsgSnapshot`: whether more chunks of the same snapshot follow.
+    #[prost(bool, tag = "14")]
+    pub snapshot_chunk_more: bool,
+    /// Used by `MsgReadIndex` and `MsgReadIndexResp`: an opaque sequence number set by the
+    /// application and echoed back in the resulting `ReadState`.
+    #[prost(uint64, tag = "15")]
+    pub read_seq: u64,
+    /// Used by `MsgHeartbeat` when the leader delegates its lease: the number of ticks during
+    /// which the follower may serve reads locally.
+    #[prost(uint64, tag = "16")]
+    pub lease_ticks: u64,
+    /// Used by `MsgReadIndex` and `MsgReadIndexResp`: an opaque address of a requester which
+    /// isn't a member of the group, e.g. a proxy, echoed back in the response so the transport
+    /// can route it there. A follower relaying the response of a read it forwarded for such a
+    /// requester sends it with no `to`, the transport must route it on this address.
+    #[prost(bytes, tag = "17")]
+    pub return_address: std::vec::Vec<u8>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct HardState {
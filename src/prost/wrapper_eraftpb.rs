@@ -92,6 +92,18 @@ impl Entry {
     pub fn get_sync_log(&self) -> bool {
         self.sync_log
     }
+    #[inline]
+    pub fn clear_compressed(&mut self) {
+        self.compressed = false
+    }
+    #[inline]
+    pub fn set_compressed(&mut self, v: bool) {
+        self.compressed = v;
+    }
+    #[inline]
+    pub fn get_compressed(&self) -> bool {
+        self.compressed
+    }
 }
 impl ::protobuf::Clear for Entry {
     fn clear(&mut self) {
@@ -633,6 +645,74 @@ impl Message {
     pub fn take_context(&mut self) -> std::vec::Vec<u8> {
         ::std::mem::replace(&mut self.context, ::std::vec::Vec::new())
     }
+    #[inline]
+    pub fn clear_snapshot_chunk_offset(&mut self) {
+        self.snapshot_chunk_offset = 0
+    }
+    #[inline]
+    pub fn set_snapshot_chunk_offset(&mut self, v: u64) {
+        self.snapshot_chunk_offset = v;
+    }
+    #[inline]
+    pub fn get_snapshot_chunk_offset(&self) -> u64 {
+        self.snapshot_chunk_offset
+    }
+    #[inline]
+    pub fn clear_snapshot_chunk_more(&mut self) {
+        self.snapshot_chunk_more = false
+    }
+    #[inline]
+    pub fn set_snapshot_chunk_more(&mut self, v: bool) {
+        self.snapshot_chunk_more = v;
+    }
+    #[inline]
+    pub fn get_snapshot_chunk_more(&self) -> bool {
+        self.snapshot_chunk_more
+    }
+    #[inline]
+    pub fn clear_read_seq(&mut self) {
+        self.read_seq = 0
+    }
+    #[inline]
+    pub fn set_read_seq(&mut self, v: u64) {
+        self.read_seq = v;
+    }
+    #[inline]
+    pub fn get_read_seq(&self) -> u64 {
+        self.read_seq
+    }
+    #[inline]
+    pub fn clear_lease_ticks(&mut self) {
+        self.lease_ticks = 0
+    }
+    #[inline]
+    pub fn set_lease_ticks(&mut self, v: u64) {
+        self.lease_ticks = v;
+    }
+    #[inline]
+    pub fn get_lease_ticks(&self) -> u64 {
+        self.lease_ticks
+    }
+    #[inline]
+    pub fn clear_return_address(&mut self) {
+        self.return_address.clear();
+    }
+    #[inline]
+    pub fn set_return_address(&mut self, v: std::vec::Vec<u8>) {
+        self.return_address = v;
+    }
+    #[inline]
+    pub fn get_return_address(&self) -> &[u8] {
+        &self.return_address
+    }
+    #[inline]
+    pub fn mut_return_address(&mut self) -> &mut std::vec::Vec<u8> {
+        &mut self.return_address
+    }
+    #[inline]
+    pub fn take_return_address(&mut self) -> std::vec::Vec<u8> {
+        ::std::mem::replace(&mut self.return_address, ::std::vec::Vec::new())
+    }
 }
 impl ::protobuf::Clear for Message {
     fn clear(&mut self) {
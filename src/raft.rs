@@ -26,20 +26,28 @@
 // limitations under the License.
 
 use std::cmp;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
 
 use crate::eraftpb::{
-    ConfChange, ConfChangeType, Entry, EntryType, HardState, Message, MessageType, Snapshot,
+    ConfChange, ConfChangeType, ConfState, Entry, EntryType, HardState, Message, MessageType,
+    Snapshot, SnapshotMetadata,
 };
 use hashbrown::{HashMap, HashSet};
 use prost::Message as ProstMsg;
 use rand::{self, Rng};
 
+use super::config::{
+    BecomeFollowerHook, CommitHook, DroppedProposalHook, ElectionTimeoutStrategy, EntryCodec,
+    ProgressStateHook, ReadStateCallback, SnapshotAbortHook,
+};
 use super::errors::{Error, Result, StorageError};
 use super::progress::progress_set::{CandidacyStatus, Configuration, ProgressSet};
 use super::progress::{Progress, ProgressState};
 use super::raft_log::RaftLog;
 use super::read_only::{ReadOnly, ReadOnlyOption, ReadState};
-use super::storage::Storage;
+use super::status::{LogGrowth, MessageMetrics, ProgressSnapshot};
+use super::storage::{RaftState, Storage};
 use super::Config;
 use crate::util;
 
@@ -71,6 +79,139 @@ impl Default for StateRole {
     }
 }
 
+/// The outcome of restoring a snapshot, see `Raft::restore_with_reason`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RestoreResult {
+    /// The snapshot was restored, replacing the log and the configuration.
+    Restored,
+    /// The log already contains the snapshot's index and term, so only the commit index was
+    /// moved forward to it.
+    FastForwarded,
+    /// The snapshot is older than the committed index.
+    StaleIgnored,
+    /// The snapshot would turn this voter into a learner.
+    RejectedLearnerDemotion,
+    /// The snapshot ends at the committed index but with another term, so restoring it would
+    /// rewrite committed history.
+    RejectedCommittedConflict,
+}
+
+/// How far an in-flight joint consensus membership change has progressed, see
+/// `Raft::membership_change_status`.
+#[derive(Default, Debug, PartialEq, Clone, Copy)]
+pub struct MembershipChangeStatus {
+    /// The index of the entry beginning the change.
+    pub begin_index: u64,
+    /// Whether the entry beginning the change is committed.
+    pub begin_committed: bool,
+    /// Whether the entry beginning the change is applied.
+    pub begin_applied: bool,
+    /// The index of the entry finalizing the change, if it has been appended to the log.
+    pub finalize_index: Option<u64>,
+    /// Whether the entry finalizing the change is committed.
+    pub finalize_committed: bool,
+}
+
+/// A plain data copy of the in-memory state of a `Raft`, for replay tests to detect when two
+/// runs diverge. It is unrelated to the snapshots of the replicated state machine.
+#[cfg(feature = "testexport")]
+#[doc(hidden)]
+#[derive(Default, Debug, PartialEq, Clone)]
+pub struct RaftDebugState {
+    pub id: u64,
+    pub term: u64,
+    pub vote: u64,
+    pub state: StateRole,
+    pub leader_id: u64,
+    pub lead_transferee: Option<u64>,
+    pub committed: u64,
+    pub applied: u64,
+    pub last_index: u64,
+    pub last_term: u64,
+    pub pending_conf_index: u64,
+    pub election_elapsed: usize,
+    pub heartbeat_elapsed: usize,
+    pub randomized_election_timeout: usize,
+    /// The progresses sorted by id.
+    pub progress: Vec<(u64, Progress)>,
+    /// The votes of the ongoing election sorted by id.
+    pub votes: Vec<(u64, bool)>,
+    /// The contexts of the pending read index requests in the order they are served.
+    pub pending_reads: Vec<Vec<u8>>,
+}
+
+#[cfg(feature = "testexport")]
+impl RaftDebugState {
+    /// Encodes the state as a JSON object, with the fields in declaration order, so the
+    /// states of a run can be stored, e.g. in a replay log, and compared as text.
+    pub fn encode(&self) -> String {
+        let progress: Vec<_> = self
+            .progress
+            .iter()
+            .map(|(id, pr)| {
+                format!(
+                    "{{\"id\":{},\"matched\":{},\"next_idx\":{},\"state\":\"{:?}\",\
+                     \"paused\":{},\"pending_snapshot\":{},\"recent_active\":{},\
+                     \"committed\":{},\"heartbeat_sent_at\":{},\"snapshot_sent_index\":{},\
+                     \"snapshot_sent_at\":{},\"inflights\":{},\"inflights_cap\":{}}}",
+                    id,
+                    pr.matched,
+                    pr.next_idx,
+                    pr.state,
+                    pr.paused,
+                    pr.pending_snapshot,
+                    pr.recent_active,
+                    pr.committed,
+                    encode_opt(pr.heartbeat_sent_at),
+                    pr.snapshot_sent_index,
+                    encode_opt(pr.snapshot_sent_at),
+                    pr.ins.count(),
+                    pr.ins.cap(),
+                )
+            })
+            .collect();
+        let votes: Vec<_> = self
+            .votes
+            .iter()
+            .map(|(id, granted)| format!("[{},{}]", id, granted))
+            .collect();
+        let pending_reads: Vec<_> = self
+            .pending_reads
+            .iter()
+            .map(|ctx| format!("{:?}", ctx).replace(' ', ""))
+            .collect();
+        format!(
+            "{{\"id\":{},\"term\":{},\"vote\":{},\"state\":\"{:?}\",\"leader_id\":{},\
+             \"lead_transferee\":{},\"committed\":{},\"applied\":{},\"last_index\":{},\
+             \"last_term\":{},\"pending_conf_index\":{},\"election_elapsed\":{},\
+             \"heartbeat_elapsed\":{},\"randomized_election_timeout\":{},\"progress\":[{}],\
+             \"votes\":[{}],\"pending_reads\":[{}]}}",
+            self.id,
+            self.term,
+            self.vote,
+            self.state,
+            self.leader_id,
+            encode_opt(self.lead_transferee),
+            self.committed,
+            self.applied,
+            self.last_index,
+            self.last_term,
+            self.pending_conf_index,
+            self.election_elapsed,
+            self.heartbeat_elapsed,
+            self.randomized_election_timeout,
+            progress.join(","),
+            votes.join(","),
+            pending_reads.join(","),
+        )
+    }
+}
+
+#[cfg(feature = "testexport")]
+fn encode_opt(v: Option<u64>) -> String {
+    v.map_or_else(|| "null".to_owned(), |v| v.to_string())
+}
+
 /// A constant represents invalid id of raft.
 pub const INVALID_ID: u64 = 0;
 /// A constant represents invalid index of raft log.
@@ -149,7 +290,9 @@ pub struct Raft<T: Storage> {
     /// but scanning the log is possibly expensive. This implies that the index stated here may not
     /// necessarily be a config change entry, and it may not be a `BeginMembershipChange` entry, even if
     /// we set this to one.
-    pub pending_conf_index: u64,
+    ///
+    /// **Use `Raft::pending_conf_index()` to read this value.**
+    pending_conf_index: u64,
 
     /// The last `BeginMembershipChange` entry. Once we make this change we exit the joint state.
     ///
@@ -167,6 +310,9 @@ pub struct Raft<T: Storage> {
     #[get = "pub"]
     pending_membership_change: Option<ConfChange>,
 
+    /// The index of the entry finalizing the pending membership change, once it's in the log.
+    pending_finalize_index: Option<u64>,
+
     /// The queue of read-only requests.
     pub read_only: ReadOnly,
 
@@ -191,6 +337,42 @@ pub struct Raft<T: Storage> {
 
     skip_bcast_commit: bool,
     batch_append: bool,
+    min_commit_bcast_delta: u64,
+    /// The commit index carried by the last `bcast_append`.
+    last_bcast_commit: u64,
+    timeout_now_max_lag: u64,
+    max_term_gap: u64,
+    strict_message_routing: bool,
+    entry_codec: Option<Arc<dyn EntryCodec>>,
+    compression_threshold: usize,
+    read_state_callback: Option<ReadStateCallback>,
+    disable_proposal_forwarding: bool,
+    delegate_read_lease: bool,
+    /// The number of ticks of the lease delegated by the leader with its last heartbeat.
+    delegated_lease_ticks: usize,
+    /// The number of ticks since the lease was delegated.
+    delegated_lease_elapsed: usize,
+    suppress_duplicate_appends: bool,
+    /// The `(index, log_term, last entry index, commit)` of the last `MsgAppend` sent to each
+    /// peer since the last heartbeat, kept if `suppress_duplicate_appends` is set.
+    last_sent_appends: HashMap<u64, (u64, u64, u64, u64)>,
+    buffer_proposals_without_leader: usize,
+    /// The proposals received while no leader was known, see
+    /// `Config::buffer_proposals_without_leader`.
+    buffered_proposals: Vec<Message>,
+    dropped_proposal_hook: Option<DroppedProposalHook>,
+    progress_state_hook: Option<ProgressStateHook>,
+    snapshot_abort_hook: Option<SnapshotAbortHook>,
+    commit_hook: Option<CommitHook>,
+    become_follower_hook: Option<BecomeFollowerHook>,
+    count_learners_in_read_quorum: bool,
+    prefer_lower_id_candidate: bool,
+    heartbeat_response_timeout_ticks: usize,
+    /// The number of ticks since the node became leader.
+    leader_ticks: u64,
+    /// The term at which the node last became leader, see `leader_epoch`.
+    leader_epoch: u64,
+    snapshot_cooldown_ticks: usize,
 
     heartbeat_timeout: usize,
     election_timeout: usize,
@@ -201,9 +383,46 @@ pub struct Raft<T: Storage> {
     randomized_election_timeout: usize,
     min_election_timeout: usize,
     max_election_timeout: usize,
+    election_timeout_strategy: Option<Arc<Mutex<dyn ElectionTimeoutStrategy>>>,
+
+    deterministic_broadcast_order: bool,
+    async_storage_writes: bool,
+    /// The last index known to be persisted, see `advance_persisted`.
+    persisted: u64,
+    /// The bias of the election timeout around leadership transfers, 0 if disabled.
+    transfer_election_bias_ticks: usize,
+    /// The cap of the election timeout backoff after failed pre-votes, 0 if disabled.
+    prevote_backoff_cap_ticks: usize,
+    /// The lag behind the committed index beyond which pre-votes are refused, 0 if disabled.
+    prevote_max_lag: u64,
+    /// The number of consecutive failed pre-vote rounds since a leader was last heard from.
+    failed_pre_votes: u32,
+
+    /// The number of accepted appends which conflicted with the local log.
+    append_conflicts: u64,
+    /// The index ranges of uncommitted entries overwritten by appends from a leader, not yet
+    /// taken by the application. Only recorded with `record_overwritten_entries`.
+    overwritten_entries: Vec<Range<u64>>,
+    record_overwritten_entries: bool,
+
+    /// The number of configuration changes committed but not applied, memoized with the
+    /// `(applied, committed)` range it was counted over.
+    unapplied_conf_changes: Option<((u64, u64), usize)>,
+
+    /// The entries appended as a leader since the node started.
+    appended: LogGrowth,
+    /// The value of `appended` when `take_log_growth` was last called.
+    appended_at_last_take: LogGrowth,
+
+    /// The messages sent and received since the node started or `take_message_metrics` was
+    /// last called.
+    message_metrics: MessageMetrics,
 
     /// Tag is only used for logging
     tag: String,
+
+    /// The metadata and the next expected offset of a chunked snapshot being received.
+    receiving_snapshot: Option<(SnapshotMetadata, u64)>,
 }
 
 trait AssertSend: Send {}
@@ -220,6 +439,49 @@ fn new_message(to: u64, field_type: MessageType, from: Option<u64>) -> Message {
     m
 }
 
+/// Returns the ids of the peers other than `self_id` in ascending order.
+fn sorted_peer_ids(prs: &ProgressSet, self_id: u64) -> Vec<u64> {
+    let mut ids: Vec<u64> = prs
+        .iter()
+        .map(|(&id, _)| id)
+        .filter(|&id| id != self_id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Builds the response to the read index request `req`, routed back to its sender or to its
+/// return address.
+fn read_index_resp(mut req: Message, index: u64) -> Message {
+    let mut to_send = Message::default();
+    to_send.set_to(req.get_from());
+    to_send.set_msg_type(MessageType::MsgReadIndexResp);
+    to_send.set_index(index);
+    to_send.set_read_seq(req.get_read_seq());
+    to_send.set_return_address(req.take_return_address());
+    to_send.set_entries(req.take_entries());
+    to_send
+}
+
+/// Whether messages of type `t` are only ever sent by another peer, so one from this very node
+/// must have been echoed back by the transport.
+fn is_peer_msg(t: MessageType) -> bool {
+    match t {
+        MessageType::MsgAppend
+        | MessageType::MsgAppendResponse
+        | MessageType::MsgRequestVote
+        | MessageType::MsgRequestVoteResponse
+        | MessageType::MsgSnapshot
+        | MessageType::MsgHeartbeat
+        | MessageType::MsgHeartbeatResponse
+        | MessageType::MsgTimeoutNow
+        | MessageType::MsgReadIndexResp
+        | MessageType::MsgRequestPreVote
+        | MessageType::MsgRequestPreVoteResponse => true,
+        _ => false,
+    }
+}
+
 /// Maps vote and pre_vote message types to their correspond responses.
 pub fn vote_resp_msg_type(t: MessageType) -> MessageType {
     match t {
@@ -235,6 +497,49 @@ impl<T: Storage> Raft<T> {
     pub fn new(c: &Config, store: T) -> Result<Raft<T>> {
         c.validate()?;
         let raft_state = store.initial_state()?;
+        Raft::new_with_state(c, store, raft_state)
+    }
+
+    /// Creates a new raft for bootstrapping a cluster with the given `voters` and `learners`,
+    /// instead of reading the initial membership from the storage's `ConfState`.
+    ///
+    /// The storage may also already hold the same membership, e.g. when a bootstrapped node
+    /// restarts.
+    ///
+    /// The membership is only kept in memory, raft doesn't write it to the storage. The caller
+    /// must persist it as the storage's `ConfState`, e.g. with
+    /// `MemStorage::initialize_with_conf_state`, before the node handles any message, or a
+    /// restarted node won't know its peers.
+    ///
+    /// # Errors
+    ///
+    /// * The config is invalid.
+    /// * The given membership is invalid, e.g. it has no voters.
+    /// * The storage holds a different, non-empty `ConfState`.
+    pub fn new_with_peers(
+        c: &Config,
+        store: T,
+        voters: &[u64],
+        learners: &[u64],
+    ) -> Result<Raft<T>> {
+        c.validate()?;
+        let mut raft_state = store.initial_state()?;
+        let configuration = Configuration::new(voters.iter().cloned(), learners.iter().cloned());
+        configuration.valid()?;
+        let persisted = &raft_state.conf_state;
+        if !(persisted.get_nodes().is_empty() && persisted.get_learners().is_empty())
+            && Configuration::from(persisted.clone()) != configuration
+        {
+            return Err(Error::ConfigInvalid(format!(
+                "peers {:?} conflict with the persisted conf state {:?}",
+                configuration, persisted
+            )));
+        }
+        raft_state.conf_state = configuration.into();
+        Raft::new_with_state(c, store, raft_state)
+    }
+
+    fn new_with_state(c: &Config, store: T, raft_state: RaftState) -> Result<Raft<T>> {
         let conf_state = &raft_state.conf_state;
         let peers = conf_state.get_nodes();
         let learners = conf_state.get_learners();
@@ -261,36 +566,108 @@ impl<T: Storage> Raft<T> {
             election_elapsed: Default::default(),
             pending_conf_index: Default::default(),
             pending_membership_change: Default::default(),
+            pending_finalize_index: None,
             vote: Default::default(),
             heartbeat_elapsed: Default::default(),
             randomized_election_timeout: 0,
             min_election_timeout: c.min_election_tick(),
             max_election_timeout: c.max_election_tick(),
+            election_timeout_strategy: c.election_timeout_strategy.clone(),
             skip_bcast_commit: c.skip_bcast_commit,
             tag: c.tag.to_owned(),
             batch_append: c.batch_append,
+            min_commit_bcast_delta: c.min_commit_bcast_delta,
+            last_bcast_commit: 0,
+            timeout_now_max_lag: c.timeout_now_max_lag,
+            max_term_gap: c.max_term_gap,
+            strict_message_routing: c.strict_message_routing,
+            entry_codec: c.entry_codec.clone(),
+            compression_threshold: c.compression_threshold,
+            read_state_callback: c.read_state_callback.clone(),
+            disable_proposal_forwarding: c.disable_proposal_forwarding,
+            delegate_read_lease: c.delegate_read_lease,
+            delegated_lease_ticks: 0,
+            delegated_lease_elapsed: 0,
+            suppress_duplicate_appends: c.suppress_duplicate_appends,
+            last_sent_appends: HashMap::default(),
+            buffer_proposals_without_leader: c.buffer_proposals_without_leader,
+            buffered_proposals: vec![],
+            dropped_proposal_hook: c.dropped_proposal_hook.clone(),
+            progress_state_hook: c.progress_state_hook.clone(),
+            snapshot_abort_hook: c.snapshot_abort_hook.clone(),
+            commit_hook: c.commit_hook.clone(),
+            become_follower_hook: c.become_follower_hook.clone(),
+            count_learners_in_read_quorum: c.count_learners_in_read_quorum,
+            prefer_lower_id_candidate: c.prefer_lower_id_candidate,
+            heartbeat_response_timeout_ticks: c.heartbeat_response_timeout_ticks,
+            leader_ticks: 0,
+            leader_epoch: 0,
+            snapshot_cooldown_ticks: c.snapshot_cooldown_ticks,
+            deterministic_broadcast_order: c.deterministic_broadcast_order,
+            async_storage_writes: c.async_storage_writes,
+            persisted: 0,
+            transfer_election_bias_ticks: c.transfer_election_bias_ticks,
+            prevote_backoff_cap_ticks: c.prevote_backoff_cap_ticks,
+            prevote_max_lag: c.prevote_max_lag,
+            failed_pre_votes: 0,
+            append_conflicts: 0,
+            overwritten_entries: vec![],
+            record_overwritten_entries: c.record_overwritten_entries,
+            unapplied_conf_changes: None,
+            appended: LogGrowth::default(),
+            appended_at_last_take: LogGrowth::default(),
+            message_metrics: MessageMetrics::default(),
+            receiving_snapshot: None,
         };
+        // A corrupted conf state, e.g. listing a node as both a voter and a learner, fails the
+        // insertions, which is reported rather than crashing the process.
         for p in peers {
             let pr = Progress::new(1, r.max_inflight);
-            if let Err(e) = r.mut_prs().insert_voter(*p, pr) {
-                panic!("{}", e);
-            }
+            r.mut_prs().insert_voter(*p, pr)?;
         }
         for p in learners {
             let pr = Progress::new(1, r.max_inflight);
-            if let Err(e) = r.mut_prs().insert_learner(*p, pr) {
-                panic!("{}", e);
-            };
+            r.mut_prs().insert_learner(*p, pr)?;
             if *p == r.id {
                 r.is_learner = true;
             }
         }
+        if c.is_learner {
+            if r.prs().voter_ids().contains(&r.id) {
+                return Err(Error::ConfigInvalid(format!(
+                    "{} is configured as a learner but is a voter in the initial conf state",
+                    r.id
+                )));
+            }
+            r.is_learner = true;
+        }
+
+        if c.count_learners_in_read_quorum {
+            warn!(
+                "{} counts learners in the read index quorum, reads may be stale if the \
+                 learners are partitioned with a deposed leader",
+                r.tag
+            );
+        }
+
+        if r.is_learner && c.read_only_option == ReadOnlyOption::LeaseBased {
+            // A learner never holds a lease: it can't become leader, and reads issued on it
+            // are always forwarded to the leader, which serves them with its own option.
+            info!(
+                "{} is a learner, read_only_option {:?} only applies once promoted to voter",
+                r.tag, c.read_only_option
+            );
+        }
 
         if raft_state.hard_state != HardState::default() {
             r.load_state(&raft_state.hard_state);
         }
-        if c.applied > 0 {
-            r.commit_apply(c.applied);
+        // Whatever the storage holds initially is durable.
+        r.persisted = r.raft_log.last_index();
+        r.check_applied_regression(c)?;
+        // A regressed applied index was only warned about, resume from the snapshot then.
+        if c.applied > r.raft_log.applied {
+            r.commit_apply(c.applied)?;
         }
         let term = r.term;
         r.become_follower(term, INVALID_ID);
@@ -321,6 +698,25 @@ impl<T: Storage> Raft<T> {
         Ok(r)
     }
 
+    /// Checks whether `Config::applied` implies the application state regressed, i.e. it's
+    /// behind the snapshot, see `Config::panic_on_applied_regression`.
+    fn check_applied_regression(&self, c: &Config) -> Result<()> {
+        // Everything up to the snapshot was applied when it was taken.
+        let snapshot_index = self.raft_log.get_applied();
+        if c.applied == 0 || c.applied >= snapshot_index {
+            return Ok(());
+        }
+        let msg = format!(
+            "applied {} regressed, snapshot index: {}",
+            c.applied, snapshot_index
+        );
+        if c.panic_on_applied_regression {
+            return Err(Error::ConfigInvalid(msg));
+        }
+        warn!("{} {}", self.tag, msg);
+        Ok(())
+    }
+
     /// Grabs an immutable reference to the store.
     #[inline]
     pub fn get_store(&self) -> &T {
@@ -351,6 +747,18 @@ impl<T: Storage> Raft<T> {
         self.read_states.len()
     }
 
+    /// Returns whether there are outbound messages waiting to be sent.
+    #[inline]
+    pub fn has_messages(&self) -> bool {
+        !self.msgs.is_empty()
+    }
+
+    /// Returns how many outbound messages are waiting to be sent.
+    #[inline]
+    pub fn message_count(&self) -> usize {
+        self.msgs.len()
+    }
+
     /// Returns a value representing the softstate at the time of calling.
     pub fn soft_state(&self) -> SoftState {
         SoftState {
@@ -373,6 +781,167 @@ impl<T: Storage> Raft<T> {
         self.state == StateRole::Leader && self.check_quorum
     }
 
+    /// Returns the number of ticks left before the leader checks its quorum again, i.e. the
+    /// remaining lifetime of its lease, or `None` if it's not in lease.
+    ///
+    /// Lease based reads should be refused once this gets close to 0, to leave a margin for
+    /// clock drift.
+    pub fn lease_remaining_ticks(&self) -> Option<usize> {
+        if !self.in_lease() {
+            return None;
+        }
+        Some(self.election_timeout.saturating_sub(self.election_elapsed))
+    }
+
+    /// Returns the index up to which a follower can serve reads locally, without a
+    /// `MsgReadIndex` round trip to the leader, or `None` if it holds no lease delegated by
+    /// the leader, see `Config::delegate_read_lease`.
+    ///
+    /// The index is the follower's commit index, which may lag behind the leader's.
+    pub fn follower_read_index(&self) -> Option<u64> {
+        if self.state == StateRole::Follower
+            && self.leader_id != INVALID_ID
+            && self.delegated_lease_elapsed < self.delegated_lease_ticks
+        {
+            Some(self.raft_log.committed)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether a proposal stepped on this peer would be forwarded to the leader,
+    /// which is the case for followers and learners which know the current leader, unless
+    /// `Config::disable_proposal_forwarding` is set.
+    pub fn can_forward_proposals(&self) -> bool {
+        self.state == StateRole::Follower
+            && self.leader_id != INVALID_ID
+            && !self.disable_proposal_forwarding
+    }
+
+    /// Returns the number of accepted appends which overwrote conflicting entries of the local
+    /// log. A steady increase indicates leader churn.
+    #[inline]
+    pub fn append_conflicts(&self) -> u64 {
+        self.append_conflicts
+    }
+
+    /// Returns the entries appended by this node as a leader since it started. The counters
+    /// only grow, and are not persisted.
+    #[inline]
+    pub fn appended(&self) -> LogGrowth {
+        self.appended
+    }
+
+    /// Returns the entries appended by this node as a leader since the last call, which gives
+    /// the log growth rate when called periodically. The first call covers everything since
+    /// the node started.
+    pub fn take_log_growth(&mut self) -> LogGrowth {
+        let growth = LogGrowth {
+            entries: self.appended.entries - self.appended_at_last_take.entries,
+            bytes: self.appended.bytes - self.appended_at_last_take.bytes,
+        };
+        self.appended_at_last_take = self.appended;
+        growth
+    }
+
+    /// Returns the number of messages of each type sent and received since the node started
+    /// or `take_message_metrics` was last called.
+    #[inline]
+    pub fn message_metrics(&self) -> MessageMetrics {
+        self.message_metrics
+    }
+
+    /// Returns the message metrics like `message_metrics`, and resets them.
+    pub fn take_message_metrics(&mut self) -> MessageMetrics {
+        let metrics = self.message_metrics;
+        self.message_metrics = MessageMetrics::default();
+        metrics
+    }
+
+    /// Returns the number of entries in the log after the last snapshot, which can be used to
+    /// decide when to create a new one.
+    #[inline]
+    pub fn entries_since_snapshot(&self) -> u64 {
+        self.raft_log.last_index() + 1 - self.raft_log.first_index()
+    }
+
+    /// Returns the committed entries which aren't applied yet, i.e. from `applied + 1` up to
+    /// the committed index, truncated to `max_size` bytes but always holding at least one
+    /// entry if any is pending. Like `RaftLog::next_entries`, it starts after the snapshot if
+    /// `applied` is behind it. Empty if everything committed is applied.
+    ///
+    /// Calling it repeatedly with a limit, advancing `applied` with `commit_apply` after each
+    /// batch, lets the application apply the committed entries in bounded batches.
+    ///
+    /// # Errors
+    ///
+    /// Returns the storage error if the entries can't be fetched.
+    pub fn next_entries(&self, max_size: Option<u64>) -> Result<Vec<Entry>> {
+        let low = cmp::max(self.raft_log.applied + 1, self.raft_log.first_index());
+        let high = self.raft_log.committed + 1;
+        if low >= high {
+            return Ok(vec![]);
+        }
+        self.raft_log.slice(low, high, max_size)
+    }
+
+    /// Returns the encoded size of the entries in the log after the last snapshot.
+    ///
+    /// This reads all the entries, so it's more expensive than `entries_since_snapshot`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the storage error if the entries can't be fetched.
+    pub fn bytes_since_snapshot(&self) -> Result<u64> {
+        let ents = self.raft_log.entries(self.raft_log.first_index(), None)?;
+        Ok(ents.iter().map(|e| e.encoded_len() as u64).sum())
+    }
+
+    /// Returns the number of committed but unapplied configuration changes, which prevent
+    /// campaigning. Only the committed tail matters: entries which aren't committed yet may
+    /// still be overwritten by the next leader.
+    ///
+    /// Committed entries never change, so the count is reused until the applied or committed
+    /// index moves, sparing repeated campaign attempts a scan of a large backlog.
+    fn unapplied_conf_changes(&mut self) -> usize {
+        let key = (self.raft_log.applied, self.raft_log.committed);
+        if let Some((k, n)) = self.unapplied_conf_changes {
+            if k == key {
+                return n;
+            }
+        }
+        let ents = self
+            .raft_log
+            .slice(key.0 + 1, key.1 + 1, None)
+            .expect("unexpected error getting unapplied entries");
+        let n = self.num_pending_conf(&ents);
+        self.unapplied_conf_changes = Some((key, n));
+        n
+    }
+
+    /// Takes the index ranges of uncommitted entries which were overwritten by a new leader
+    /// since the last call, so the application can undo work it did speculatively on them.
+    /// Always empty unless `Config::record_overwritten_entries` is set.
+    pub fn take_overwritten_entries(&mut self) -> Vec<Range<u64>> {
+        self.overwritten_entries.drain(..).collect()
+    }
+
+    /// Returns the ids of the peers the leader is currently sending a snapshot to.
+    ///
+    /// Each of them costs a snapshot fetch from the storage, so the length of this bounds the
+    /// snapshot pressure on the storage and can be used to cap concurrent snapshot streams.
+    /// Always empty on a non-leader.
+    pub fn peers_receiving_snapshot(&self) -> Vec<u64> {
+        if self.state != StateRole::Leader {
+            return vec![];
+        }
+        self.prs()
+            .iter()
+            .filter(|(_, pr)| pr.state == ProgressState::Snapshot)
+            .map(|(&id, _)| id)
+            .collect()
+    }
+
     /// For testing leader lease
     #[doc(hidden)]
     pub fn set_randomized_election_timeout(&mut self, t: usize) {
@@ -400,12 +969,153 @@ impl<T: Storage> Raft<T> {
         self.randomized_election_timeout
     }
 
+    /// Fetch the number of ticks elapsed since the election timer was last reset.
+    #[inline]
+    pub fn election_elapsed_ticks(&self) -> usize {
+        self.election_elapsed
+    }
+
+    /// Returns the number of ticks left before a follower or candidate starts an election,
+    /// unless it hears from a leader in the meantime. A leader never campaigns, so the value
+    /// is meaningless for it.
+    #[inline]
+    pub fn ticks_until_election(&self) -> usize {
+        self.randomized_election_timeout
+            .saturating_sub(self.election_elapsed)
+    }
+
+    /// Returns the term at which this node last became leader, 0 if it hasn't led since it
+    /// started.
+    ///
+    /// The epoch strictly increases with each leadership, also across restarts as the term is
+    /// persisted, and no two nodes ever lead with the same one. So external resources gated on
+    /// leadership, e.g. a lock service, can fence a deposed leader by rejecting the operations
+    /// of an epoch older than the latest they've seen.
+    ///
+    /// The current term nearly serves this, but it also advances through failed elections and
+    /// on followers, so it doesn't tell whether, or when, this node actually led.
+    #[inline]
+    pub fn leader_epoch(&self) -> u64 {
+        self.leader_epoch
+    }
+
+    /// Restarts the election timer of a follower or candidate as if it just heard from the
+    /// leader, e.g. when the application learns out of band that the leader is alive. Does
+    /// nothing on a leader, whose election timer drives the quorum check.
+    ///
+    /// Resetting the timer while the leader is actually gone delays the election replacing
+    /// it, so only call it on reliable liveness information.
+    pub fn reset_election_timer(&mut self) {
+        if self.state != StateRole::Leader {
+            self.election_elapsed = 0;
+        }
+    }
+
     /// Set whether skip broadcast empty commit messages at runtime.
     #[inline]
     pub fn skip_bcast_commit(&mut self, skip: bool) {
         self.skip_bcast_commit = skip;
     }
 
+    /// Hands a read state to the callback if one is configured, or queues it in `read_states`.
+    fn push_read_state(&mut self, rs: ReadState) {
+        match self.read_state_callback {
+            Some(ref callback) => callback(rs),
+            None => self.read_states.push(rs),
+        }
+    }
+
+    /// Applies the state transition `f` to the progress `pr` of peer `id`, and reports the
+    /// change of its state to the hook if one is configured.
+    fn transition_progress<F>(&self, id: u64, pr: &mut Progress, f: F)
+    where
+        F: FnOnce(&mut Progress),
+    {
+        let from = pr.state;
+        f(pr);
+        if from == pr.state {
+            return;
+        }
+        if let Some(ref hook) = self.progress_state_hook {
+            hook(id, from, pr.state);
+        }
+    }
+
+    /// Reports the pending snapshot at `index` of peer `id` abandoned to the hook if one is
+    /// configured.
+    fn report_snapshot_abort(&self, id: u64, index: u64) {
+        if let Some(ref hook) = self.snapshot_abort_hook {
+            hook(id, index);
+        }
+    }
+
+    /// Reports the commit index advance from `old_commit` to the `commit_hook`, if any.
+    fn report_commit(&self, old_commit: u64) {
+        let committed = self.raft_log.committed;
+        if committed <= old_commit {
+            return;
+        }
+        if let Some(ref hook) = self.commit_hook {
+            hook(old_commit, committed);
+        }
+    }
+
+    /// Returns the term of the entry at `index`, which may be the index of the last compacted
+    /// entry, i.e. `first_index - 1`. `None` if the entry was compacted away, its term is
+    /// unavailable from the storage, or it is past the last index.
+    pub fn term_of(&self, index: u64) -> Option<u64> {
+        // Checks the last index first, `index + 1` would overflow for `u64::MAX`.
+        if index > self.raft_log.last_index() || index + 1 < self.raft_log.first_index() {
+            return None;
+        }
+        self.raft_log.term(index).ok()
+    }
+
+    /// Whether the read index request `m` was issued on this node, rather than forwarded by a
+    /// follower or sent by a non-member with a return address.
+    fn is_local_read(&self, m: &Message) -> bool {
+        m.get_return_address().is_empty() && (m.get_from() == INVALID_ID || m.get_from() == self.id)
+    }
+
+    /// Returns a copy of the replication progress of the given peer, `None` unless this node
+    /// is the leader and tracks the peer.
+    pub fn peer_progress(&self, id: u64) -> Option<ProgressSnapshot> {
+        if self.state != StateRole::Leader {
+            return None;
+        }
+        let pr = self.prs().get(id)?;
+        Some(ProgressSnapshot::new(pr, self.prs().is_learner(id)))
+    }
+
+    /// Returns the peers which haven't answered a heartbeat for
+    /// `Config::heartbeat_response_timeout_ticks`, in ascending order. Empty unless this node
+    /// is the leader and the detection is enabled.
+    pub fn unresponsive_peers(&self) -> Vec<u64> {
+        if self.state != StateRole::Leader || self.heartbeat_response_timeout_ticks == 0 {
+            return vec![];
+        }
+        let timeout = self.heartbeat_response_timeout_ticks as u64;
+        let mut peers: Vec<u64> = self
+            .prs()
+            .iter()
+            .filter(|&(_, pr)| match pr.heartbeat_sent_at {
+                Some(sent_at) => self.leader_ticks - sent_at >= timeout,
+                None => false,
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    /// Returns the index at or below which a configuration change may still be pending, see
+    /// `has_pending_conf`. Proposing a configuration change is only allowed once the applied
+    /// index reaches it.
+    #[inline]
+    pub fn pending_conf_index(&self) -> u64 {
+        self.pending_conf_index
+    }
+
     /// Set when the peer began a joint consensus change.
     ///
     /// This will also set `pending_conf_index` if it is larger than the existing number.
@@ -420,6 +1130,7 @@ impl<T: Storage> Raft<T> {
             }
         }
         self.pending_membership_change = maybe_change.clone();
+        self.pending_finalize_index = None;
     }
 
     /// Get the index which the pending membership change started at.
@@ -432,16 +1143,129 @@ impl<T: Storage> Raft<T> {
             .map(ConfChange::get_start_index)
     }
 
+    /// Returns how far the pending membership change has progressed, or `None` if there is
+    /// none.
+    ///
+    /// > **Note:** This is an experimental feature.
+    pub fn membership_change_status(&self) -> Option<MembershipChangeStatus> {
+        let begin_index = self.began_membership_change_at()?;
+        let finalize_index = self.pending_finalize_index;
+        Some(MembershipChangeStatus {
+            begin_index,
+            begin_committed: begin_index <= self.raft_log.committed,
+            begin_applied: begin_index <= self.raft_log.applied,
+            finalize_index,
+            finalize_committed: match finalize_index {
+                Some(index) => index <= self.raft_log.committed,
+                None => false,
+            },
+        })
+    }
+
+    /// Returns the committed configuration change entries of the pending membership change,
+    /// from its begin entry up to the committed index, which includes the finalize entry once
+    /// committed, e.g. to record in an audit log what the cluster agreed to. Empty if no
+    /// membership change is pending.
+    ///
+    /// > **Note:** This is an experimental feature.
+    ///
+    /// # Errors
+    ///
+    /// Returns the storage error if the entries can't be fetched, e.g. they were compacted.
+    pub fn membership_change_entries(&self) -> Result<Vec<Entry>> {
+        let begin_index = match self.began_membership_change_at() {
+            Some(index) => index,
+            None => return Ok(vec![]),
+        };
+        let committed = self.raft_log.committed;
+        if begin_index > committed {
+            return Ok(vec![]);
+        }
+        let ents = self.raft_log.slice(begin_index, committed + 1, None)?;
+        Ok(ents
+            .into_iter()
+            .filter(|e| e.get_entry_type() == EntryType::EntryConfChange)
+            .collect())
+    }
+
+    /// Returns the entries of the log in `[lo, hi)`, persisted or not, for debugging tools and
+    /// tests inspecting a diverging log. `hi` is clamped to the end of the log, so an empty
+    /// range past it gives no entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StorageError::Compacted` if `lo` is below the first index still in the log.
+    #[doc(hidden)]
+    pub fn log_entries(&self, lo: u64, hi: u64) -> Result<Vec<Entry>> {
+        if lo < self.raft_log.first_index() {
+            return Err(Error::Store(StorageError::Compacted));
+        }
+        let hi = cmp::min(hi, self.raft_log.last_index() + 1);
+        if lo >= hi {
+            return Ok(vec![]);
+        }
+        self.raft_log.slice(lo, hi, None)
+    }
+
     /// Set whether batch append msg at runtime.
     #[inline]
     pub fn set_batch_append(&mut self, batch_append: bool) {
         self.batch_append = batch_append;
     }
 
+    /// Set the allowed lag of a transfer target campaigning on `MsgTimeoutNow` at runtime.
+    #[inline]
+    pub fn set_timeout_now_max_lag(&mut self, lag: u64) {
+        self.timeout_now_max_lag = lag;
+    }
+
+    /// Set the heartbeat and election timeouts at runtime, e.g. to widen them while the
+    /// network is unstable. The arguments mean the same as the `Config` fields
+    /// `heartbeat_tick`, `election_tick`, `min_election_tick` and `max_election_tick`, 0
+    /// picking the default range for the latter two. The randomized election timeout is drawn
+    /// again from the new range, the elapsed ticks are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::ConfigInvalid` if the timeouts break the invariants checked by
+    /// `Config::validate`, leaving the current timeouts unchanged.
+    pub fn set_timeouts(
+        &mut self,
+        heartbeat: usize,
+        election: usize,
+        min_election: usize,
+        max_election: usize,
+    ) -> Result<()> {
+        let c = Config {
+            id: self.id,
+            heartbeat_tick: heartbeat,
+            election_tick: election,
+            min_election_tick: min_election,
+            max_election_tick: max_election,
+            ..Default::default()
+        };
+        c.validate()?;
+        info!(
+            "{} set timeouts to heartbeat: {}, election: {}, randomized in [{}, {})",
+            self.tag,
+            c.heartbeat_tick,
+            c.election_tick,
+            c.min_election_tick(),
+            c.max_election_tick()
+        );
+        self.heartbeat_timeout = c.heartbeat_tick;
+        self.election_timeout = c.election_tick;
+        self.min_election_timeout = c.min_election_tick();
+        self.max_election_timeout = c.max_election_tick();
+        self.reset_randomized_election_timeout();
+        Ok(())
+    }
+
     // send persists state to stable storage and then sends to its mailbox.
     fn send(&mut self, mut m: Message) {
         debug!("Sending from {} to {}: {:?}", self.id, m.get_to(), m);
         m.set_from(self.id);
+        self.message_metrics.record_sent(m.get_msg_type());
         if m.get_msg_type() == MessageType::MsgRequestVote
             || m.get_msg_type() == MessageType::MsgRequestPreVote
             || m.get_msg_type() == MessageType::MsgRequestVoteResponse
@@ -488,6 +1312,18 @@ impl<T: Storage> Raft<T> {
         self.msgs.push(m);
     }
 
+    /// Whether a snapshot at `index` was already sent to the follower of `pr` within
+    /// `snapshot_cooldown_ticks`.
+    fn in_snapshot_cooldown(&self, pr: &Progress, index: u64) -> bool {
+        if self.snapshot_cooldown_ticks == 0 || index > pr.snapshot_sent_index {
+            return false;
+        }
+        match pr.snapshot_sent_at {
+            Some(sent_at) => self.leader_ticks - sent_at < self.snapshot_cooldown_ticks as u64,
+            None => false,
+        }
+    }
+
     fn prepare_send_snapshot(&mut self, m: &mut Message, pr: &mut Progress, to: u64) -> bool {
         if !pr.recent_active {
             debug!(
@@ -518,6 +1354,18 @@ impl<T: Storage> Raft<T> {
             snapshot.get_metadata().get_index(),
             snapshot.get_metadata().get_term(),
         );
+        if self.in_snapshot_cooldown(pr, sindex) {
+            // Probe from the last snapshot sent instead, the follower may have retained it.
+            debug!(
+                "{} not sending snapshot[index: {}] to {} again within the cooldown, \
+                 probing from {}",
+                self.tag, sindex, to, pr.snapshot_sent_index
+            );
+            pr.next_idx = pr.snapshot_sent_index + 1;
+            return false;
+        }
+        pr.snapshot_sent_index = sindex;
+        pr.snapshot_sent_at = Some(self.leader_ticks);
         m.set_snapshot(snapshot);
         debug!(
             "{} [firstindex: {}, commit: {}] sent snapshot[index: {}, term: {}] to {} \
@@ -530,7 +1378,7 @@ impl<T: Storage> Raft<T> {
             to,
             pr
         );
-        pr.become_snapshot(sindex);
+        self.transition_progress(to, pr, |pr| pr.become_snapshot(sindex));
         debug!(
             "{} paused sending replication messages to {} [{:?}]",
             self.tag, to, pr
@@ -580,6 +1428,43 @@ impl<T: Storage> Raft<T> {
         is_batched
     }
 
+    /// Compresses the data of the entries larger than the threshold with the entry codec, if
+    /// one is configured.
+    fn compress_entries(&self, ents: &mut [Entry]) {
+        let codec = match self.entry_codec {
+            Some(ref codec) => codec,
+            None => return,
+        };
+        for e in ents.iter_mut() {
+            if e.get_data().len() <= self.compression_threshold {
+                continue;
+            }
+            let data = codec.compress(e.get_data());
+            if data.len() < e.get_data().len() {
+                e.set_data(data);
+                e.set_compressed(true);
+            }
+        }
+    }
+
+    /// Restores the entries compressed by the sender, so the log holds the original data.
+    fn decompress_entries(&self, ents: &[Entry]) -> Result<Vec<Entry>> {
+        let codec = self.entry_codec.as_ref().ok_or_else(|| {
+            Error::ViolatesContract("compressed entries received without an entry codec".into())
+        })?;
+        let mut decompressed = Vec::with_capacity(ents.len());
+        for e in ents {
+            let mut e = e.clone();
+            if e.get_compressed() {
+                let data = codec.decompress(e.get_data())?;
+                e.set_data(data);
+                e.set_compressed(false);
+            }
+            decompressed.push(e);
+        }
+        Ok(decompressed)
+    }
+
     /// Sends RPC, with entries to the given peer.
     pub fn send_append(&mut self, to: u64, pr: &mut Progress) {
         if pr.is_paused() {
@@ -594,41 +1479,87 @@ impl<T: Storage> Raft<T> {
         let ents = self.raft_log.entries(pr.next_idx, self.max_msg_size);
         let mut m = Message::default();
         m.set_to(to);
-        if term.is_err() || ents.is_err() {
-            // send snapshot if we failed to get term or entries
-            trace!(
-                "{} Skipping sending to {}, term: {:?}, index: {}, ents: {:?}",
-                self.tag,
-                to,
-                term,
-                pr.next_idx,
-                ents,
-            );
-            if !self.prepare_send_snapshot(&mut m, pr, to) {
-                return;
+        match (term, ents) {
+            (Ok(term), Ok(mut ents)) => {
+                self.compress_entries(&mut ents);
+                if self.batch_append {
+                    let batched = self.try_batching(to, pr, &mut ents);
+                    if batched {
+                        return;
+                    }
+                }
+                self.prepare_send_entries(&mut m, pr, term, ents);
             }
-        } else {
-            let mut ents = ents.unwrap();
-            if self.batch_append {
-                let batched = self.try_batching(to, pr, &mut ents);
-                if batched {
+            (term, ents)
+                if term == Err(Error::Store(StorageError::Compacted))
+                    || ents == Err(Error::Store(StorageError::Compacted)) =>
+            {
+                // send snapshot if the term or entries have been compacted
+                trace!(
+                    "{} Skipping sending to {}, term: {:?}, index: {}, ents: {:?}",
+                    self.tag,
+                    to,
+                    term,
+                    pr.next_idx,
+                    ents,
+                );
+                if !self.prepare_send_snapshot(&mut m, pr, to) {
                     return;
                 }
             }
-            let term = term.unwrap();
-            self.prepare_send_entries(&mut m, pr, term, ents);
+            (term, ents) => {
+                // Any other failure is unexpected, and a snapshot wouldn't fix it. Retry with
+                // the next heartbeat response instead.
+                error!(
+                    "{} failed to fetch the log to send to {}, term: {:?}, index: {}, \
+                     ents: {:?}",
+                    self.tag, to, term, pr.next_idx, ents,
+                );
+                return;
+            }
+        }
+        if m.get_msg_type() == MessageType::MsgAppend && self.is_duplicate_append(&m) {
+            trace!(
+                "{} Skipping sending to {}, the same append was sent since the last heartbeat, \
+                 index: {}",
+                self.tag,
+                to,
+                m.get_index(),
+            );
+            return;
         }
         self.send(m);
     }
 
+    /// Checks whether `m` is the same append as the last one sent to its receiver since the
+    /// last heartbeat, and records it otherwise. Always false unless
+    /// `suppress_duplicate_appends` is set.
+    fn is_duplicate_append(&mut self, m: &Message) -> bool {
+        if !self.suppress_duplicate_appends {
+            return false;
+        }
+        let last = m
+            .get_entries()
+            .last()
+            .map_or(m.get_index(), |e| e.get_index());
+        let sent = (m.get_index(), m.get_log_term(), last, m.get_commit());
+        if self.last_sent_appends.get(&m.get_to()) == Some(&sent) {
+            return true;
+        }
+        self.last_sent_appends.insert(m.get_to(), sent);
+        false
+    }
+
     // send_heartbeat sends an empty MsgAppend
-    fn send_heartbeat(&mut self, to: u64, pr: &Progress, ctx: Option<Vec<u8>>) {
+    fn send_heartbeat(&mut self, to: u64, pr: &mut Progress, ctx: Option<Vec<u8>>) {
         // Attach the commit as min(to.matched, self.raft_log.committed).
         // When the leader sends out heartbeat message,
         // the receiver(follower) might not be matched with the leader
         // or it might not have all the committed entries.
         // The leader MUST NOT forward the follower's commit to
-        // an unmatched index.
+        // an unmatched index. For a caught up follower this is exactly
+        // the leader's commit, so heartbeats alone advance its commit
+        // when empty appends aren't broadcast (see `skip_bcast_commit`).
         let mut m = Message::default();
         m.set_to(to);
         m.set_msg_type(MessageType::MsgHeartbeat);
@@ -637,6 +1568,15 @@ impl<T: Storage> Raft<T> {
         if let Some(context) = ctx {
             m.set_context(context);
         }
+        if self.delegate_read_lease {
+            if let Some(remaining) = self.lease_remaining_ticks() {
+                let delegated = remaining.saturating_sub(self.heartbeat_timeout);
+                m.set_lease_ticks(delegated as u64);
+            }
+        }
+        if pr.heartbeat_sent_at.is_none() {
+            pr.heartbeat_sent_at = Some(self.leader_ticks);
+        }
         self.send(m);
     }
 
@@ -645,10 +1585,38 @@ impl<T: Storage> Raft<T> {
     pub fn bcast_append(&mut self) {
         let self_id = self.id;
         let mut prs = self.take_prs();
-        prs.iter_mut()
-            .filter(|&(id, _)| *id != self_id)
-            .for_each(|(id, pr)| self.send_append(*id, pr));
+        if self.deterministic_broadcast_order {
+            for id in sorted_peer_ids(&prs, self_id) {
+                let pr = prs.get_mut(id).unwrap();
+                if !pr.is_paused() {
+                    self.send_append(id, pr);
+                }
+            }
+        } else {
+            // Skip paused peers, e.g. those receiving a snapshot or with a full inflight
+            // window, before getting into `send_append`.
+            prs.iter_mut()
+                .filter(|&(id, ref pr)| *id != self_id && !pr.is_paused())
+                .for_each(|(id, pr)| self.send_append(*id, pr));
+        }
         self.set_prs(prs);
+        self.last_bcast_commit = self.raft_log.committed;
+    }
+
+    /// Broadcasts heartbeats to all the followers if it's the leader, otherwise does nothing.
+    pub fn ping(&mut self) {
+        if self.state == StateRole::Leader {
+            self.bcast_heartbeat();
+        }
+    }
+
+    /// Broadcasts heartbeats carrying the context of the last pending read if it's the leader,
+    /// so reads which missed a quorum, e.g. during a transient partition, are confirmed without
+    /// waiting for the next heartbeat timeout. Does nothing if no read is pending.
+    pub fn flush_read_index(&mut self) {
+        if self.state == StateRole::Leader && self.read_only.pending_read_count() > 0 {
+            self.bcast_heartbeat();
+        }
     }
 
     /// Sends RPC, without entries to all the peers.
@@ -659,11 +1627,19 @@ impl<T: Storage> Raft<T> {
 
     #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
     fn bcast_heartbeat_with_ctx(&mut self, ctx: Option<Vec<u8>>) {
+        // Appends lost since the last heartbeat may be sent again.
+        self.last_sent_appends.clear();
         let self_id = self.id;
         let mut prs = self.take_prs();
-        prs.iter_mut()
-            .filter(|&(id, _)| *id != self_id)
-            .for_each(|(id, pr)| self.send_heartbeat(*id, pr, ctx.clone()));
+        if self.deterministic_broadcast_order {
+            for id in sorted_peer_ids(&prs, self_id) {
+                self.send_heartbeat(id, prs.get_mut(id).unwrap(), ctx.clone());
+            }
+        } else {
+            prs.iter_mut()
+                .filter(|&(id, _)| *id != self_id)
+                .for_each(|(id, pr)| self.send_heartbeat(*id, pr, ctx.clone()));
+        }
         self.set_prs(prs);
     }
 
@@ -671,7 +1647,65 @@ impl<T: Storage> Raft<T> {
     /// changed (in which case the caller should call `r.bcast_append`).
     pub fn maybe_commit(&mut self) -> bool {
         let mci = self.prs().maximal_committed_index();
-        self.raft_log.maybe_commit(mci, self.term)
+        let old_commit = self.raft_log.committed;
+        if !self.raft_log.maybe_commit(mci, self.term) {
+            return false;
+        }
+        self.report_commit(old_commit);
+        true
+    }
+
+    /// **Break-glass disaster recovery only.** Advances the commit index as if a quorum of
+    /// `quorum` voters sufficed, for this single call, and broadcasts the new commit index.
+    /// Returns true if the commit index changed. As usual, only an entry of the current term
+    /// can be committed this way.
+    ///
+    /// Committing without a real majority can lose acknowledged writes and violate
+    /// linearizability if the missing voters come back. Only use it when the lost voters are
+    /// confirmed gone for good, then replace them with a membership change.
+    ///
+    /// # Errors
+    ///
+    /// * This node isn't the leader.
+    /// * A membership change is in progress.
+    /// * `quorum` is 0 or larger than the number of voters.
+    #[doc(hidden)]
+    pub fn force_commit_with_quorum_override(&mut self, quorum: usize) -> Result<bool> {
+        if self.state != StateRole::Leader {
+            return Err(Error::NotLeader);
+        }
+        if self.prs().is_in_membership_change() {
+            return Err(Error::ViolatesContract(
+                "can't override the quorum during a membership change".to_owned(),
+            ));
+        }
+        let mut matched: Vec<u64> = self.prs().voters().map(|(_, pr)| pr.matched).collect();
+        if quorum == 0 || quorum > matched.len() {
+            return Err(Error::ViolatesContract(format!(
+                "quorum override {} out of range [1, {}]",
+                quorum,
+                matched.len()
+            )));
+        }
+        // Reverse sort.
+        matched.sort_by(|a, b| b.cmp(a));
+        let index = matched[quorum - 1];
+        error!(
+            "{} UNSAFE: forcing commit with a quorum of {} out of {} voters, up to index {}, \
+             committed: {}",
+            self.tag,
+            quorum,
+            matched.len(),
+            index,
+            self.raft_log.committed
+        );
+        let old_commit = self.raft_log.committed;
+        if !self.raft_log.maybe_commit(index, self.term) {
+            return Ok(false);
+        }
+        self.report_commit(old_commit);
+        self.bcast_append();
+        Ok(true)
     }
 
     /// Commit that the Raft peer has applied up to the given index.
@@ -681,7 +1715,22 @@ impl<T: Storage> Raft<T> {
     /// # Hooks
     ///
     /// * Post: Checks to see if it's time to finalize a Joint Consensus state.
-    pub fn commit_apply(&mut self, applied: u64) {
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::AppliedOutOfRange` if `applied` is beyond the committed index or behind
+    /// the applied index, leaving the applied index unchanged. 0 is ignored.
+    pub fn commit_apply(&mut self, applied: u64) -> Result<()> {
+        if applied == 0 {
+            return Ok(());
+        }
+        if applied > self.raft_log.committed || applied < self.raft_log.applied {
+            return Err(Error::AppliedOutOfRange(
+                applied,
+                self.raft_log.applied,
+                self.raft_log.committed,
+            ));
+        }
         #[allow(deprecated)]
         self.raft_log.applied_to(applied);
 
@@ -694,11 +1743,29 @@ impl<T: Storage> Raft<T> {
 
         if let Some(index) = start_index {
             // Invariant: We know that if we have commited past some index, we can also commit that index.
-            if applied >= index && self.state == StateRole::Leader {
+            // The finalize entry may already be in the log, e.g. if an earlier call applied the
+            // begin entry, or the applied range spans both entries.
+            if applied >= index
+                && self.state == StateRole::Leader
+                && self.pending_finalize_index.is_none()
+            {
                 // We must replicate the commit entry.
                 self.append_finalize_conf_change_entry();
             }
         }
+        Ok(())
+    }
+
+    /// Returns the index of the first entry of `ents` after `index` finalizing a membership
+    /// change.
+    fn find_finalize_entry(ents: &[Entry], index: u64) -> Option<u64> {
+        ents.iter()
+            .filter(|e| e.get_index() > index && e.get_entry_type() == EntryType::EntryConfChange)
+            .find(|e| match ConfChange::decode(e.get_data()) {
+                Ok(cc) => cc.get_change_type() == ConfChangeType::FinalizeMembershipChange,
+                Err(_) => false,
+            })
+            .map(Entry::get_index)
     }
 
     fn append_finalize_conf_change_entry(&mut self) {
@@ -710,7 +1777,8 @@ impl<T: Storage> Raft<T> {
         entry.set_entry_type(EntryType::EntryConfChange);
         entry.set_data(data);
         // Index/Term set here.
-        self.append_entry(&mut [entry]);
+        self.append_to_log(&mut [entry]);
+        self.pending_finalize_index = Some(self.raft_log.last_index());
         self.bcast_append();
     }
 
@@ -719,11 +1787,15 @@ impl<T: Storage> Raft<T> {
         if self.term != term {
             self.term = term;
             self.vote = INVALID_ID;
+            // A chunked snapshot from the previous term will never be completed.
+            self.abort_snapshot_chunks();
         }
         self.leader_id = INVALID_ID;
         self.reset_randomized_election_timeout();
         self.election_elapsed = 0;
         self.heartbeat_elapsed = 0;
+        self.delegated_lease_ticks = 0;
+        self.last_sent_appends.clear();
 
         self.abort_leader_transfer();
 
@@ -733,33 +1805,135 @@ impl<T: Storage> Raft<T> {
         self.read_only = ReadOnly::new(self.read_only.option);
 
         let last_index = self.raft_log.last_index();
+        let durable_index = self.durable_index(last_index);
         let self_id = self.id;
         for (&id, pr) in self.mut_prs().iter_mut() {
             pr.reset(last_index + 1);
             if id == self_id {
-                pr.matched = last_index;
+                pr.matched = durable_index;
+                // A node is always live from its own point of view.
+                pr.recent_active = true;
             }
         }
     }
 
-    /// Appends a slice of entries to the log. The entries are updated to match
+    /// Appends a slice of entries to the log of the leader. The entries are updated to match
     /// the current index and term.
-    pub fn append_entry(&mut self, es: &mut [Entry]) {
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NotLeader` if this node isn't the leader, since appending updates the
+    /// progress of the leader and may commit the entries.
+    pub fn append_entry(&mut self, es: &mut [Entry]) -> Result<()> {
+        if self.state != StateRole::Leader {
+            return Err(Error::NotLeader);
+        }
+        self.append_to_log(es);
+        Ok(())
+    }
+
+    /// Appends a slice of entries to the log like `append_entry`, whatever the role of this
+    /// node, for tests building a log.
+    #[doc(hidden)]
+    pub fn append_entry_unchecked(&mut self, es: &mut [Entry]) {
+        self.append_to_log(es);
+    }
+
+    /// Appends a slice of entries to the log without checking the role, for the callers which
+    /// already know this node leads, or is becoming the leader.
+    fn append_to_log(&mut self, es: &mut [Entry]) {
         let mut li = self.raft_log.last_index();
         for (i, e) in es.iter_mut().enumerate() {
+            // Entries carrying an index out of order point at an integration bug, it would
+            // be overwritten silently otherwise.
+            debug_assert!(
+                e.get_index() == 0 || e.get_index() == li + 1 + i as u64,
+                "{} entry #{} has index {}, expected {}",
+                self.tag,
+                i,
+                e.get_index(),
+                li + 1 + i as u64
+            );
             e.set_term(self.term);
             e.set_index(li + 1 + i as u64);
         }
         // use latest "last" index after truncate/append
         li = self.raft_log.append(es);
+        self.appended.entries += es.len() as u64;
+        self.appended.bytes += es.iter().map(|e| e.encoded_len() as u64).sum::<u64>();
 
         let self_id = self.id;
-        self.mut_prs().get_mut(self_id).unwrap().maybe_update(li);
+        let durable_index = self.durable_index(li);
+        self.mut_prs()
+            .get_mut(self_id)
+            .unwrap()
+            .maybe_update(durable_index);
 
         // Regardless of maybe_commit's return, our caller will call bcastAppend.
         self.maybe_commit();
     }
 
+    /// Reports that the log is durably persisted up to `persisted_index`, whose entry has
+    /// `persisted_term`. With `Config::async_storage_writes`, the leader counts its own entries
+    /// towards the commit quorum only up to this index, and broadcasts the commit index if it
+    /// advanced.
+    ///
+    /// Indexes past the last index or below the one already reported are ignored, and so is
+    /// a report whose term doesn't match the log, as the entries persisted were overwritten
+    /// since.
+    pub fn advance_persisted(&mut self, persisted_index: u64, persisted_term: u64) {
+        if self.raft_log.term(persisted_index) != Ok(persisted_term) {
+            debug!(
+                "{} ignoring persisted [index: {}, term: {}], the log has moved on",
+                self.tag, persisted_index, persisted_term
+            );
+            return;
+        }
+        self.persisted_to(persisted_index);
+    }
+
+    /// Advances the persisted index to `persisted_index`, which the caller knows to be in the
+    /// log, see `advance_persisted`.
+    pub(crate) fn persisted_to(&mut self, persisted_index: u64) {
+        if persisted_index <= self.persisted || persisted_index > self.raft_log.last_index() {
+            return;
+        }
+        self.persisted = persisted_index;
+        if !self.async_storage_writes || self.state != StateRole::Leader {
+            return;
+        }
+        let self_id = self.id;
+        let updated = match self.mut_prs().get_mut(self_id) {
+            Some(pr) => pr.maybe_update(persisted_index),
+            None => false,
+        };
+        if updated && self.maybe_commit() {
+            self.bcast_append();
+        }
+    }
+
+    /// Returns the last index reported persisted by `advance_persisted`.
+    #[inline]
+    pub fn persisted(&self) -> u64 {
+        self.persisted
+    }
+
+    /// Whether entries are persisted asynchronously, see `Config::async_storage_writes`.
+    #[inline]
+    pub fn async_storage_writes(&self) -> bool {
+        self.async_storage_writes
+    }
+
+    /// Returns the part of the log up to `last_index` the leader may count as replicated to
+    /// itself, which excludes the entries not persisted yet with async storage writes.
+    fn durable_index(&self, last_index: u64) -> u64 {
+        if self.async_storage_writes {
+            cmp::min(last_index, self.persisted)
+        } else {
+            last_index
+        }
+    }
+
     /// Returns true to indicate that there will probably be some readiness need to be handled.
     pub fn tick(&mut self) -> bool {
         match self.state {
@@ -776,6 +1950,7 @@ impl<T: Storage> Raft<T> {
     /// Returns true to indicate that there will probably be some readiness need to be handled.
     pub fn tick_election(&mut self) -> bool {
         self.election_elapsed += 1;
+        self.delegated_lease_elapsed += 1;
         if !self.pass_election_timeout() || !self.promotable() {
             return false;
         }
@@ -789,6 +1964,7 @@ impl<T: Storage> Raft<T> {
     // tick_heartbeat is run by leaders to send a MsgBeat after self.heartbeat_timeout.
     // Returns true to indicate that there will probably be some readiness need to be handled.
     fn tick_heartbeat(&mut self) -> bool {
+        self.leader_ticks += 1;
         self.heartbeat_elapsed += 1;
         self.election_elapsed += 1;
 
@@ -819,11 +1995,49 @@ impl<T: Storage> Raft<T> {
     }
 
     /// Converts this node to a follower.
+    ///
+    /// If the node is already a follower at `term`, it only records `leader_id` and restarts
+    /// the election timer, which is the case for every message from the leader of the term.
     pub fn become_follower(&mut self, term: u64, leader_id: u64) {
+        if self.state == StateRole::Follower
+            && self.term == term
+            && leader_id != INVALID_ID
+            && (self.leader_id == leader_id || self.leader_id == INVALID_ID)
+        {
+            // There is nothing to reset within the term, and re-randomizing the election
+            // timeout would only perturb it.
+            self.election_elapsed = 0;
+            if self.leader_id == leader_id {
+                return;
+            }
+            self.leader_id = leader_id;
+            self.reset_pre_vote_backoff();
+            debug!(
+                "{} became follower of {} at term {}",
+                self.tag, leader_id, term
+            );
+            if let Some(ref hook) = self.become_follower_hook {
+                hook(term, leader_id);
+            }
+            self.forward_buffered_proposals();
+            return;
+        }
+        let was_leader = self.state == StateRole::Leader;
         self.reset(term);
         self.leader_id = leader_id;
         self.state = StateRole::Follower;
+        if leader_id != INVALID_ID {
+            self.reset_pre_vote_backoff();
+        }
+        if was_leader {
+            // Give the node which took over, e.g. the transfer target, a head start.
+            self.bias_election_timeout(true);
+        }
         info!("{} became follower at term {}", self.tag, self.term);
+        if let Some(ref hook) = self.become_follower_hook {
+            hook(term, leader_id);
+        }
+        self.forward_buffered_proposals();
     }
 
     // TODO: revoke pub when there is a better way to test.
@@ -885,6 +2099,10 @@ impl<T: Storage> Raft<T> {
         self.reset(term);
         self.leader_id = self.id;
         self.state = StateRole::Leader;
+        self.leader_ticks = 0;
+        self.leader_epoch = term;
+        self.failed_pre_votes = 0;
+        self.last_bcast_commit = self.raft_log.committed;
 
         // Followers enter replicate mode when they've been successfully probed
         // (perhaps after having received a snapshot as a result). The leader is
@@ -893,14 +2111,15 @@ impl<T: Storage> Raft<T> {
         let id = self.id;
         self.mut_prs().get_mut(id).unwrap().become_replicate();
 
-        // Conservatively set the pending_conf_index to the last index in the
-        // log. There may or may not be a pending config change, but it's
-        // safe to delay any future proposals until we commit all our
-        // pending log entries, and scanning the entire tail of the log
-        // could be expensive.
-        self.pending_conf_index = self.raft_log.last_index();
+        // Set the pending_conf_index to the latest config change which isn't applied yet.
+        // Assuming one at the last index instead would block config changes until the whole
+        // tail is applied, even if the proposed change was lost before it was replicated.
+        self.pending_conf_index = self.last_unapplied_conf_index();
 
-        self.append_entry(&mut [Entry::default()]);
+        // A single-node cluster is its own quorum, so `append_entry` commits the no-op before
+        // this returns and reads can be served right away, unless `async_storage_writes`
+        // defers the commit until the no-op is reported persisted.
+        self.append_to_log(&mut [Entry::default()]);
 
         // In most cases, we append only a new entry marked with an index and term.
         // In the specific case of a node recovering while in the middle of a membership change,
@@ -924,9 +2143,93 @@ impl<T: Storage> Raft<T> {
         }
 
         info!("{} became leader at term {}", self.tag, self.term);
+        self.forward_buffered_proposals();
         trace!("EXIT become_leader");
     }
 
+    /// Keeps a proposal received while no leader is known, if there is room left in the
+    /// buffer, see `Config::buffer_proposals_without_leader`. Nothing is kept if forwarding
+    /// proposals is disabled.
+    fn buffer_proposal(&mut self, m: Message) -> Result<()> {
+        if self.disable_proposal_forwarding
+            || self.buffered_proposals.len() >= self.buffer_proposals_without_leader
+        {
+            info!(
+                "{} no leader at term {}; dropping proposal",
+                self.tag, self.term
+            );
+            return Err(Error::ProposalDropped);
+        }
+        debug!(
+            "{} no leader at term {}; buffering proposal",
+            self.tag, self.term
+        );
+        self.buffered_proposals.push(m);
+        Ok(())
+    }
+
+    /// Steps the proposals buffered while no leader was known, now that there is one.
+    fn forward_buffered_proposals(&mut self) {
+        if self.leader_id == INVALID_ID || self.buffered_proposals.is_empty() {
+            return;
+        }
+        let proposals: Vec<Message> = self.buffered_proposals.drain(..).collect();
+        info!(
+            "{} stepping {} proposals buffered without a leader, leader: {}",
+            self.tag,
+            proposals.len(),
+            self.leader_id
+        );
+        for m in proposals {
+            if let Err(e) = self.step(m.clone()) {
+                info!("{} dropping buffered proposal: {:?}", self.tag, e);
+                if let Some(ref hook) = self.dropped_proposal_hook {
+                    hook(m);
+                }
+            }
+        }
+    }
+
+    /// Returns the index of the latest config change entry of the log which isn't applied
+    /// yet, or the applied index if there is none. Falls back to the last index if the
+    /// unapplied entries can't be read.
+    ///
+    /// The unapplied entries are scanned in chunks of at most `max_msg_size` bytes, so a long
+    /// unapplied tail isn't loaded at once.
+    fn last_unapplied_conf_index(&self) -> u64 {
+        let last_index = self.raft_log.last_index();
+        let mut conf_index = self.raft_log.applied;
+        let mut low = self.raft_log.applied + 1;
+        while low <= last_index {
+            let ents = match self.raft_log.slice(low, last_index + 1, self.max_msg_size) {
+                Ok(ents) => ents,
+                Err(e) => {
+                    warn!(
+                        "{} failed to scan unapplied entries [{}, {}] for config changes: {:?}",
+                        self.tag, low, last_index, e
+                    );
+                    return last_index;
+                }
+            };
+            if let Some(e) = ents
+                .iter()
+                .rev()
+                .find(|e| e.get_entry_type() == EntryType::EntryConfChange)
+            {
+                conf_index = e.get_index();
+            }
+            match ents.last() {
+                Some(e) => low = e.get_index() + 1,
+                None => break,
+            }
+        }
+        conf_index
+    }
+
+    /// Counts the configuration change entries in `ents`.
+    ///
+    /// Joint consensus transitions (`BeginMembershipChange` and `FinalizeMembershipChange`) are
+    /// carried by `EntryConfChange` entries just like simple changes, so they are counted too.
     fn num_pending_conf(&self, ents: &[Entry]) -> usize {
         ents.iter()
             .filter(|e| e.get_entry_type() == EntryType::EntryConfChange)
@@ -943,6 +2246,10 @@ impl<T: Storage> Raft<T> {
             (MessageType::MsgRequestPreVote, self.term + 1)
         } else {
             self.become_candidate();
+            if campaign_type == CAMPAIGN_TRANSFER {
+                // The transfer target retries first should this election fail.
+                self.bias_election_timeout(false);
+            }
             (MessageType::MsgRequestVote, self.term)
         };
         let self_id = self.id;
@@ -965,28 +2272,33 @@ impl<T: Storage> Raft<T> {
 
         // Only send vote request to voters.
         let prs = self.take_prs();
-        prs.voter_ids()
-            .iter()
-            .filter(|&id| *id != self_id)
-            .for_each(|&id| {
-                info!(
-                    "{} [logterm: {}, index: {}] sent {:?} request to {} at term {}",
-                    self.tag,
-                    self.raft_log.last_term(),
-                    self.raft_log.last_index(),
-                    vote_msg,
-                    id,
-                    self.term
-                );
-                let mut m = new_message(id, vote_msg, None);
-                m.set_term(term);
-                m.set_index(self.raft_log.last_index());
-                m.set_log_term(self.raft_log.last_term());
-                if campaign_type == CAMPAIGN_TRANSFER {
-                    m.set_context(campaign_type.to_vec());
-                }
-                self.send(m);
-            });
+        let mut voters: Vec<u64> = prs
+            .voter_ids()
+            .into_iter()
+            .filter(|&id| id != self_id)
+            .collect();
+        if self.deterministic_broadcast_order {
+            voters.sort();
+        }
+        voters.into_iter().for_each(|id| {
+            info!(
+                "{} [logterm: {}, index: {}] sent {:?} request to {} at term {}",
+                self.tag,
+                self.raft_log.last_term(),
+                self.raft_log.last_index(),
+                vote_msg,
+                id,
+                self.term
+            );
+            let mut m = new_message(id, vote_msg, None);
+            m.set_term(term);
+            m.set_index(self.raft_log.last_index());
+            m.set_log_term(self.raft_log.last_term());
+            if campaign_type == CAMPAIGN_TRANSFER {
+                m.set_context(campaign_type.to_vec());
+            }
+            self.send(m);
+        });
         self.set_prs(prs);
     }
 
@@ -995,13 +2307,60 @@ impl<T: Storage> Raft<T> {
         self.votes.entry(id).or_insert(vote);
     }
 
+    /// Returns the votes received in the ongoing election as `(granted, rejected, quorum)`,
+    /// counting only the voters of the current configuration.
+    ///
+    /// During a joint consensus transition the candidate needs a quorum of the next
+    /// configuration as well, see `next_vote_tally`.
+    pub fn current_vote_tally(&self) -> (usize, usize, usize) {
+        self.prs().configuration().vote_tally(&self.votes)
+    }
+
+    /// Returns the votes received in the ongoing election from the voters of the next
+    /// configuration as `(granted, rejected, quorum)`, or `None` if not in a joint consensus
+    /// transition.
+    pub fn next_vote_tally(&self) -> Option<(usize, usize, usize)> {
+        self.prs()
+            .next_configuration()
+            .as_ref()
+            .map(|next| next.vote_tally(&self.votes))
+    }
+
     /// Steps the raft along via a message. This should be called everytime your raft receives a
     /// message from a peer.
     pub fn step(&mut self, m: Message) -> Result<()> {
+        self.message_metrics.record_received(m.get_msg_type());
+        if self.strict_message_routing && m.get_to() != INVALID_ID && m.get_to() != self.id {
+            return Err(Error::WrongDestination(self.id, m.get_to()));
+        }
+        if m.get_from() == self.id && is_peer_msg(m.get_msg_type()) {
+            // Stepping our own message back, e.g. a self-acknowledged append, would corrupt
+            // the progress or the vote.
+            warn!(
+                "{} dropped a {:?} message from itself, is the transport echoing messages?",
+                self.tag,
+                m.get_msg_type()
+            );
+            return Ok(());
+        }
+
         // Handle the message term, which may result in our stepping down to a follower.
         if m.get_term() == 0 {
             // local message
         } else if m.get_term() > self.term {
+            if self.max_term_gap != 0 && m.get_term() - self.term > self.max_term_gap {
+                warn!(
+                    "{} [term: {}] ignored a {:?} message from {} with a suspicious term {}, \
+                     max term gap: {}",
+                    self.tag,
+                    self.term,
+                    m.get_msg_type(),
+                    m.get_from(),
+                    m.get_term(),
+                    self.max_term_gap
+                );
+                return Ok(());
+            }
             if m.get_msg_type() == MessageType::MsgRequestVote
                 || m.get_msg_type() == MessageType::MsgRequestPreVote
             {
@@ -1137,12 +2496,8 @@ impl<T: Storage> Raft<T> {
         match m.get_msg_type() {
             MessageType::MsgHup => {
                 if self.state != StateRole::Leader {
-                    let ents = self
-                        .raft_log
-                        .slice(self.raft_log.applied + 1, self.raft_log.committed + 1, None)
-                        .expect("unexpected error getting unapplied entries");
-                    let n = self.num_pending_conf(&ents);
-                    if n != 0 && self.raft_log.committed > self.raft_log.applied {
+                    let n = self.unapplied_conf_changes();
+                    if n != 0 {
                         warn!(
                             "{} cannot campaign at term {} since there are still {} pending \
                              configuration changes to apply",
@@ -1166,6 +2521,21 @@ impl<T: Storage> Raft<T> {
             MessageType::MsgRequestVote | MessageType::MsgRequestPreVote => {
                 debug_assert!(m.get_log_term() != 0, "{:?} log term can't be 0", m);
 
+                if self.prefer_lower_id_candidate && self.should_defer_to_candidate(&m) {
+                    // We stop competing with a lower id candidate whose log is as up-to-date
+                    // as ours, so it can win this term instead of both splitting the votes.
+                    // Only we counted our vote for ourselves, so it can be given again.
+                    info!(
+                        "{} [term: {}] defers to candidate {} with a lower id",
+                        self.tag,
+                        self.term,
+                        m.get_from()
+                    );
+                    let term = self.term;
+                    self.become_follower(term, INVALID_ID);
+                    self.vote = INVALID_ID;
+                }
+
                 // We can vote if this is a repeat of a vote we've already cast...
                 let can_vote = (self.vote == m.get_from()) ||
                     // ...we haven't voted and we don't think there's a leader yet in this term...
@@ -1173,7 +2543,10 @@ impl<T: Storage> Raft<T> {
                     // ...or this is a PreVote for a future term...
                     (m.msg_type == MessageType::MsgRequestPreVote as i32 && m.get_term() > self.term);
                 // ...and we believe the candidate is up to date.
-                if can_vote && self.raft_log.is_up_to_date(m.get_index(), m.get_log_term()) {
+                if can_vote
+                    && self.raft_log.is_up_to_date(m.get_index(), m.get_log_term())
+                    && !self.pre_candidate_lags(&m)
+                {
                     // When responding to Msg{Pre,}Vote messages we include the term
                     // from the message, not the local term. To see why consider the
                     // case where a single node was previously partitioned away and
@@ -1190,6 +2563,16 @@ impl<T: Storage> Raft<T> {
                     to_send.set_term(m.get_term());
                     self.send(to_send);
                     if m.get_msg_type() == MessageType::MsgRequestVote {
+                        // A node votes for at most one candidate per term, `can_vote` must
+                        // never let a vote be overwritten.
+                        debug_assert!(
+                            self.vote == INVALID_ID || self.vote == m.get_from(),
+                            "{} [term: {}] votes for {} after voting for {}",
+                            self.tag,
+                            self.term,
+                            m.get_from(),
+                            self.vote
+                        );
                         // Only record real votes.
                         self.election_elapsed = 0;
                         self.vote = m.get_from();
@@ -1203,6 +2586,19 @@ impl<T: Storage> Raft<T> {
                     self.send(to_send);
                 }
             }
+            MessageType::MsgBeat | MessageType::MsgCheckQuorum
+                if self.state != StateRole::Leader =>
+            {
+                // Only a leader generates these, a node may still see one queued before it
+                // stepped down.
+                debug!(
+                    "{} [term: {}, state: {:?}] ignoring {:?} since not leader",
+                    self.tag,
+                    self.term,
+                    self.state,
+                    m.get_msg_type()
+                );
+            }
             _ => match self.state {
                 StateRole::PreCandidate | StateRole::Candidate => self.step_candidate(m)?,
                 StateRole::Follower => self.step_follower(m)?,
@@ -1212,6 +2608,88 @@ impl<T: Storage> Raft<T> {
         Ok(())
     }
 
+    /// Proposes a batch of normal entries carrying `entries` on the leader, appending them at
+    /// once and broadcasting them in a single round, and returns the index assigned to each, in
+    /// order. It's cheaper than stepping a `MsgPropose` per entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NotLeader` if this node isn't the leader, since a forwarded proposal has
+    /// no known index, and `Error::ProposalDropped` if the leader can't take proposals, e.g.
+    /// during a leadership transfer. Either way no entry of the batch is appended.
+    pub fn propose_batch(&mut self, entries: Vec<Vec<u8>>) -> Result<Vec<u64>> {
+        if self.state != StateRole::Leader {
+            return Err(Error::NotLeader);
+        }
+        self.check_leader_accepts_proposals()?;
+        if entries.is_empty() {
+            return Ok(vec![]);
+        }
+        let first_index = self.raft_log.last_index() + 1;
+        let mut ents: Vec<Entry> = entries
+            .into_iter()
+            .map(|data| {
+                let mut e = Entry::default();
+                e.set_data(data);
+                e
+            })
+            .collect();
+        self.append_to_log(&mut ents);
+        self.bcast_append();
+        Ok((first_index..first_index + ents.len() as u64).collect())
+    }
+
+    /// Steps the raft along via a message like `step`, and takes the messages it generated,
+    /// including those of nested steps, e.g. the votes requested after a `MsgHup`. It suits
+    /// synchronous transports, which can then reply inline.
+    ///
+    /// Messages queued before are left in `msgs`. With `Config::batch_append`, entries may be
+    /// merged into an append queued before instead of generating a new message.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `step`, leaving any messages generated so far in `msgs`.
+    pub fn step_and_take(&mut self, m: Message) -> Result<Vec<Message>> {
+        let queued = self.msgs.len();
+        self.step(m)?;
+        Ok(self.msgs.split_off(queued))
+    }
+
+    /// Whether `m` requests a pre-vote for a candidate whose log lags further behind our
+    /// committed index than `prevote_max_lag`.
+    fn pre_candidate_lags(&self, m: &Message) -> bool {
+        if m.get_msg_type() != MessageType::MsgRequestPreVote || self.prevote_max_lag == 0 {
+            return false;
+        }
+        let committed = self.raft_log.committed;
+        if m.get_index().saturating_add(self.prevote_max_lag) >= committed {
+            return false;
+        }
+        info!(
+            "{} [committed: {}] refused a pre-vote to {} [index: {}] lagging more than {} \
+             entries",
+            self.tag,
+            committed,
+            m.get_from(),
+            m.get_index(),
+            self.prevote_max_lag
+        );
+        true
+    }
+
+    /// Whether a candidate should step down in favor of the candidate requesting a vote in `m`,
+    /// which breaks ties between candidates splitting the votes of a term, see
+    /// `Config::prefer_lower_id_candidate`.
+    fn should_defer_to_candidate(&self, m: &Message) -> bool {
+        self.state == StateRole::Candidate
+            && self.vote == self.id
+            && m.get_msg_type() == MessageType::MsgRequestVote
+            && m.get_term() == self.term
+            && m.get_from() < self.id
+            && m.get_log_term() == self.raft_log.last_term()
+            && m.get_index() == self.raft_log.last_index()
+    }
+
     /// Apply a `BeginMembershipChange` variant `ConfChange`.
     ///
     /// > **Note:** This is an experimental feature.
@@ -1250,8 +2728,18 @@ impl<T: Storage> Raft<T> {
         };
 
         self.set_pending_membership_change(conf_change.clone());
+        // The finalize entry may already be in the log, e.g. when the begin entry is applied
+        // again after a restart.
+        let start_index = conf_change.get_start_index();
+        let low = cmp::max(start_index + 1, self.raft_log.first_index());
+        let ents = self.raft_log.entries(low, None)?;
+        self.pending_finalize_index = Self::find_finalize_entry(&ents, start_index);
         let pr = Progress::new(self.raft_log.last_index() + 1, self.max_inflight);
         self.mut_prs().begin_membership_change(configuration, pr)?;
+        // Pending reads now need a quorum of the next configuration too, but its new voters
+        // never received the heartbeats carrying their contexts. Send them again so the reads
+        // don't wait for the next heartbeat timeout.
+        self.flush_read_index();
         Ok(())
     }
 
@@ -1304,6 +2792,9 @@ impl<T: Storage> Raft<T> {
         }
 
         self.mut_prs().finalize_membership_change()?;
+        if self.is_learner && self.prs().configuration().voters().contains(&self.id) {
+            self.is_learner = false;
+        }
         // Ensure we reset this on *any* node, since the leader might have failed
         // and we don't want to finalize twice.
         self.set_pending_membership_change(None);
@@ -1352,6 +2843,28 @@ impl<T: Storage> Raft<T> {
     ) {
         let pr = prs.get_mut(m.get_from()).unwrap();
         pr.recent_active = true;
+        pr.committed = cmp::max(pr.committed, m.get_commit());
+
+        // The follower reports its commit index in every response. If it already covers the
+        // pending snapshot, e.g. because the follower restored a snapshot sent by the previous
+        // leader, the snapshot is redundant and appends can resume right after the commit index.
+        if pr.state == ProgressState::Snapshot && pr.committed >= pr.pending_snapshot {
+            pr.matched = cmp::max(
+                pr.matched,
+                cmp::min(pr.committed, self.raft_log.last_index()),
+            );
+            let aborted = pr.pending_snapshot;
+            self.transition_progress(m.get_from(), pr, Progress::become_probe);
+            debug!(
+                "{} {} already committed the pending snapshot, resumed sending replication \
+                 messages [{:?}]",
+                self.tag,
+                m.get_from(),
+                pr
+            );
+            self.report_snapshot_abort(m.get_from(), aborted);
+            *send_append = true;
+        }
 
         if m.get_reject() {
             debug!(
@@ -1361,6 +2874,8 @@ impl<T: Storage> Raft<T> {
                 m.get_from(),
                 m.get_index()
             );
+            // Whatever is sent next is a retry, never a duplicate.
+            self.last_sent_appends.remove(&m.get_from());
 
             if pr.maybe_decr_to(m.get_index(), m.get_reject_hint()) {
                 debug!(
@@ -1370,7 +2885,7 @@ impl<T: Storage> Raft<T> {
                     pr
                 );
                 if pr.state == ProgressState::Replicate {
-                    pr.become_probe();
+                    self.transition_progress(m.get_from(), pr, Progress::become_probe);
                 }
                 *send_append = true;
             }
@@ -1396,7 +2911,9 @@ impl<T: Storage> Raft<T> {
         }
 
         match pr.state {
-            ProgressState::Probe => pr.become_replicate(),
+            ProgressState::Probe => {
+                self.transition_progress(m.get_from(), pr, Progress::become_replicate);
+            }
             ProgressState::Snapshot => {
                 if !pr.maybe_snapshot_abort() {
                     return;
@@ -1408,7 +2925,9 @@ impl<T: Storage> Raft<T> {
                     m.get_from(),
                     pr
                 );
-                pr.become_probe();
+                let aborted = pr.pending_snapshot;
+                self.transition_progress(m.get_from(), pr, Progress::become_probe);
+                self.report_snapshot_abort(m.get_from(), aborted);
             }
             ProgressState::Replicate => pr.ins.free_to(m.get_index()),
         }
@@ -1426,6 +2945,7 @@ impl<T: Storage> Raft<T> {
         {
             let pr = prs.get_mut(m.get_from()).unwrap();
             pr.recent_active = true;
+            pr.heartbeat_sent_at = None;
             pr.resume();
 
             // free one slot for the full inflights window to allow progress.
@@ -1441,27 +2961,29 @@ impl<T: Storage> Raft<T> {
             }
         }
 
-        if !prs.has_quorum(&self.read_only.recv_ack(m)) {
+        let acks = self.read_only.recv_ack(m);
+        let confirmed = if self.count_learners_in_read_quorum {
+            prs.has_quorum_counting_learners(&acks)
+        } else {
+            prs.has_quorum(&acks)
+        };
+        if !confirmed {
             return;
         }
 
         let rss = self.read_only.advance(m);
         for rs in rss {
             let mut req = rs.req;
-            if req.get_from() == INVALID_ID || req.get_from() == self.id {
+            if self.is_local_read(&req) {
                 // from local member
                 let rs = ReadState {
                     index: rs.index,
                     request_ctx: req.take_entries()[0].take_data(),
+                    seq: req.get_read_seq(),
                 };
-                self.read_states.push(rs);
+                self.push_read_state(rs);
             } else {
-                let mut to_send = Message::default();
-                to_send.set_to(req.get_from());
-                to_send.set_msg_type(MessageType::MsgReadIndexResp);
-                to_send.set_index(rs.index);
-                to_send.set_entries(req.take_entries());
-                more_to_send.push(to_send);
+                more_to_send.push(read_index_resp(req, rs.index));
             }
         }
     }
@@ -1521,8 +3043,10 @@ impl<T: Storage> Raft<T> {
 
     fn handle_snapshot_status(&mut self, m: &Message, pr: &mut Progress) {
         if m.get_reject() {
-            pr.snapshot_failure();
-            pr.become_probe();
+            self.transition_progress(m.get_from(), pr, |pr| {
+                pr.snapshot_failure();
+                pr.become_probe();
+            });
             debug!(
                 "{} snapshot failed, resumed sending replication messages to {} [{:?}]",
                 self.tag,
@@ -1530,7 +3054,7 @@ impl<T: Storage> Raft<T> {
                 pr
             );
         } else {
-            pr.become_probe();
+            self.transition_progress(m.get_from(), pr, Progress::become_probe);
             debug!(
                 "{} snapshot succeeded, resumed sending replication messages to {} [{:?}]",
                 self.tag,
@@ -1577,7 +3101,7 @@ impl<T: Storage> Raft<T> {
                 // During optimistic replication, if the remote becomes unreachable,
                 // there is huge probability that a MsgAppend is lost.
                 if pr.state == ProgressState::Replicate {
-                    pr.become_probe();
+                    self.transition_progress(m.get_from(), pr, Progress::become_probe);
                 }
                 debug!(
                     "{} failed to send message to {} because it is unreachable [{:?}]",
@@ -1594,12 +3118,30 @@ impl<T: Storage> Raft<T> {
         self.set_prs(prs);
     }
 
-    fn step_leader(&mut self, mut m: Message) -> Result<()> {
-        // These message types do not require any progress for m.From.
-        match m.get_msg_type() {
-            MessageType::MsgBeat => {
-                self.bcast_heartbeat();
-                return Ok(());
+    /// Checks whether the leader can take new proposals.
+    fn check_leader_accepts_proposals(&self) -> Result<()> {
+        if !self.prs().voter_ids().contains(&self.id) {
+            // If we are not currently a member of the range (i.e. this node
+            // was removed from the configuration while serving as leader),
+            // drop any new proposals.
+            return Err(Error::ProposalDropped);
+        }
+        if let Some(lead_transferee) = self.lead_transferee {
+            debug!(
+                "{} [term {}] transfer leadership to {} is in progress; dropping proposal",
+                self.tag, self.term, lead_transferee
+            );
+            return Err(Error::ProposalDropped);
+        }
+        Ok(())
+    }
+
+    fn step_leader(&mut self, mut m: Message) -> Result<()> {
+        // These message types do not require any progress for m.From.
+        match m.get_msg_type() {
+            MessageType::MsgBeat => {
+                self.bcast_heartbeat();
+                return Ok(());
             }
             MessageType::MsgCheckQuorum => {
                 if !self.check_quorum_active() {
@@ -1616,22 +3158,7 @@ impl<T: Storage> Raft<T> {
                 if m.get_entries().is_empty() {
                     panic!("{} stepped empty MsgProp", self.tag);
                 }
-                if !self.prs().voter_ids().contains(&self.id) {
-                    // If we are not currently a member of the range (i.e. this node
-                    // was removed from the configuration while serving as leader),
-                    // drop any new proposals.
-                    return Err(Error::ProposalDropped);
-                }
-                if self.lead_transferee.is_some() {
-                    debug!(
-                        "{} [term {}] transfer leadership to {} is in progress; dropping \
-                         proposal",
-                        self.tag,
-                        self.term,
-                        self.lead_transferee.unwrap()
-                    );
-                    return Err(Error::ProposalDropped);
-                }
+                self.check_leader_accepts_proposals()?;
 
                 for (i, e) in m.mut_entries().iter_mut().enumerate() {
                     if e.get_entry_type() == EntryType::EntryConfChange {
@@ -1648,7 +3175,7 @@ impl<T: Storage> Raft<T> {
                         }
                     }
                 }
-                self.append_entry(&mut m.mut_entries());
+                self.append_to_log(&mut m.mut_entries());
                 self.bcast_append();
                 return Ok(());
             }
@@ -1666,37 +3193,41 @@ impl<T: Storage> Raft<T> {
                     // We can express this in terms of the term and index instead of
                     // a user-supplied value.
                     // This would allow multiple reads to piggyback on the same message.
-                    match self.read_only.option {
-                        ReadOnlyOption::Safe => {
-                            let ctx = m.get_entries()[0].get_data().to_vec();
-                            self.read_only.add_request(self.raft_log.committed, m);
-                            self.bcast_heartbeat_with_ctx(Some(ctx));
-                        }
-                        ReadOnlyOption::LeaseBased => {
-                            let read_index = self.raft_log.committed;
-                            if m.get_from() == INVALID_ID || m.get_from() == self.id {
-                                // from local member
-                                let rs = ReadState {
-                                    index: read_index,
-                                    request_ctx: m.take_entries()[0].take_data(),
-                                };
-                                self.read_states.push(rs);
-                            } else {
-                                let mut to_send = Message::default();
-                                to_send.set_to(m.get_from());
-                                to_send.set_msg_type(MessageType::MsgReadIndexResp);
-                                to_send.set_index(read_index);
-                                to_send.set_entries(m.take_entries());
-                                self.send(to_send);
-                            }
+                    //
+                    // A request whose minimum index, carried in `index`, is already committed
+                    // tolerates the staleness of a lease read.
+                    let min_index_committed =
+                        m.get_index() != 0 && m.get_index() <= self.raft_log.committed;
+                    if self.read_only.option == ReadOnlyOption::LeaseBased
+                        || (min_index_committed && self.in_lease())
+                    {
+                        let read_index = self.raft_log.committed;
+                        if self.is_local_read(&m) {
+                            // from local member
+                            let rs = ReadState {
+                                index: read_index,
+                                request_ctx: m.take_entries()[0].take_data(),
+                                seq: m.get_read_seq(),
+                            };
+                            self.push_read_state(rs);
+                        } else {
+                            self.send(read_index_resp(m, read_index));
                         }
+                    } else {
+                        let ctx = m.get_entries()[0].get_data().to_vec();
+                        self.read_only.add_request(self.raft_log.committed, m);
+                        self.bcast_heartbeat_with_ctx(Some(ctx));
                     }
-                } else {
+                } else if self.is_local_read(&m) {
                     let rs = ReadState {
                         index: self.raft_log.committed,
                         request_ctx: m.take_entries()[0].take_data(),
+                        seq: m.get_read_seq(),
                     };
-                    self.read_states.push(rs);
+                    self.push_read_state(rs);
+                } else {
+                    let read_index = self.raft_log.committed;
+                    self.send(read_index_resp(m, read_index));
                 }
                 return Ok(());
             }
@@ -1716,7 +3247,7 @@ impl<T: Storage> Raft<T> {
         );
         if maybe_commit {
             if self.maybe_commit() {
-                if self.should_bcast_commit() {
+                if self.should_bcast_commit() && self.commit_advanced_enough() {
                     self.bcast_append();
                 }
             } else if old_paused {
@@ -1745,13 +3276,7 @@ impl<T: Storage> Raft<T> {
     // whether they respond to MsgRequestVote or MsgRequestPreVote.
     fn step_candidate(&mut self, m: Message) -> Result<()> {
         match m.get_msg_type() {
-            MessageType::MsgPropose => {
-                info!(
-                    "{} no leader at term {}; dropping proposal",
-                    self.tag, self.term
-                );
-                return Err(Error::ProposalDropped);
-            }
+            MessageType::MsgPropose => return self.buffer_proposal(m),
             MessageType::MsgAppend => {
                 debug_assert_eq!(self.term, m.get_term());
                 self.become_follower(m.get_term(), m.get_from());
@@ -1791,6 +3316,17 @@ impl<T: Storage> Raft<T> {
                     self.term
                 );
                 self.register_vote(from_id, acceptance);
+                let (granted, rejected, quorum) = self.current_vote_tally();
+                info!(
+                    "{} [term {}] has {} granted and {} rejected votes of {} needed, next \
+                     configuration: {:?}",
+                    self.tag,
+                    self.term,
+                    granted,
+                    rejected,
+                    quorum,
+                    self.next_vote_tally()
+                );
                 match self.prs().candidacy_status(&self.votes) {
                     CandidacyStatus::Elected => {
                         if self.state == StateRole::PreCandidate {
@@ -1804,11 +3340,18 @@ impl<T: Storage> Raft<T> {
                         // pb.MsgPreVoteResp contains future term of pre-candidate
                         // m.term > self.term; reuse self.term
                         let term = self.term;
+                        let pre_vote_failed = self.state == StateRole::PreCandidate;
                         self.become_follower(term, INVALID_ID);
+                        if pre_vote_failed {
+                            self.back_off_pre_vote();
+                        }
                     }
                     CandidacyStatus::Eligible => (),
                 };
             }
+            // Already campaigning, so another election would only bump the term again. A
+            // retransmit of the `MsgTimeoutNow` which started the campaign carries the previous
+            // term and is dropped by `step` before getting here.
             MessageType::MsgTimeoutNow => debug!(
                 "{} [term {} state {:?}] ignored MsgTimeoutNow from {}",
                 self.tag,
@@ -1825,9 +3368,12 @@ impl<T: Storage> Raft<T> {
         match m.get_msg_type() {
             MessageType::MsgPropose => {
                 if self.leader_id == INVALID_ID {
+                    return self.buffer_proposal(m);
+                }
+                if self.disable_proposal_forwarding {
                     info!(
-                        "{} no leader at term {}; dropping proposal",
-                        self.tag, self.term
+                        "{} not forwarding to leader {} at term {}; dropping proposal",
+                        self.tag, self.leader_id, self.term
                     );
                     return Err(Error::ProposalDropped);
                 }
@@ -1835,18 +3381,17 @@ impl<T: Storage> Raft<T> {
                 self.send(m);
             }
             MessageType::MsgAppend => {
-                self.election_elapsed = 0;
-                self.leader_id = m.get_from();
+                self.become_follower(self.term, m.get_from());
                 self.handle_append_entries(&m);
             }
             MessageType::MsgHeartbeat => {
-                self.election_elapsed = 0;
-                self.leader_id = m.get_from();
+                self.become_follower(self.term, m.get_from());
+                self.delegated_lease_ticks = m.get_lease_ticks() as usize;
+                self.delegated_lease_elapsed = 0;
                 self.handle_heartbeat(m);
             }
             MessageType::MsgSnapshot => {
-                self.election_elapsed = 0;
-                self.leader_id = m.get_from();
+                self.become_follower(self.term, m.get_from());
                 self.handle_snapshot(m);
             }
             MessageType::MsgTransferLeader => {
@@ -1861,7 +3406,17 @@ impl<T: Storage> Raft<T> {
                 self.send(m);
             }
             MessageType::MsgTimeoutNow => {
-                if self.promotable() {
+                let lag = m.get_commit().saturating_sub(self.raft_log.last_index());
+                if self.receiving_snapshot.is_some() || lag > self.timeout_now_max_lag {
+                    info!(
+                        "{} [last index {}] ignored MsgTimeoutNow from {} with commit {} as it \
+                         can't win an election",
+                        self.tag,
+                        self.raft_log.last_index(),
+                        m.get_from(),
+                        m.get_commit()
+                    );
+                } else if self.promotable() {
                     info!(
                         "{} [term {}] received MsgTimeoutNow from {} and starts an election to \
                          get leadership.",
@@ -1882,6 +3437,8 @@ impl<T: Storage> Raft<T> {
                 }
             }
             MessageType::MsgReadIndex => {
+                // Followers, learners included, never serve reads locally regardless of the
+                // read only option; the leader confirms the read index for them.
                 if self.leader_id == INVALID_ID {
                     info!(
                         "{} no leader at term {}; dropping index reading msg",
@@ -1889,6 +3446,13 @@ impl<T: Storage> Raft<T> {
                     );
                     return Ok(());
                 }
+                if self.disable_proposal_forwarding {
+                    info!(
+                        "{} not forwarding to leader {} at term {}; dropping index reading msg",
+                        self.tag, self.leader_id, self.term
+                    );
+                    return Err(Error::ProposalDropped);
+                }
                 m.set_to(self.leader_id);
                 self.send(m);
             }
@@ -1902,11 +3466,21 @@ impl<T: Storage> Raft<T> {
                     );
                     return Ok(());
                 }
+                if !m.get_return_address().is_empty() {
+                    // The read was forwarded for a non-member, relay the answer to it. Its id
+                    // was lost when forwarding, the transport routes on the return address.
+                    // `send` sets the term again.
+                    m.set_to(INVALID_ID);
+                    m.set_term(0);
+                    self.send(m);
+                    return Ok(());
+                }
                 let rs = ReadState {
                     index: m.get_index(),
                     request_ctx: m.take_entries()[0].take_data(),
+                    seq: m.get_read_seq(),
                 };
-                self.read_states.push(rs);
+                self.push_read_state(rs);
             }
             _ => {}
         }
@@ -1922,22 +3496,65 @@ impl<T: Storage> Raft<T> {
             to_send.set_to(m.get_from());
             to_send.set_msg_type(MessageType::MsgAppendResponse);
             to_send.set_index(self.raft_log.committed);
+            to_send.set_commit(self.raft_log.committed);
             self.send(to_send);
             return;
         }
         debug_assert!(m.get_log_term() != 0, "{:?} log term can't be 0", m);
 
+        let decompressed;
+        let ents = if m.get_entries().iter().any(|e| e.get_compressed()) {
+            match self.decompress_entries(m.get_entries()) {
+                Ok(ents) => {
+                    decompressed = ents;
+                    &decompressed[..]
+                }
+                Err(e) => {
+                    error!(
+                        "{} dropped msgApp from {} with entries which can't be decompressed: {}",
+                        self.tag,
+                        m.get_from(),
+                        e
+                    );
+                    return;
+                }
+            }
+        } else {
+            m.get_entries()
+        };
+
         let mut to_send = Message::default();
         to_send.set_to(m.get_from());
         to_send.set_msg_type(MessageType::MsgAppendResponse);
-        match self.raft_log.maybe_append(
-            m.get_index(),
-            m.get_log_term(),
-            m.get_commit(),
-            m.get_entries(),
-        ) {
-            Some(mlast_index) => {
+        let old_commit = self.raft_log.committed;
+        match self
+            .raft_log
+            .maybe_append(m.get_index(), m.get_log_term(), m.get_commit(), ents)
+        {
+            Some((overwritten, mlast_index)) => {
+                if let Some(overwritten) = overwritten {
+                    self.append_conflicts += 1;
+                    let conflict_idx = overwritten.start;
+                    self.persisted = cmp::min(self.persisted, conflict_idx - 1);
+                    if self
+                        .pending_finalize_index
+                        .map_or(false, |i| i >= conflict_idx)
+                    {
+                        self.pending_finalize_index = None;
+                    }
+                    if self.record_overwritten_entries {
+                        self.overwritten_entries.push(overwritten);
+                    }
+                }
+                self.report_commit(old_commit);
+                if let Some(start_index) = self.began_membership_change_at() {
+                    if self.pending_finalize_index.is_none() {
+                        self.pending_finalize_index = Self::find_finalize_entry(ents, start_index);
+                    }
+                }
                 to_send.set_index(mlast_index);
+                // Lets the leader track the commit index of each follower.
+                to_send.set_commit(self.raft_log.committed);
                 self.send(to_send);
             }
             None => {
@@ -1954,6 +3571,7 @@ impl<T: Storage> Raft<T> {
                 to_send.set_index(m.get_index());
                 to_send.set_reject(true);
                 to_send.set_reject_hint(self.raft_log.last_index());
+                to_send.set_commit(self.raft_log.committed);
                 self.send(to_send);
             }
         }
@@ -1962,7 +3580,9 @@ impl<T: Storage> Raft<T> {
     // TODO: revoke pub when there is a better way to test.
     /// For a message, commit and send out heartbeat.
     pub fn handle_heartbeat(&mut self, mut m: Message) {
+        let old_commit = self.raft_log.committed;
         self.raft_log.commit_to(m.get_commit());
+        self.report_commit(old_commit);
         let mut to_send = Message::default();
         to_send.set_to(m.get_from());
         to_send.set_msg_type(MessageType::MsgHeartbeatResponse);
@@ -1972,11 +3592,21 @@ impl<T: Storage> Raft<T> {
 
     fn handle_snapshot(&mut self, mut m: Message) {
         debug_assert!(m.get_term() != 0, "{:?} term can't be 0", m);
+        let chunked = m.get_snapshot_chunk_more() || m.get_snapshot_chunk_offset() != 0;
+        if chunked {
+            if !self.handle_snapshot_chunk(&mut m) {
+                return;
+            }
+        } else {
+            // A whole snapshot supersedes any partially received one.
+            self.abort_snapshot_chunks();
+        }
         let (sindex, sterm) = (
             m.get_snapshot().get_metadata().get_index(),
             m.get_snapshot().get_metadata().get_term(),
         );
-        if self.restore(m.take_snapshot()) {
+        let res = self.restore_with_reason(m.take_snapshot());
+        if res == RestoreResult::Restored {
             info!(
                 "{} [commit: {}, term: {}] restored snapshot [index: {}, term: {}]",
                 self.tag, self.term, self.raft_log.committed, sindex, sterm
@@ -1987,9 +3617,12 @@ impl<T: Storage> Raft<T> {
             to_send.set_index(self.raft_log.last_index());
             self.send(to_send);
         } else {
+            if chunked {
+                self.mut_store().discard_snapshot_chunks();
+            }
             info!(
-                "{} [commit: {}] ignored snapshot [index: {}, term: {}]",
-                self.tag, self.raft_log.committed, sindex, sterm
+                "{} [commit: {}] ignored snapshot [index: {}, term: {}]: {:?}",
+                self.tag, self.raft_log.committed, sindex, sterm, res
             );
             let mut to_send = Message::default();
             to_send.set_to(m.get_from());
@@ -1999,7 +3632,97 @@ impl<T: Storage> Raft<T> {
         }
     }
 
-    fn restore_raft(&mut self, snap: &Snapshot) -> Option<bool> {
+    /// Routes a chunk of a chunked `MsgSnapshot` to the storage.
+    ///
+    /// Returns true once the final chunk has been applied, at which point the snapshot carried
+    /// by `m` can be restored.
+    fn handle_snapshot_chunk(&mut self, m: &mut Message) -> bool {
+        let offset = m.get_snapshot_chunk_offset();
+        let more = m.get_snapshot_chunk_more();
+        let meta = m.get_snapshot().get_metadata().clone();
+        let expected_offset = match self.receiving_snapshot {
+            Some((ref receiving, next_offset))
+                if receiving.get_index() == meta.get_index()
+                    && receiving.get_term() == meta.get_term() =>
+            {
+                next_offset
+            }
+            _ => 0,
+        };
+        if offset != expected_offset {
+            warn!(
+                "{} received chunk at offset {} of snapshot [index: {}, term: {}] from {}, \
+                 expected offset {}; discarding the partially received snapshot",
+                self.tag,
+                offset,
+                meta.get_index(),
+                meta.get_term(),
+                m.get_from(),
+                expected_offset
+            );
+            self.abort_snapshot_chunks();
+            return false;
+        }
+        if offset == 0 {
+            // The first chunk of a new snapshot, drop any other partially received one.
+            self.abort_snapshot_chunks();
+            if meta.get_index() < self.raft_log.committed {
+                info!(
+                    "{} [commit: {}] ignored chunked snapshot [index: {}, term: {}]",
+                    self.tag,
+                    self.raft_log.committed,
+                    meta.get_index(),
+                    meta.get_term()
+                );
+                let mut to_send = Message::default();
+                to_send.set_to(m.get_from());
+                to_send.set_msg_type(MessageType::MsgAppendResponse);
+                to_send.set_index(self.raft_log.committed);
+                self.send(to_send);
+                return false;
+            }
+        }
+
+        let data = m.mut_snapshot().take_data();
+        if let Err(e) = self
+            .mut_store()
+            .apply_snapshot_chunk(&meta, offset, &data, !more)
+        {
+            error!(
+                "{} failed to apply chunk at offset {} of snapshot [index: {}, term: {}]: {:?}",
+                self.tag,
+                offset,
+                meta.get_index(),
+                meta.get_term(),
+                e
+            );
+            self.receiving_snapshot = None;
+            self.mut_store().discard_snapshot_chunks();
+            return false;
+        }
+        if more {
+            self.receiving_snapshot = Some((meta, offset + data.len() as u64));
+            return false;
+        }
+        self.receiving_snapshot = None;
+        true
+    }
+
+    /// Discards a partially received chunked snapshot, if any.
+    fn abort_snapshot_chunks(&mut self) {
+        if let Some((meta, offset)) = self.receiving_snapshot.take() {
+            info!(
+                "{} aborted receiving snapshot [index: {}, term: {}] at offset {}",
+                self.tag,
+                meta.get_index(),
+                meta.get_term(),
+                offset
+            );
+            self.mut_store().discard_snapshot_chunks();
+        }
+    }
+
+    fn restore_raft(&mut self, snap: &Snapshot) -> Option<RestoreResult> {
         let meta = snap.get_metadata();
         if self.raft_log.match_term(meta.get_index(), meta.get_term()) {
             info!(
@@ -2013,7 +3736,7 @@ impl<T: Storage> Raft<T> {
                 meta.get_term()
             );
             self.raft_log.commit_to(meta.get_index());
-            return Some(false);
+            return Some(RestoreResult::FastForwarded);
         }
 
         // Both of learners and voters are empty means the peer is created by ConfChange.
@@ -2026,7 +3749,7 @@ impl<T: Storage> Raft<T> {
                         meta.get_index(),
                         meta.get_term(),
                     );
-                    return Some(false);
+                    return Some(RestoreResult::RejectedLearnerDemotion);
                 }
             }
         }
@@ -2057,21 +3780,122 @@ impl<T: Storage> Raft<T> {
             conf_change.set_start_index(meta.get_pending_membership_change_index());
             self.pending_membership_change = Some(conf_change);
         }
+        // The log restarts at the snapshot, a finalize entry can only arrive with later appends.
+        self.pending_finalize_index = None;
         None
     }
 
     /// Recovers the state machine from a snapshot. It restores the log and the
     /// configuration of state machine.
+    ///
+    /// Returns true only if the snapshot was restored, use `restore_with_reason` to find out
+    /// why it wasn't.
     pub fn restore(&mut self, snap: Snapshot) -> bool {
-        if snap.get_metadata().get_index() < self.raft_log.committed {
-            return false;
+        self.restore_with_reason(snap) == RestoreResult::Restored
+    }
+
+    /// Like `restore`, but returns what was done with the snapshot.
+    pub fn restore_with_reason(&mut self, snap: Snapshot) -> RestoreResult {
+        let meta = snap.get_metadata();
+        if meta.get_index() < self.raft_log.committed {
+            return RestoreResult::StaleIgnored;
+        }
+        if meta.get_index() == self.raft_log.committed
+            && !self.raft_log.match_term(meta.get_index(), meta.get_term())
+        {
+            // Committed entries never change, so a leader can't have sent this snapshot unless
+            // the logs diverged, which breaks the log matching property.
+            error!(
+                "{} [commit: {}, term of commit: {:?}] rejected snapshot [index: {}, term: {}] \
+                 conflicting with the committed log",
+                self.tag,
+                self.raft_log.committed,
+                self.raft_log.term(self.raft_log.committed),
+                meta.get_index(),
+                meta.get_term()
+            );
+            return RestoreResult::RejectedCommittedConflict;
         }
-        if let Some(b) = self.restore_raft(&snap) {
-            return b;
+        if let Some(res) = self.restore_raft(&snap) {
+            return res;
         }
 
+        // The snapshot replaces the log past the committed index until it's persisted.
+        self.persisted = cmp::min(self.persisted, self.raft_log.committed);
         self.raft_log.restore(snap);
-        true
+        RestoreResult::Restored
+    }
+
+    /// Creates a snapshot at `index` with the given `conf_state` and state machine `data`, and
+    /// hands it to `Storage::save_snapshot` to persist.
+    ///
+    /// The term is read from the log, and a pending membership change which began at or before
+    /// `index` is recorded in the metadata.
+    ///
+    /// # Errors
+    ///
+    /// * `index` is greater than the applied index.
+    /// * The term of `index` can't be fetched, e.g. it has been compacted.
+    /// * The storage fails to persist the snapshot.
+    pub fn create_snapshot(
+        &mut self,
+        index: u64,
+        conf_state: ConfState,
+        data: Vec<u8>,
+    ) -> Result<Snapshot> {
+        let mut meta = self.snapshot_metadata(index)?;
+        meta.set_conf_state(conf_state);
+        let term = meta.get_term();
+
+        let mut snapshot = Snapshot::default();
+        snapshot.set_data(data);
+        snapshot.set_metadata(meta);
+
+        self.mut_store().save_snapshot(&snapshot)?;
+        info!(
+            "{} created snapshot [index: {}, term: {}]",
+            self.tag, index, term
+        );
+        Ok(snapshot)
+    }
+
+    /// Builds the metadata of a snapshot at `index`, with its term read from the log, the
+    /// membership currently in effect, see `applied_conf_state`, and the pending membership
+    /// change if it began at or before `index`, so that restoring the snapshot resumes it.
+    ///
+    /// # Errors
+    ///
+    /// * `index` is greater than the applied index.
+    /// * The term of `index` can't be fetched, e.g. it has been compacted.
+    pub fn snapshot_metadata(&self, index: u64) -> Result<SnapshotMetadata> {
+        if index > self.raft_log.applied {
+            return Err(Error::ViolatesContract(format!(
+                "snapshot index {} > applied index {}",
+                index, self.raft_log.applied
+            )));
+        }
+        let mut meta = SnapshotMetadata::default();
+        meta.set_index(index);
+        meta.set_term(self.raft_log.term(index)?);
+        meta.set_conf_state(self.applied_conf_state());
+        if let Some(ref change) = self.pending_membership_change {
+            if change.get_start_index() <= index {
+                meta.set_pending_membership_change(change.get_configuration().clone());
+                meta.set_pending_membership_change_index(change.get_start_index());
+            }
+        }
+        Ok(meta)
+    }
+
+    /// Returns the membership currently in effect as a `ConfState`, suitable for passing to
+    /// `create_snapshot`.
+    ///
+    /// During a joint consensus transition this is the configuration being left, since the
+    /// `next` configuration is not in effect until the change is finalized. `create_snapshot`
+    /// records the pending change in the snapshot metadata, so restoring the snapshot resumes
+    /// the transition.
+    pub fn applied_conf_state(&self) -> ConfState {
+        self.prs().configuration().clone().into()
     }
 
     /// Check if there is any pending confchange.
@@ -2087,10 +3911,46 @@ impl<T: Storage> Raft<T> {
         !self.skip_bcast_commit || self.has_pending_conf()
     }
 
+    /// Whether the commit index advanced enough since it was last broadcast to be worth
+    /// broadcasting again, see `Config::min_commit_bcast_delta`.
+    fn commit_advanced_enough(&self) -> bool {
+        self.has_pending_conf()
+            || self
+                .raft_log
+                .committed
+                .saturating_sub(self.last_bcast_commit)
+                >= self.min_commit_bcast_delta
+    }
+
     /// Indicates whether state machine can be promoted to leader,
     /// which is true when its own id is in progress list.
+    ///
+    /// During a joint membership change this is the case if the node is a voter of either
+    /// configuration, see `is_voting_member`.
     pub fn promotable(&self) -> bool {
-        self.prs().voter_ids().contains(&self.id)
+        self.is_voting_member()
+    }
+
+    /// Returns whether the node is a voter of the current configuration and of the incoming
+    /// configuration of a joint membership change, as `(current, incoming)`. The incoming one
+    /// is `false` outside of a membership change.
+    pub fn voter_in_configurations(&self) -> (bool, bool) {
+        let prs = self.prs();
+        let current = prs.configuration().voters().contains(&self.id);
+        let incoming = match *prs.next_configuration() {
+            Some(ref next) => next.voters().contains(&self.id),
+            None => false,
+        };
+        (current, incoming)
+    }
+
+    /// Returns whether the vote of this node counts in the current election quorum. During a
+    /// joint membership change an election needs a quorum of both the outgoing and the
+    /// incoming configuration, so a voter of only one of them still votes, e.g. a voter being
+    /// removed or one being added.
+    pub fn is_voting_member(&self) -> bool {
+        let (current, incoming) = self.voter_in_configurations();
+        current || incoming
     }
 
     /// Propose that the peer group change its active set to a new set.
@@ -2239,6 +4099,21 @@ impl<T: Storage> Raft<T> {
         Ok(())
     }
 
+    /// Removes a learner from the raft, like `remove_node` but refusing to remove a voter,
+    /// which would shrink the quorum when a learner was meant.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::NotLearner` if `id` is a voter.
+    /// * `id` is not a learner.
+    /// * There is a pending membership change. (See `is_in_membership_change()`)
+    pub fn remove_learner(&mut self, id: u64) -> Result<()> {
+        if self.prs().voter_ids().contains(&id) {
+            return Err(Error::NotLearner(id));
+        }
+        self.remove_node(id)
+    }
+
     /// Updates the progress of the learner or voter.
     pub fn set_progress(&mut self, id: u64, matched: u64, next_idx: u64, is_learner: bool) {
         let mut p = Progress::new(next_idx, self.max_inflight);
@@ -2252,6 +4127,141 @@ impl<T: Storage> Raft<T> {
         }
     }
 
+    /// Starts transferring the leadership to `target`. The target is first caught up if its
+    /// log lags behind, and the transfer is aborted if it doesn't finish within an election
+    /// timeout.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::NotLeader` if this node isn't the leader.
+    /// * `Error::TransferTargetIsLearner` if `target` is a learner.
+    /// * `Error::TransferTargetNotVoter` if `target` isn't in the group.
+    /// * `Error::ViolatesContract` if `target` is this node.
+    pub fn transfer_leader(&mut self, target: u64) -> Result<()> {
+        if self.state != StateRole::Leader {
+            return Err(Error::NotLeader);
+        }
+        if self.prs().learner_ids().contains(&target) {
+            return Err(Error::TransferTargetIsLearner(target));
+        }
+        if !self.prs().voter_ids().contains(&target) {
+            return Err(Error::TransferTargetNotVoter(target));
+        }
+        if target == self.id {
+            return Err(Error::ViolatesContract(
+                "the leadership can't be transferred to the leader itself".into(),
+            ));
+        }
+        let mut m = Message::default();
+        m.set_msg_type(MessageType::MsgTransferLeader);
+        m.set_from(target);
+        self.step(m)
+    }
+
+    /// Reports whether the snapshot sent to peer `id` was delivered, which resumes or retries
+    /// replication to it. Only a leader has snapshots in flight; reports for unknown peers are
+    /// logged and ignored.
+    pub fn report_snapshot(&mut self, id: u64, success: bool) {
+        if self.prs().get(id).is_none() {
+            warn!(
+                "{} ignored the snapshot status of unknown peer {}",
+                self.tag, id
+            );
+            return;
+        }
+        let mut m = Message::default();
+        m.set_msg_type(MessageType::MsgSnapStatus);
+        m.set_from(id);
+        m.set_reject(!success);
+        // Reporting to a non-leader is a no-op, so the result doesn't matter.
+        let _ = self.step(m);
+    }
+
+    /// Sends the current snapshot to peer `id` and forces its progress into the snapshot state
+    /// with `snapshot_index` pending, which pauses replication to it, keeping the rest of its
+    /// progress.
+    ///
+    /// If no snapshot can be sent right now, e.g. the peer isn't recently active or the
+    /// snapshot is temporarily unavailable, the progress is left as is, so the regular
+    /// replication keeps going.
+    ///
+    /// This is an escape hatch for manual recovery. Replication resumes once the peer reports
+    /// the snapshot status, see `report_snapshot`, or acknowledges an index at or above
+    /// `snapshot_index`.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::NotLeader` if this node isn't the leader, only the leader replicates.
+    /// * `Error::ViolatesContract` if `id` is this node.
+    /// * `Error::NotExists` if `id` has no progress.
+    pub fn set_peer_snapshot_pending(&mut self, id: u64, snapshot_index: u64) -> Result<()> {
+        if self.state != StateRole::Leader {
+            return Err(Error::NotLeader);
+        }
+        if id == self.id {
+            return Err(Error::ViolatesContract(format!(
+                "the leader {} can't wait for a snapshot itself",
+                id
+            )));
+        }
+        if self.prs().get(id).is_none() {
+            return Err(Error::NotExists(id, "progress"));
+        }
+        let mut prs = self.take_prs();
+        let mut m = Message::default();
+        m.set_to(id);
+        let sent = {
+            let pr = prs.get_mut(id).unwrap();
+            let sent = self.prepare_send_snapshot(&mut m, pr, id);
+            if sent {
+                // Wait for the requested index rather than the one of the snapshot sent.
+                self.transition_progress(id, pr, |pr| pr.become_snapshot(snapshot_index));
+                pr.pause();
+            }
+            sent
+        };
+        self.set_prs(prs);
+        if !sent {
+            info!(
+                "{} couldn't send a snapshot to {}, leaving its progress as is",
+                self.tag, id
+            );
+            return Ok(());
+        }
+        info!(
+            "{} forced progress of {} to snapshot pending at index {}",
+            self.tag, id, snapshot_index
+        );
+        self.send(m);
+        Ok(())
+    }
+
+    /// Sets the max number of in-flight append messages to peer `id`, overriding
+    /// `Config::max_inflight_msgs` for it, e.g. to allow a larger window on a high latency link.
+    ///
+    /// The messages currently in flight are kept. If there are more than `max`, no more are
+    /// sent until enough of them are acknowledged.
+    ///
+    /// # Errors
+    ///
+    /// * `id` has no progress.
+    /// * `max` is 0.
+    pub fn set_peer_max_inflight(&mut self, id: u64, max: usize) -> Result<()> {
+        if max == 0 {
+            return Err(Error::ConfigInvalid(
+                "max inflight messages must be greater than 0".to_owned(),
+            ));
+        }
+        let tag = self.tag.clone();
+        let pr = self
+            .mut_prs()
+            .get_mut(id)
+            .ok_or_else(|| Error::NotExists(id, "progress"))?;
+        pr.ins.set_cap(max);
+        info!("{} set max inflight messages of {} to {}", tag, id, max);
+        Ok(())
+    }
+
     /// Takes the progress set (destructively turns to `None`).
     pub fn take_prs(&mut self) -> ProgressSet {
         self.prs.take().unwrap()
@@ -2272,6 +4282,172 @@ impl<T: Storage> Raft<T> {
         self.prs.as_mut().unwrap()
     }
 
+    /// Checks the core invariants of the state machine, returning a description of the first
+    /// one which doesn't hold.
+    #[cfg(feature = "testexport")]
+    #[doc(hidden)]
+    pub fn check_invariants(&self) -> Result<()> {
+        let violated = |desc: String| Err(Error::InvariantViolated(desc));
+        let (applied, committed) = (self.raft_log.applied, self.raft_log.committed);
+        let last_index = self.raft_log.last_index();
+        if applied > committed || committed > last_index {
+            return violated(format!(
+                "applied {} <= committed {} <= last index {} doesn't hold",
+                applied, committed, last_index
+            ));
+        }
+        // The persisted hard state may lag behind, but never commits more than the log.
+        let persisted_commit = self.raft_log.store.initial_state()?.hard_state.get_commit();
+        if persisted_commit > committed {
+            return violated(format!(
+                "persisted hard state commit {} > committed {}",
+                persisted_commit, committed
+            ));
+        }
+        match self.state {
+            StateRole::Leader | StateRole::Candidate if self.vote != self.id => {
+                return violated(format!(
+                    "{:?} at term {} voted for {}",
+                    self.state, self.term, self.vote
+                ));
+            }
+            StateRole::Leader if self.leader_id != self.id => {
+                return violated(format!("leader believes {} is the leader", self.leader_id));
+            }
+            StateRole::Leader => {
+                // With async storage writes the leader only counts what it persisted.
+                let durable_index = self.durable_index(last_index);
+                if let Some(pr) = self.prs().get(self.id) {
+                    if pr.matched != durable_index {
+                        return violated(format!(
+                            "leader matched {} != durable index {}, last index {}",
+                            pr.matched, durable_index, last_index
+                        ));
+                    }
+                }
+            }
+            _ => (),
+        }
+        if let Some(ref change) = self.pending_membership_change {
+            if change.get_start_index() != self.pending_conf_index {
+                return violated(format!(
+                    "pending membership change started at {} != pending conf index {}",
+                    change.get_start_index(),
+                    self.pending_conf_index
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the in-memory state into a `RaftDebugState`.
+    #[cfg(feature = "testexport")]
+    #[doc(hidden)]
+    pub fn debug_state(&self) -> RaftDebugState {
+        let mut progress: Vec<_> = self
+            .prs()
+            .iter()
+            .map(|(&id, pr)| (id, pr.clone()))
+            .collect();
+        progress.sort_by_key(|&(id, _)| id);
+        let mut votes: Vec<_> = self.votes.iter().map(|(&id, &v)| (id, v)).collect();
+        votes.sort();
+        RaftDebugState {
+            id: self.id,
+            term: self.term,
+            vote: self.vote,
+            state: self.state,
+            leader_id: self.leader_id,
+            lead_transferee: self.lead_transferee,
+            committed: self.raft_log.committed,
+            applied: self.raft_log.applied,
+            last_index: self.raft_log.last_index(),
+            last_term: self.raft_log.last_term(),
+            pending_conf_index: self.pending_conf_index,
+            election_elapsed: self.election_elapsed,
+            heartbeat_elapsed: self.heartbeat_elapsed,
+            randomized_election_timeout: self.randomized_election_timeout,
+            progress,
+            votes,
+            pending_reads: self.read_only.read_index_queue.iter().cloned().collect(),
+        }
+    }
+
+    /// Validates the state recovered from storage, typically right after a restart.
+    ///
+    /// A committed index behind the storage's snapshot is moved up to the snapshot, as
+    /// everything in a snapshot is committed by definition. Any other inconsistency between
+    /// the committed index, the applied index and the entries in storage is reported.
+    ///
+    /// # Errors
+    ///
+    /// * The applied index is greater than the committed index.
+    /// * The committed index is beyond the last persisted entry.
+    /// * Entries between the applied index and the snapshot have been compacted.
+    /// * The committed but unapplied entries are not contiguous.
+    /// * The storage fails to return its indices or entries.
+    pub fn reconcile_persisted_state(&mut self) -> Result<()> {
+        let first_index = self.get_store().first_index()?;
+        let last_index = self.get_store().last_index()?;
+        let snapshot_index = first_index - 1;
+
+        if self.raft_log.committed < snapshot_index {
+            warn!(
+                "{} committed index {} is behind the snapshot at {}, moving it forward",
+                self.tag, self.raft_log.committed, snapshot_index
+            );
+            self.raft_log.committed = snapshot_index;
+        }
+        let (committed, applied) = (self.raft_log.committed, self.raft_log.applied);
+        if applied > committed {
+            return Err(Error::PersistedStateMismatch(format!(
+                "applied index {} > committed index {}",
+                applied, committed
+            )));
+        }
+        if committed > last_index {
+            return Err(Error::PersistedStateMismatch(format!(
+                "committed index {} > last persisted index {}",
+                committed, last_index
+            )));
+        }
+        if applied < snapshot_index {
+            return Err(Error::PersistedStateMismatch(format!(
+                "entries ({}, {}] are neither applied nor available, the snapshot must be \
+                 applied first",
+                applied, snapshot_index
+            )));
+        }
+
+        if committed == applied {
+            return Ok(());
+        }
+        let ents = self.get_store().entries(applied + 1, committed + 1, None)?;
+        // The term of a compacted entry may be unknown, it's only used as a lower bound.
+        let mut prev_term = self.get_store().term(applied).unwrap_or(0);
+        for (expected, e) in (applied + 1..).zip(&ents) {
+            if e.get_index() != expected || e.get_term() < prev_term {
+                return Err(Error::PersistedStateMismatch(format!(
+                    "entry [index: {}, term: {}] doesn't follow [index: {}, term: {}]",
+                    e.get_index(),
+                    e.get_term(),
+                    expected - 1,
+                    prev_term
+                )));
+            }
+            prev_term = e.get_term();
+        }
+        if ents.len() as u64 != committed - applied {
+            return Err(Error::PersistedStateMismatch(format!(
+                "expected {} committed entries after {}, found {}",
+                committed - applied,
+                applied,
+                ents.len()
+            )));
+        }
+        Ok(())
+    }
+
     // TODO: revoke pub when there is a better way to test.
     /// For a given hardstate, load the state into self.
     pub fn load_state(&mut self, hs: &HardState) {
@@ -2300,8 +4476,18 @@ impl<T: Storage> Raft<T> {
     /// Regenerates and stores the election timeout.
     pub fn reset_randomized_election_timeout(&mut self) {
         let prev_timeout = self.randomized_election_timeout;
-        let timeout =
-            rand::thread_rng().gen_range(self.min_election_timeout, self.max_election_timeout);
+        let (min, max) = (self.min_election_timeout, self.max_election_timeout);
+        let timeout = match self.election_timeout_strategy {
+            Some(ref strategy) => {
+                // A strategy poisoned by a panic in another raft sharing it is still usable.
+                let timeout = strategy
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .next_election_timeout(min, max, self.id);
+                cmp::min(cmp::max(timeout, min), max - 1)
+            }
+            None => rand::thread_rng().gen_range(min, max),
+        };
         debug!(
             "{} reset election timeout {} -> {} at {}",
             self.tag, prev_timeout, timeout, self.election_elapsed
@@ -2309,6 +4495,55 @@ impl<T: Storage> Raft<T> {
         self.randomized_election_timeout = timeout;
     }
 
+    /// Raises or lowers the freshly randomized election timeout by
+    /// `transfer_election_bias_ticks`, keeping it at least one tick.
+    fn bias_election_timeout(&mut self, upward: bool) {
+        if self.transfer_election_bias_ticks == 0 {
+            return;
+        }
+        let base = self.randomized_election_timeout;
+        let timeout = if upward {
+            base.saturating_add(self.transfer_election_bias_ticks)
+        } else {
+            cmp::max(1, base.saturating_sub(self.transfer_election_bias_ticks))
+        };
+        debug!(
+            "{} biases election timeout {} -> {} around leader transfer",
+            self.tag, base, timeout
+        );
+        self.randomized_election_timeout = timeout;
+    }
+
+    /// Extends the freshly randomized election timeout exponentially with the number of
+    /// consecutive failed pre-vote rounds, up to `prevote_backoff_cap_ticks`.
+    fn back_off_pre_vote(&mut self) {
+        if self.prevote_backoff_cap_ticks == 0 {
+            return;
+        }
+        self.failed_pre_votes = self.failed_pre_votes.saturating_add(1);
+        let factor = 1usize
+            .checked_shl(self.failed_pre_votes)
+            .unwrap_or(usize::max_value());
+        let base = self.randomized_election_timeout;
+        let timeout = cmp::max(
+            base,
+            cmp::min(base.saturating_mul(factor), self.prevote_backoff_cap_ticks),
+        );
+        debug!(
+            "{} backs off election timeout {} -> {} after {} failed pre-votes",
+            self.tag, base, timeout, self.failed_pre_votes
+        );
+        self.randomized_election_timeout = timeout;
+    }
+
+    /// Clears the pre-vote backoff once a leader is heard from.
+    fn reset_pre_vote_backoff(&mut self) {
+        if self.failed_pre_votes != 0 {
+            self.failed_pre_votes = 0;
+            self.reset_randomized_election_timeout();
+        }
+    }
+
     // check_quorum_active returns true if the quorum is active from
     // the view of the local raft state machine. Otherwise, it returns
     // false.
@@ -2321,7 +4556,9 @@ impl<T: Storage> Raft<T> {
 
     /// Issues a message to timeout immediately.
     pub fn send_timeout_now(&mut self, to: u64) {
-        let msg = new_message(to, MessageType::MsgTimeoutNow, None);
+        let mut msg = new_message(to, MessageType::MsgTimeoutNow, None);
+        // Lets the target refuse to campaign if it lags too far behind.
+        msg.set_commit(self.raft_log.committed);
         self.send(msg);
     }
 
@@ -26,6 +26,7 @@
 // limitations under the License.
 
 use std::cmp;
+use std::ops::Range;
 
 use crate::eraftpb::{Entry, Snapshot};
 
@@ -203,7 +204,8 @@ impl<T: Storage> RaftLog<T> {
     }
 
     /// Returns None if the entries cannot be appended. Otherwise,
-    /// it returns Some(last index of new entries).
+    /// it returns Some((range of the entries overwritten, last index of new entries)), the
+    /// range being None if no existing entry conflicted.
     ///
     /// # Panics
     ///
@@ -214,10 +216,11 @@ impl<T: Storage> RaftLog<T> {
         term: u64,
         committed: u64,
         ents: &[Entry],
-    ) -> Option<u64> {
+    ) -> Option<(Option<Range<u64>>, u64)> {
         let last_new_index = idx + ents.len() as u64;
         if self.match_term(idx, term) {
             let conflict_idx = self.find_conflict(ents);
+            let mut overwritten = None;
             if conflict_idx == 0 {
             } else if conflict_idx <= self.committed {
                 panic!(
@@ -225,11 +228,16 @@ impl<T: Storage> RaftLog<T> {
                     self.tag, conflict_idx, self.committed
                 )
             } else {
+                // The entries from the conflict onwards are truncated by the append.
+                let last_index = self.last_index();
+                if conflict_idx <= last_index {
+                    overwritten = Some(conflict_idx..last_index + 1);
+                }
                 let offset = idx + 1;
                 self.append(&ents[(conflict_idx - offset) as usize..]);
             }
             self.commit_to(cmp::min(committed, last_new_index));
-            return Some(last_new_index);
+            return Some((overwritten, last_new_index));
         }
         None
     }
@@ -281,8 +289,17 @@ impl<T: Storage> RaftLog<T> {
     }
 
     /// Attempts to set the stable up to a given index.
-    pub fn stable_to(&mut self, idx: u64, term: u64) {
-        self.unstable.stable_to(idx, term)
+    ///
+    /// The entries becoming stable are handed to `Storage::append` first. If it fails, the
+    /// error is returned and the entries are kept unstable.
+    pub fn stable_to(&mut self, idx: u64, term: u64) -> Result<()> {
+        let offset = self.unstable.offset;
+        if idx >= offset && self.unstable.maybe_term(idx) == Some(term) {
+            let ents = &self.unstable.entries[..(idx + 1 - offset) as usize];
+            self.store.append(ents)?;
+        }
+        self.unstable.stable_to(idx, term);
+        Ok(())
     }
 
     /// Snaps the unstable up to a current index.
@@ -356,6 +373,9 @@ impl<T: Storage> RaftLog<T> {
     /// later term is more up-to-date. If the logs end with the same term, then
     /// whichever log has the larger last_index is more up-to-date. If the logs are
     /// the same, the given log is up-to-date.
+    ///
+    /// The last index and term are read through the unstable entries, so a conflicting suffix
+    /// truncated by an append but still in the storage is never compared against.
     pub fn is_up_to_date(&self, last_index: u64, term: u64) -> bool {
         term > self.last_term() || (term == self.last_term() && last_index >= self.last_index())
     }
@@ -850,7 +870,7 @@ mod test {
                 .expect("");
             let mut raft_log = new_raft_log(store);
             raft_log.append(new_ents);
-            raft_log.stable_to(stablei, stablet);
+            raft_log.stable_to(stablei, stablet).expect("");
             if raft_log.unstable.offset != wunstable {
                 panic!(
                     "#{}: unstable = {}, want {}",
@@ -868,7 +888,7 @@ mod test {
             let store = MemStorage::new();
             let mut raft_log = new_raft_log(store);
             raft_log.append(&[new_entry(1, 1), new_entry(2, 2)]);
-            raft_log.stable_to(stablei, stablet);
+            raft_log.stable_to(stablei, stablet).expect("");
             if raft_log.unstable.offset != wunstable {
                 panic!(
                     "#{}: unstable = {}, want {}",
@@ -901,7 +921,9 @@ mod test {
             let ents = raft_log.unstable_entries().unwrap_or(&[]).to_vec();
             let l = ents.len();
             if l > 0 {
-                raft_log.stable_to(ents[l - 1].get_index(), ents[l - i].get_term());
+                raft_log
+                    .stable_to(ents[l - 1].get_index(), ents[l - i].get_term())
+                    .expect("");
             }
             if &ents != wents {
                 panic!("#{}: unstableEnts = {:?}, want {:?}", i, ents, wents);
@@ -1262,7 +1284,7 @@ mod test {
             if res.is_err() {
                 continue;
             }
-            let glasti = res.unwrap();
+            let glasti = res.unwrap().map(|(_, last)| last);
             let gcommitted = raft_log.committed;
             if glasti != wlasti {
                 panic!("#{}: lastindex = {:?}, want {:?}", i, glasti, wlasti);
@@ -1284,6 +1306,40 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_log_maybe_append_overwritten() {
+        setup_for_test();
+        let previous_ents = vec![new_entry(1, 1), new_entry(2, 2), new_entry(3, 3)];
+        let tests = vec![
+            // appending new entries overwrites nothing
+            (3, 3, vec![new_entry(4, 4)], None),
+            // existing entries aren't overwritten
+            (1, 1, vec![new_entry(2, 2)], None),
+            (
+                1,
+                1,
+                vec![new_entry(2, 2), new_entry(3, 3), new_entry(4, 4)],
+                None,
+            ),
+            // the tail from the conflict onwards is overwritten
+            (2, 2, vec![new_entry(3, 4)], Some(3..4)),
+            (1, 1, vec![new_entry(2, 4)], Some(2..4)),
+            (1, 1, vec![new_entry(2, 2), new_entry(3, 4)], Some(3..4)),
+        ];
+        for (i, (index, log_term, ents, woverwritten)) in tests.into_iter().enumerate() {
+            let store = MemStorage::new();
+            let mut raft_log = new_raft_log(store);
+            raft_log.append(&previous_ents);
+            let (overwritten, _) = raft_log.maybe_append(index, log_term, 0, &ents).unwrap();
+            if overwritten != woverwritten {
+                panic!(
+                    "#{}: overwritten = {:?}, want {:?}",
+                    i, overwritten, woverwritten
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_commit_to() {
         setup_for_test();
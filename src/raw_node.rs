@@ -231,7 +231,7 @@ impl<T: Storage> RawNode<T> {
         Ok(rn)
     }
 
-    fn commit_ready(&mut self, rd: Ready) {
+    fn commit_ready(&mut self, rd: Ready) -> Result<()> {
         if rd.ss.is_some() {
             self.prev_ss = rd.ss.unwrap();
         }
@@ -240,22 +240,31 @@ impl<T: Storage> RawNode<T> {
                 self.prev_hs = e;
             }
         }
+        // With async storage writes, the application reports persistence separately through
+        // `on_persist_ready`.
+        let persisted = !self.raft.async_storage_writes();
         if !rd.entries.is_empty() {
             let e = rd.entries.last().unwrap();
-            self.raft.raft_log.stable_to(e.get_index(), e.get_term());
+            self.raft.raft_log.stable_to(e.get_index(), e.get_term())?;
+            if persisted {
+                self.raft.persisted_to(e.get_index());
+            }
         }
         if rd.snapshot != Snapshot::default() {
-            self.raft
-                .raft_log
-                .stable_snap_to(rd.snapshot.get_metadata().get_index());
+            let index = rd.snapshot.get_metadata().get_index();
+            self.raft.raft_log.stable_snap_to(index);
+            if persisted {
+                self.raft.persisted_to(index);
+            }
         }
         if !rd.read_states.is_empty() {
             self.raft.read_states.clear();
         }
+        Ok(())
     }
 
-    fn commit_apply(&mut self, applied: u64) {
-        self.raft.commit_apply(applied);
+    fn commit_apply(&mut self, applied: u64) -> Result<()> {
+        self.raft.commit_apply(applied)
     }
 
     /// Tick advances the internal logical clock by a single tick.
@@ -402,8 +411,13 @@ impl<T: Storage> RawNode<T> {
 
     /// Advance notifies the RawNode that the application has applied and saved progress in the
     /// last Ready results.
-    pub fn advance(&mut self, rd: Ready) {
-        self.advance_append(rd);
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `Storage::append` for the entries of the Ready, see
+    /// `advance_append`.
+    pub fn advance(&mut self, rd: Ready) -> Result<()> {
+        self.advance_append(rd)?;
         let commit_idx = self.prev_hs.get_commit();
         if commit_idx != 0 {
             // In most cases, prevHardSt and rd.HardState will be the same
@@ -415,20 +429,41 @@ impl<T: Storage> RawNode<T> {
             // incorporated into the snapshot, even if rd.CommittedEntries is
             // empty). Therefore we mark all committed entries as applied
             // whether they were included in rd.HardState or not.
-            self.advance_apply(commit_idx);
+            if let Err(e) = self.advance_apply(commit_idx) {
+                panic!("{}", e);
+            }
         }
+        Ok(())
     }
 
     /// Appends and commits the ready value.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `Storage::append` for the entries of the Ready, which are kept
+    /// unstable then.
+    #[inline]
+    pub fn advance_append(&mut self, rd: Ready) -> Result<()> {
+        self.commit_ready(rd)
+    }
+
+    /// Reports that the entries and snapshot handed out by `Ready` are durably persisted up to
+    /// `index`, whose entry has `term`, with `Config::async_storage_writes`. Without it,
+    /// `advance` already treats them as persisted. See `Raft::advance_persisted`.
     #[inline]
-    pub fn advance_append(&mut self, rd: Ready) {
-        self.commit_ready(rd);
+    pub fn on_persist_ready(&mut self, index: u64, term: u64) {
+        self.raft.advance_persisted(index, term);
     }
 
     /// Advance apply to the passed index.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::AppliedOutOfRange` if `applied` isn't between the applied and the
+    /// committed index, see `Raft::commit_apply`.
     #[inline]
-    pub fn advance_apply(&mut self, applied: u64) {
-        self.commit_apply(applied);
+    pub fn advance_apply(&mut self, applied: u64) -> Result<()> {
+        self.commit_apply(applied)
     }
 
     /// Status returns the current status of the given group.
@@ -437,6 +472,11 @@ impl<T: Storage> RawNode<T> {
         Status::new(&self.raft)
     }
 
+    /// Ping broadcasts heartbeats to all the followers. Nothing happens if it's not the leader.
+    pub fn ping(&mut self) {
+        self.raft.ping()
+    }
+
     /// ReportUnreachable reports the given node is not reachable for the last send.
     pub fn report_unreachable(&mut self, id: u64) {
         let mut m = Message::default();
@@ -448,13 +488,8 @@ impl<T: Storage> RawNode<T> {
 
     /// ReportSnapshot reports the status of the sent snapshot.
     pub fn report_snapshot(&mut self, id: u64, status: SnapshotStatus) {
-        let rej = status == SnapshotStatus::Failure;
-        let mut m = Message::default();
-        m.set_msg_type(MessageType::MsgSnapStatus);
-        m.set_from(id);
-        m.set_reject(rej);
-        // we don't care if it is ok actually
-        let _ = self.raft.step(m);
+        self.raft
+            .report_snapshot(id, status == SnapshotStatus::Finish);
     }
 
     /// TransferLeader tries to transfer leadership to the given transferee.
@@ -470,8 +505,25 @@ impl<T: Storage> RawNode<T> {
     /// index, any linearizable read requests issued before the read request can be
     /// processed safely. The read state will have the same rctx attached.
     pub fn read_index(&mut self, rctx: Vec<u8>) {
+        self.read_index_with_seq(rctx, 0);
+    }
+
+    /// Same as `read_index`, but the read state will also carry `seq`, so the application can
+    /// correlate it with the request without relying on `rctx`.
+    pub fn read_index_with_seq(&mut self, rctx: Vec<u8>, seq: u64) {
+        self.read_index_at_least(rctx, seq, 0);
+    }
+
+    /// Same as `read_index_with_seq`, but any read index of at least `min_index` is acceptable,
+    /// e.g. the index of the client's last write for read-your-writes consistency.
+    ///
+    /// If the leader is in lease and has already committed `min_index`, it serves the read
+    /// without a heartbeat round. 0 requires a linearizable read.
+    pub fn read_index_at_least(&mut self, rctx: Vec<u8>, seq: u64, min_index: u64) {
         let mut m = Message::default();
         m.set_msg_type(MessageType::MsgReadIndex);
+        m.set_read_seq(seq);
+        m.set_index(min_index);
         let mut e = Entry::default();
         e.set_data(rctx);
         m.set_entries(vec![e]);
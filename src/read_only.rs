@@ -62,6 +62,8 @@ pub struct ReadState {
     pub index: u64,
     /// A datagram consisting of context about the request.
     pub request_ctx: Vec<u8>,
+    /// The sequence number given when requesting the read, 0 if none was given.
+    pub seq: u64,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -92,7 +94,8 @@ impl ReadOnly {
     /// `index` is the commit index of the raft state machine when it received
     /// the read only request.
     ///
-    /// `m` is the original read only request message from the local or remote node.
+    /// `m` is the original read only request message from the local or remote node. Its
+    /// `read_seq` is kept along with it and echoed back in the resulting `ReadState`.
     pub fn add_request(&mut self, index: u64, m: Message) {
         let ctx = {
             let key = m.get_entries()[0].get_data();
@@ -151,6 +154,11 @@ impl ReadOnly {
         self.read_index_queue.back().cloned()
     }
 
+    /// Returns the contexts of the pending read only requests, oldest first.
+    pub fn pending_contexts(&self) -> Vec<Vec<u8>> {
+        self.read_index_queue.iter().cloned().collect()
+    }
+
     #[inline]
     pub fn pending_read_count(&self) -> usize {
         self.read_index_queue.len()
@@ -25,10 +25,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::eraftpb::HardState;
+use crate::eraftpb::{HardState, MessageType};
 use hashbrown::HashMap;
 
-use crate::progress::Progress;
+use crate::progress::{Progress, ProgressState};
 use crate::raft::{Raft, SoftState, StateRole};
 use crate::storage::Storage;
 
@@ -47,6 +47,91 @@ pub struct Status {
     pub progress: HashMap<u64, Progress>,
     /// The progress of learners in catching up and applying logs.
     pub learner_progress: HashMap<u64, Progress>,
+    /// The entries this node appended to its log as a leader since it started.
+    pub appended: LogGrowth,
+    /// The number of ticks elapsed since the election timer was last reset.
+    pub election_elapsed: usize,
+    /// The number of ticks left before the node starts an election, see
+    /// `Raft::ticks_until_election`.
+    pub ticks_until_election: usize,
+    /// The peers which haven't answered a heartbeat in time, see `Raft::unresponsive_peers`.
+    pub unresponsive_peers: Vec<u64>,
+}
+
+/// A copy of the replication progress of a peer as tracked by the leader, see
+/// `Raft::peer_progress`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ProgressSnapshot {
+    /// The highest log index known to be replicated to the peer.
+    pub matched: u64,
+    /// The index of the next entry to send to the peer.
+    pub next_idx: u64,
+    /// The replication state of the peer.
+    pub state: ProgressState,
+    /// Whether the peer was heard from recently.
+    pub recent_active: bool,
+    /// Whether the peer is a learner.
+    pub is_learner: bool,
+    /// The number of append messages sent to the peer and not acknowledged yet.
+    pub inflight_count: usize,
+}
+
+impl ProgressSnapshot {
+    pub(crate) fn new(pr: &Progress, is_learner: bool) -> ProgressSnapshot {
+        ProgressSnapshot {
+            matched: pr.matched,
+            next_idx: pr.next_idx,
+            state: pr.state,
+            recent_active: pr.recent_active,
+            is_learner,
+            inflight_count: pr.ins.count(),
+        }
+    }
+}
+
+/// The growth of the log, counted in entries and their encoded bytes.
+#[derive(Default, Debug, PartialEq, Clone, Copy)]
+pub struct LogGrowth {
+    /// The number of appended entries.
+    pub entries: u64,
+    /// The encoded size of the appended entries.
+    pub bytes: u64,
+}
+
+/// The number of message types, which index the counters of `MessageMetrics`.
+const MESSAGE_TYPES: usize = MessageType::MsgRequestPreVoteResponse as usize + 1;
+
+/// The number of messages of each type sent and received by a raft, see
+/// `Raft::message_metrics`.
+#[derive(Default, Debug, PartialEq, Clone, Copy)]
+pub struct MessageMetrics {
+    sent: [u64; MESSAGE_TYPES],
+    received: [u64; MESSAGE_TYPES],
+}
+
+impl MessageMetrics {
+    /// Returns the number of messages of type `t` sent to peers.
+    #[inline]
+    pub fn sent(&self, t: MessageType) -> u64 {
+        self.sent[t as usize]
+    }
+
+    /// Returns the number of messages of type `t` stepped, including local messages such as
+    /// `MsgHup`.
+    #[inline]
+    pub fn received(&self, t: MessageType) -> u64 {
+        self.received[t as usize]
+    }
+
+    #[inline]
+    pub(crate) fn record_sent(&mut self, t: MessageType) {
+        self.sent[t as usize] += 1;
+    }
+
+    #[inline]
+    pub(crate) fn record_received(&mut self, t: MessageType) {
+        self.received[t as usize] += 1;
+    }
 }
 
 impl Status {
@@ -59,6 +144,10 @@ impl Status {
         s.hs = raft.hard_state();
         s.ss = raft.soft_state();
         s.applied = raft.raft_log.get_applied();
+        s.appended = raft.appended();
+        s.election_elapsed = raft.election_elapsed_ticks();
+        s.ticks_until_election = raft.ticks_until_election();
+        s.unresponsive_peers = raft.unresponsive_peers();
         if s.ss.raft_state == StateRole::Leader {
             s.progress = raft.prs().voters().map(|(&k, v)| (k, v.clone())).collect();
             s.learner_progress = raft
@@ -69,4 +158,15 @@ impl Status {
         }
         s
     }
+
+    /// Returns a copy of the progress of the given peer, `None` if the status wasn't taken on
+    /// the leader or the peer is unknown.
+    pub fn peer_progress(&self, id: u64) -> Option<ProgressSnapshot> {
+        if let Some(pr) = self.progress.get(&id) {
+            return Some(ProgressSnapshot::new(pr, false));
+        }
+        self.learner_progress
+            .get(&id)
+            .map(|pr| ProgressSnapshot::new(pr, true))
+    }
 }
@@ -125,6 +125,50 @@ pub trait Storage {
     /// so raft state machine could know that Storage needs some time to prepare
     /// snapshot and call snapshot later.
     fn snapshot(&self) -> Result<Snapshot>;
+
+    /// Persists a snapshot created by `Raft::create_snapshot`, so it can be returned by later
+    /// calls to `snapshot`. Entries covered by the snapshot may be compacted afterwards.
+    ///
+    /// The default implementation does nothing, for storages which build snapshots on demand.
+    fn save_snapshot(&mut self, _snapshot: &Snapshot) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called from `RaftLog::stable_to`, e.g. through `RawNode::advance`, with the unstable
+    /// entries that just became stable. By then the application has already persisted them and
+    /// sent the messages of the `Ready`, so this is a notification rather than a durability
+    /// point. An error is returned to the caller, and the entries are kept unstable.
+    ///
+    /// The default implementation does nothing, for storages like `MemStorage` into which the
+    /// application appends the entries itself.
+    fn append(&mut self, _entries: &[Entry]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Applies a chunk of the data of an incoming snapshot described by `meta`, starting at
+    /// `offset` bytes into the whole snapshot data. `last` is true for the final chunk.
+    ///
+    /// This allows large snapshots to be streamed into the state machine without buffering
+    /// them entirely. The applied data must be kept staged until the snapshot is handed out by
+    /// `Ready::snapshot`, as the transfer may still be aborted (see `discard_snapshot_chunks`)
+    /// or the snapshot rejected by raft after the final chunk.
+    ///
+    /// The default implementation ignores the chunks.
+    fn apply_snapshot_chunk(
+        &mut self,
+        _meta: &SnapshotMetadata,
+        _offset: u64,
+        _data: &[u8],
+        _last: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Discards all the chunks applied by `apply_snapshot_chunk` for a snapshot which has not
+    /// been restored. Called when a chunked transfer is aborted mid-stream.
+    ///
+    /// The default implementation does nothing.
+    fn discard_snapshot_chunks(&mut self) {}
 }
 
 /// The Memory Storage Core instance holds the actual state of the storage struct. To access this
@@ -135,6 +179,8 @@ pub struct MemStorageCore {
     entries: Vec<Entry>,
     // Metadata of the last snapshot received.
     snapshot_metadata: SnapshotMetadata,
+    // The last snapshot created by the application, see `Storage::save_snapshot`.
+    saved_snapshot: Option<Snapshot>,
 }
 
 impl Default for MemStorageCore {
@@ -144,6 +190,7 @@ impl Default for MemStorageCore {
             entries: vec![],
             // Every time a snapshot is applied to the storage, the metadata will be stored here.
             snapshot_metadata: Default::default(),
+            saved_snapshot: None,
         }
     }
 }
@@ -231,6 +278,8 @@ impl MemStorageCore {
         }
 
         self.snapshot_metadata = meta.clone();
+        // A snapshot saved before is older than the storage now.
+        self.saved_snapshot = None;
 
         self.raft_state.hard_state.set_term(term);
         self.raft_state.hard_state.set_commit(index);
@@ -247,7 +296,30 @@ impl MemStorageCore {
         Ok(())
     }
 
+    /// Keeps a snapshot created by the application, which is returned by `snapshot` from then
+    /// on, data included, until a newer one is saved or applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns `StorageError::SnapshotOutOfDate` if the snapshot is older than the one saved.
+    pub fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let index = snapshot.get_metadata().get_index();
+        if index < self.snapshot_metadata.get_index()
+            || self
+                .saved_snapshot
+                .as_ref()
+                .map_or(false, |s| index < s.get_metadata().get_index())
+        {
+            return Err(Error::Store(StorageError::SnapshotOutOfDate));
+        }
+        self.saved_snapshot = Some(snapshot.clone());
+        Ok(())
+    }
+
     fn snapshot(&self) -> Snapshot {
+        if let Some(ref snapshot) = self.saved_snapshot {
+            return snapshot.clone();
+        }
         let mut snapshot = Snapshot::default();
 
         // Use the latest applied_idx to construct the snapshot.
@@ -355,7 +427,7 @@ impl MemStorageCore {
 /// contains raft logs. So you can call `MemStorage::append` to persist new received unstable raft
 /// logs and then access them with `Storage` APIs. The only exception is `Storage::snapshot`. There
 /// is no data in `Snapshot` returned by `MemStorage::snapshot` because applied data is not stored
-/// in `MemStorage`.
+/// in `MemStorage`, unless the application saved a snapshot through `Storage::save_snapshot`.
 #[derive(Clone, Default)]
 pub struct MemStorage {
     core: Arc<RwLock<MemStorageCore>>,
@@ -482,6 +554,11 @@ impl Storage for MemStorage {
         let core = self.rl();
         Ok(core.snapshot())
     }
+
+    /// Implements the Storage trait.
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.wl().save_snapshot(snapshot)
+    }
 }
 
 #[cfg(test)]
@@ -23,7 +23,8 @@ use raft::{
         ConfChange, ConfChangeType, ConfState, Entry, EntryType, Message, MessageType, Snapshot,
     },
     storage::MemStorage,
-    Config, Configuration, Raft, Result, INVALID_ID,
+    Config, Configuration, MembershipChangeStatus, ProgressState, Raft, Result, StateRole,
+    INVALID_ID,
 };
 
 use crate::test_util::new_message;
@@ -53,6 +54,312 @@ mod api {
         Ok(())
     }
 
+    // Test that a cluster can be bootstrapped from an explicit peer list on empty storage.
+    #[test]
+    fn new_with_peers() -> Result<()> {
+        setup_for_test();
+        let config = Config {
+            id: 1,
+            tag: "1".into(),
+            ..Default::default()
+        };
+        let raft = Raft::new_with_peers(&config, MemStorage::new(), &[1, 2, 3], &[4])?;
+        assert_eq!(
+            raft.prs().configuration(),
+            &Configuration::new(vec![1, 2, 3], vec![4])
+        );
+
+        // Restarting with the same membership is fine.
+        let store = MemStorage::new_with_conf_state((vec![1, 2, 3], vec![4]));
+        assert!(Raft::new_with_peers(&config, store, &[3, 2, 1], &[4]).is_ok());
+
+        let store = MemStorage::new_with_conf_state((vec![1, 2], vec![]));
+        assert!(Raft::new_with_peers(&config, store, &[1, 2, 3], &[]).is_err());
+        assert!(Raft::new_with_peers(&config, MemStorage::new(), &[], &[1]).is_err());
+        assert!(Raft::new_with_peers(&config, MemStorage::new(), &[1], &[1]).is_err());
+        Ok(())
+    }
+
+    // Test that the finalize entry is appended once, however the applied range is advanced.
+    #[test]
+    fn finalize_appended_once() -> Result<()> {
+        setup_for_test();
+        let mut raft = Raft::new(
+            &Config {
+                id: 1,
+                tag: "1".into(),
+                ..Default::default()
+            },
+            MemStorage::new_with_conf_state((vec![1], vec![])),
+        )?;
+        raft.become_candidate();
+        raft.become_leader();
+        let index = raft.raft_log.last_index() + 1;
+        raft.append_entry(&mut [begin_entry(&[1, 2], &[], index)])
+            .unwrap();
+        assert_eq!(raft.raft_log.committed, index);
+
+        let count_finalize = |raft: &Raft<MemStorage>| {
+            raft.raft_log
+                .entries(index, None)
+                .unwrap()
+                .iter()
+                .filter(|e| e.get_entry_type() == EntryType::EntryConfChange)
+                .map(|e| ConfChange::decode(e.get_data()).unwrap())
+                .filter(|cc| cc.get_change_type() == ConfChangeType::FinalizeMembershipChange)
+                .count()
+        };
+        raft.begin_membership_change(&begin_conf_change(&[1, 2], &[], index))?;
+        raft.commit_apply(index).unwrap();
+        assert_eq!(count_finalize(&raft), 1);
+        raft.commit_apply(index).unwrap();
+        assert_eq!(count_finalize(&raft), 1);
+
+        // The applied range spans the begin and finalize entries before the finalize entry
+        // is handled.
+        let finalize_index = raft.raft_log.last_index();
+        raft.mut_prs()
+            .get_mut(2)
+            .unwrap()
+            .maybe_update(finalize_index);
+        raft.maybe_commit();
+        assert_eq!(raft.raft_log.committed, finalize_index);
+        raft.commit_apply(finalize_index).unwrap();
+        assert_eq!(count_finalize(&raft), 1);
+
+        raft.finalize_membership_change(&finalize_conf_change())?;
+        raft.commit_apply(finalize_index).unwrap();
+        assert_eq!(count_finalize(&raft), 1);
+        assert!(!raft.prs().is_in_membership_change());
+        Ok(())
+    }
+
+    // Test whether nodes vote while the voters change from {1, 2} to {1, 3}, with 4 a learner
+    // throughout.
+    #[test]
+    fn is_voting_member_during_joint_change() -> Result<()> {
+        setup_for_test();
+        let expected = vec![
+            (1, (true, true)),
+            (2, (true, false)),
+            (3, (false, true)),
+            (4, (false, false)),
+        ];
+        for (id, (current, incoming)) in expected {
+            let mut raft = Raft::new(
+                &Config {
+                    id,
+                    tag: id.to_string(),
+                    ..Default::default()
+                },
+                MemStorage::new_with_conf_state((vec![1, 2], vec![4])),
+            )?;
+            raft.begin_membership_change(&begin_conf_change(&[1, 3], &[4], 2))?;
+            assert_eq!(
+                raft.voter_in_configurations(),
+                (current, incoming),
+                "{}",
+                id
+            );
+            assert_eq!(raft.is_voting_member(), current || incoming, "{}", id);
+            assert_eq!(raft.promotable(), raft.is_voting_member(), "{}", id);
+        }
+        Ok(())
+    }
+
+    // Test that the entries of a change are listed as they are committed.
+    #[test]
+    fn membership_change_entries() -> Result<()> {
+        setup_for_test();
+        let mut raft = Raft::new(
+            &Config {
+                id: 1,
+                tag: "1".into(),
+                ..Default::default()
+            },
+            MemStorage::new_with_conf_state((vec![1], vec![])),
+        )?;
+        raft.become_candidate();
+        raft.become_leader();
+        assert_eq!(raft.membership_change_entries()?, vec![]);
+
+        let index = raft.raft_log.last_index() + 1;
+        raft.append_entry(&mut [begin_entry(&[1, 2], &[], index)])?;
+        raft.begin_membership_change(&begin_conf_change(&[1, 2], &[], index))?;
+        raft.commit_apply(index).unwrap();
+        let ents = raft.membership_change_entries()?;
+        assert_eq!(ents.len(), 1);
+        assert_eq!(ents[0].get_index(), index);
+
+        // The finalize entry is listed once committed, which needs the new voter.
+        raft.append_entry(&mut [Entry::default()])?;
+        assert_eq!(raft.membership_change_entries()?.len(), 1);
+        let last_index = raft.raft_log.last_index();
+        raft.mut_prs().get_mut(2).unwrap().maybe_update(last_index);
+        raft.maybe_commit();
+        let ents = raft.membership_change_entries()?;
+        assert_eq!(
+            ents.iter().map(Entry::get_index).collect::<Vec<_>>(),
+            vec![index, index + 1]
+        );
+        let cc = ConfChange::decode(ents[1].get_data()).unwrap();
+        assert_eq!(
+            cc.get_change_type(),
+            ConfChangeType::FinalizeMembershipChange
+        );
+        Ok(())
+    }
+
+    // Test that the status follows the begin and finalize entries of a change.
+    #[test]
+    fn membership_change_status() -> Result<()> {
+        setup_for_test();
+        let mut raft = Raft::new(
+            &Config {
+                id: 1,
+                tag: "1".into(),
+                ..Default::default()
+            },
+            MemStorage::new_with_conf_state((vec![1], vec![])),
+        )?;
+        raft.become_candidate();
+        raft.become_leader();
+        assert_eq!(raft.membership_change_status(), None);
+
+        let index = raft.raft_log.last_index() + 1;
+        raft.append_entry(&mut [begin_entry(&[1, 2], &[], index)])?;
+        raft.begin_membership_change(&begin_conf_change(&[1, 2], &[], index))?;
+        let mut status = MembershipChangeStatus {
+            begin_index: index,
+            begin_committed: true,
+            ..Default::default()
+        };
+        assert_eq!(raft.membership_change_status(), Some(status));
+
+        raft.commit_apply(index).unwrap();
+        status.begin_applied = true;
+        status.finalize_index = Some(index + 1);
+        assert_eq!(raft.membership_change_status(), Some(status));
+
+        // The joint configuration needs the new voter to commit the finalize entry.
+        raft.mut_prs().get_mut(2).unwrap().maybe_update(index + 1);
+        raft.maybe_commit();
+        status.finalize_committed = true;
+        assert_eq!(raft.membership_change_status(), Some(status));
+
+        raft.commit_apply(index + 1).unwrap();
+        raft.finalize_membership_change(&finalize_conf_change())?;
+        assert_eq!(raft.membership_change_status(), None);
+        Ok(())
+    }
+
+    // Test that a follower tracks the finalize entry it receives, until it's overwritten.
+    #[test]
+    fn membership_change_status_on_follower() -> Result<()> {
+        setup_for_test();
+        let store = MemStorage::new_with_conf_state((vec![1, 2], vec![]));
+        let mut entry = Entry::new_();
+        entry.set_term(1);
+        entry.set_index(1);
+        store.wl().append(&[entry])?;
+        let mut raft = Raft::new(
+            &Config {
+                id: 2,
+                tag: "2".into(),
+                ..Default::default()
+            },
+            store,
+        )?;
+        raft.become_follower(1, 1);
+        let index = raft.raft_log.last_index() + 1;
+        let append = |index: u64, entry: Entry| {
+            let mut m = new_message(1, 2, MessageType::MsgAppend, 0);
+            m.set_term(entry.get_term());
+            m.set_index(index);
+            m.set_log_term(1);
+            m.set_entries(vec![entry]);
+            m
+        };
+
+        let mut begin = begin_entry(&[1, 2, 3], &[], index);
+        begin.set_term(1);
+        raft.handle_append_entries(&append(index - 1, begin));
+        raft.begin_membership_change(&begin_conf_change(&[1, 2, 3], &[], index))?;
+        assert_eq!(
+            raft.membership_change_status().unwrap().finalize_index,
+            None
+        );
+
+        let mut data = vec![];
+        finalize_conf_change().encode(&mut data).unwrap();
+        let mut finalize = Entry::new_();
+        finalize.set_entry_type(EntryType::EntryConfChange);
+        finalize.set_data(data);
+        finalize.set_term(1);
+        finalize.set_index(index + 1);
+        raft.handle_append_entries(&append(index, finalize));
+        assert_eq!(
+            raft.membership_change_status().unwrap().finalize_index,
+            Some(index + 1)
+        );
+
+        // A new leader overwrites the uncommitted finalize entry.
+        let mut entry = Entry::new_();
+        entry.set_term(2);
+        entry.set_index(index + 1);
+        raft.handle_append_entries(&append(index, entry));
+        assert_eq!(
+            raft.membership_change_status().unwrap().finalize_index,
+            None
+        );
+        Ok(())
+    }
+
+    // Test that a learner being promoted gets a single append and heartbeat per broadcast,
+    // although it's both a learner and a voter during the transition.
+    #[test]
+    fn broadcast_once_to_promoted_learner() -> Result<()> {
+        setup_for_test();
+        let mut raft = Raft::new(
+            &Config {
+                id: 1,
+                tag: "1".into(),
+                ..Default::default()
+            },
+            MemStorage::new_with_conf_state((vec![1, 2], vec![3])),
+        )?;
+        raft.become_candidate();
+        raft.become_leader();
+        let index = raft.raft_log.last_index() + 1;
+        raft.append_entry(&mut [begin_entry(&[1, 2, 3], &[], index)])?;
+        raft.begin_membership_change(&begin_conf_change(&[1, 2, 3], &[], index))?;
+        assert!(raft.prs().is_in_membership_change());
+
+        let want: HashMap<u64, usize> = vec![(2, 1), (3, 1)].into_iter().collect();
+        let count_to = |msgs: &[Message], ty| {
+            let mut counts: HashMap<u64, usize> = HashMap::default();
+            for m in msgs.iter().filter(|m| m.get_msg_type() == ty) {
+                *counts.entry(m.get_to()).or_insert(0) += 1;
+            }
+            counts
+        };
+        raft.msgs.clear();
+        for id in 2..=3 {
+            let pr = raft.mut_prs().get_mut(id).unwrap();
+            pr.become_probe();
+            pr.resume();
+        }
+        raft.bcast_append();
+        let counts = count_to(&raft.msgs, MessageType::MsgAppend);
+        assert_eq!(counts, want);
+
+        raft.msgs.clear();
+        raft.bcast_heartbeat();
+        let counts = count_to(&raft.msgs, MessageType::MsgHeartbeat);
+        assert_eq!(counts, want);
+        Ok(())
+    }
+
     // Test if the process rejects an overlapping voter and learner set.
     #[test]
     fn checks_for_overlapping_membership() -> Result<()> {
@@ -105,6 +412,156 @@ mod api {
             .is_err());
         Ok(())
     }
+
+    // Test that a node refuses to campaign while a committed begin entry is unapplied.
+    #[test]
+    fn unapplied_begin_blocks_campaign() -> Result<()> {
+        setup_for_test();
+        let mut raft = Raft::new(
+            &Config {
+                id: 1,
+                tag: "1".into(),
+                ..Default::default()
+            },
+            MemStorage::new_with_conf_state((vec![1, 2, 3], vec![])),
+        )?;
+        let index = raft.raft_log.last_index() + 1;
+        let mut entry = begin_entry(&[1, 2, 3, 4], &[], index);
+        entry.set_term(raft.raft_log.last_term());
+        raft.raft_log.append(&[entry]);
+        raft.raft_log.commit_to(index);
+
+        raft.step(new_message(1, 1, MessageType::MsgHup, 0))?;
+        assert_eq!(raft.state, StateRole::Follower);
+
+        raft.commit_apply(index).unwrap();
+        raft.step(new_message(1, 1, MessageType::MsgHup, 0))?;
+        assert_eq!(raft.state, StateRole::Candidate);
+        Ok(())
+    }
+
+    // Test that the applied `ConfState` is the configuration being left during a transition.
+    #[test]
+    fn applied_conf_state_in_joint() -> Result<()> {
+        setup_for_test();
+        let mut raft = Raft::new(
+            &Config {
+                id: 1,
+                tag: "1".into(),
+                ..Default::default()
+            },
+            MemStorage::new_with_conf_state((vec![1, 2, 3], vec![4])),
+        )?;
+        let index = raft.raft_log.last_index() + 1;
+        let mut entry = begin_entry(&[1, 2, 4], &[], index);
+        entry.set_term(raft.raft_log.last_term());
+        raft.raft_log.append(&[entry]);
+        raft.raft_log.commit_to(index);
+        raft.commit_apply(index).unwrap();
+        raft.begin_membership_change(&begin_conf_change(&[1, 2, 4], &[], index))?;
+
+        let conf_state = raft.applied_conf_state();
+        assert_eq!(
+            Configuration::from(conf_state.clone()),
+            Configuration::new(vec![1, 2, 3], vec![4])
+        );
+        let snapshot = raft.create_snapshot(index, conf_state, vec![])?;
+        let meta = snapshot.get_metadata();
+        assert_eq!(meta.get_pending_membership_change_index(), index);
+        assert_eq!(
+            Configuration::from(meta.get_pending_membership_change().clone()),
+            Configuration::new(vec![1, 2, 4], vec![])
+        );
+
+        raft.finalize_membership_change(&finalize_conf_change())?;
+        assert_eq!(
+            Configuration::from(raft.applied_conf_state()),
+            Configuration::new(vec![1, 2, 4], vec![])
+        );
+        Ok(())
+    }
+
+    // Test that a snapshot built from `snapshot_metadata` mid-change resumes the change when
+    // restored.
+    #[test]
+    fn snapshot_metadata_round_trip() -> Result<()> {
+        setup_for_test();
+        let mut raft = Raft::new(
+            &Config {
+                id: 1,
+                tag: "1".into(),
+                ..Default::default()
+            },
+            MemStorage::new_with_conf_state((vec![1, 2, 3], vec![])),
+        )?;
+        let index = raft.raft_log.last_index() + 1;
+        let mut entry = begin_entry(&[1, 2, 4], &[], index);
+        entry.set_term(raft.raft_log.last_term());
+        raft.raft_log.append(&[entry]);
+        raft.raft_log.commit_to(index);
+        raft.commit_apply(index)?;
+        raft.begin_membership_change(&begin_conf_change(&[1, 2, 4], &[], index))?;
+
+        // The change began after the earlier index.
+        let meta = raft.snapshot_metadata(index - 1)?;
+        assert_eq!(meta.get_pending_membership_change_index(), 0);
+        assert!(raft.snapshot_metadata(index + 1).is_err());
+
+        let meta = raft.snapshot_metadata(index)?;
+        assert_eq!(meta.get_index(), index);
+        assert_eq!(meta.get_term(), raft.raft_log.term(index)?);
+        let mut snapshot = Snapshot::default();
+        snapshot.set_metadata(meta);
+
+        let mut restored = Raft::new(
+            &Config {
+                id: 2,
+                tag: "2".into(),
+                ..Default::default()
+            },
+            MemStorage::new_with_conf_state((vec![1, 2, 3], vec![])),
+        )?;
+        assert!(restored.restore(snapshot));
+        assert_eq!(restored.began_membership_change_at(), Some(index));
+        assert_eq!(
+            Configuration::from(restored.applied_conf_state()),
+            Configuration::new(vec![1, 2, 3], vec![])
+        );
+        Ok(())
+    }
+
+    // Test that a learner added alongside a voter change starts at the leader's last index
+    // without disturbing the replication state of the retained peers.
+    #[test]
+    fn new_learner_keeps_voter_progress() -> Result<()> {
+        setup_for_test();
+        let mut raft = Raft::new(
+            &Config {
+                id: 1,
+                tag: "1".into(),
+                ..Default::default()
+            },
+            MemStorage::new_with_conf_state((vec![1, 2, 3], vec![])),
+        )?;
+        raft.become_candidate();
+        raft.become_leader();
+        let last_index = raft.raft_log.last_index();
+        raft.mut_prs().get_mut(2).unwrap().maybe_update(last_index);
+        raft.mut_prs().get_mut(2).unwrap().become_replicate();
+        let voter = raft.prs().get(2).cloned().unwrap();
+
+        raft.begin_membership_change(&begin_conf_change(&[1, 2, 4], &[5], last_index + 1))?;
+
+        assert_eq!(raft.prs().get(2), Some(&voter));
+        for id in &[4, 5] {
+            let pr = raft.prs().get(*id).unwrap();
+            assert_eq!(pr.matched, 0);
+            assert_eq!(pr.next_idx, last_index + 1);
+            assert_eq!(pr.state, ProgressState::Probe);
+        }
+        assert!(raft.prs().learner_ids().contains(&5));
+        Ok(())
+    }
 }
 
 // Test that small cluster is able to progress through adding a voter.
@@ -269,6 +726,62 @@ mod remove_learner {
     }
 }
 
+// Test that a voter can be swapped for a learner in a single joint change.
+mod three_peers_swap_voter_with_learner {
+    use super::*;
+
+    /// In a steady state transition should proceed without issue, and the promoted learner
+    /// should keep its progress.
+    #[test]
+    fn stable() -> Result<()> {
+        setup_for_test();
+        let leader = 1;
+        let old_configuration = (vec![1, 2, 3], vec![4]);
+        let new_configuration = (vec![1, 2, 4], vec![]);
+        let mut scenario = Scenario::new(leader, old_configuration, new_configuration)?;
+        scenario.spawn_new_peers()?;
+        scenario.propose_change_message()?;
+
+        info!("Allowing quorum to commit");
+        scenario.expect_read_and_dispatch_messages_from(&[1, 2, 3, 4])?;
+
+        info!("Advancing leader, now entered the joint");
+        scenario.assert_can_apply_transition_entry_at_index(
+            &[1],
+            3,
+            ConfChangeType::BeginMembershipChange,
+        );
+        scenario.assert_in_membership_change(&[1]);
+        let matched = scenario.peers[&1].prs().get(4).unwrap().matched;
+        assert_eq!(matched, 3);
+
+        info!("Leader replicates the commit and finalize entry.");
+        scenario.expect_read_and_dispatch_messages_from(&[1])?;
+        scenario.assert_can_apply_transition_entry_at_index(
+            &[2, 3, 4],
+            3,
+            ConfChangeType::BeginMembershipChange,
+        );
+        scenario.assert_in_membership_change(&[1, 2, 3, 4]);
+
+        info!("Cluster leaving the joint.");
+        scenario.expect_read_and_dispatch_messages_from(&[4, 3, 2, 1])?;
+        scenario.assert_can_apply_transition_entry_at_index(
+            &[1, 2, 3, 4],
+            4,
+            ConfChangeType::FinalizeMembershipChange,
+        );
+        scenario.assert_not_in_membership_change(&[1, 2, 3, 4]);
+
+        let prs = scenario.peers[&1].prs();
+        assert!(prs.get(3).is_none());
+        assert!(prs.get(4).unwrap().matched >= matched);
+        assert!(!scenario.peers[&4].is_learner);
+
+        Ok(())
+    }
+}
+
 // Test that small cluster is able to progress through removing a voter.
 mod remove_voter {
     use super::*;
@@ -1203,6 +1716,70 @@ mod intermingled_config_changes {
     }
 }
 
+// Test that a split vote in an even-sized joint configuration can be broken in favor of the
+// lower id candidate.
+mod split_vote_in_joint {
+    use super::*;
+
+    // Four peers in the joint configuration of {1, 2} and {1, 2, 3, 4}.
+    fn joint_network(prefer_lower_id_candidate: bool) -> Result<Network> {
+        let mut peers = vec![];
+        for id in 1..=4 {
+            let mut raft = Raft::new(
+                &Config {
+                    id,
+                    tag: id.to_string(),
+                    prefer_lower_id_candidate,
+                    ..Default::default()
+                },
+                MemStorage::new_with_conf_state((vec![1, 2], vec![])),
+            )?;
+            raft.begin_membership_change(&begin_conf_change(&[1, 2, 3, 4], &[], 2))?;
+            peers.push(Some(raft.into()));
+        }
+        Ok(Network::new(peers))
+    }
+
+    #[test]
+    fn defers_to_lower_id() -> Result<()> {
+        setup_for_test();
+        for &prefer in &[false, true] {
+            let mut network = joint_network(prefer)?;
+            // 1 gets the vote of 3 and 2 the vote of 4, neither reaches a quorum of {1, 2}.
+            network.cut(1, 2);
+            network.cut(1, 4);
+            network.cut(2, 3);
+            network.send(vec![
+                new_message(1, 1, MessageType::MsgHup, 0),
+                new_message(2, 2, MessageType::MsgHup, 0),
+            ]);
+            assert_eq!(network.peers[&1].state, StateRole::Candidate);
+            assert_eq!(network.peers[&2].state, StateRole::Candidate);
+            let term = network.peers[&1].term;
+            assert_eq!(network.peers[&2].term, term);
+
+            network.recover();
+            let mut m = new_message(1, 2, MessageType::MsgRequestVote, 0);
+            m.set_term(term);
+            m.set_log_term(network.peers[&1].raft_log.last_term());
+            m.set_index(network.peers[&1].raft_log.last_index());
+            network.send(vec![m]);
+            if prefer {
+                // 2 steps down and votes for 1, which wins the same term.
+                assert_eq!(network.peers[&2].state, StateRole::Follower);
+                assert_eq!(network.peers[&2].vote, 1);
+                assert_eq!(network.peers[&1].state, StateRole::Leader);
+                assert_eq!(network.peers[&1].term, term);
+            } else {
+                assert_eq!(network.peers[&2].state, StateRole::Candidate);
+                assert_eq!(network.peers[&2].vote, 2);
+                assert_ne!(network.peers[&1].state, StateRole::Leader);
+            }
+        }
+        Ok(())
+    }
+}
+
 mod compaction {
     use super::*;
 
@@ -1481,9 +2058,11 @@ impl Scenario {
                         }
                     }
                     if found {
-                        peer.raft_log.stable_to(entry.get_index(), entry.get_term());
+                        peer.raft_log
+                            .stable_to(entry.get_index(), entry.get_term())
+                            .expect("");
                         peer.raft_log.commit_to(entry.get_index());
-                        peer.commit_apply(entry.get_index());
+                        peer.commit_apply(entry.get_index()).unwrap();
                         let hs = peer.hard_state();
                         peer.mut_store().wl().set_hardstate(hs);
                         peer.tick();
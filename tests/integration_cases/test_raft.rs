@@ -25,9 +25,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cell::Cell;
 use std::cmp;
 use std::collections::HashMap;
 use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 use harness::*;
 use hashbrown::HashSet;
@@ -115,9 +118,9 @@ fn next_ents(r: &mut Raft<MemStorage>, s: &MemStorage) -> Vec<Entry> {
         s.wl().append(entries).expect("");
     }
     let (last_idx, last_term) = (r.raft_log.last_index(), r.raft_log.last_term());
-    r.raft_log.stable_to(last_idx, last_term);
+    r.raft_log.stable_to(last_idx, last_term).expect("");
     let ents = r.raft_log.next_entries();
-    r.commit_apply(r.raft_log.committed);
+    r.commit_apply(r.raft_log.committed).unwrap();
     ents.unwrap_or_else(Vec::new)
 }
 
@@ -1227,6 +1230,90 @@ fn test_handle_msg_append() {
     }
 }
 
+// test_append_conflicts ensures that only appends overwriting conflicting entries are counted
+// as conflicts.
+#[test]
+fn test_append_conflicts() {
+    setup_for_test();
+    let mut sm = new_test_raft_with_logs(
+        1,
+        vec![1],
+        10,
+        1,
+        MemStorage::new(),
+        &[empty_entry(1, 2), empty_entry(2, 3)],
+    );
+    sm.become_follower(3, INVALID_ID);
+
+    let mut m = new_message(2, 1, MessageType::MsgAppend, 0);
+    m.set_term(3);
+    m.set_log_term(2);
+    m.set_index(3);
+    m.set_entries(vec![empty_entry(2, 4)]);
+    sm.handle_append_entries(&m);
+    assert_eq!(sm.raft_log.last_index(), 4);
+    assert_eq!(sm.append_conflicts(), 0);
+
+    let mut m = new_message(2, 1, MessageType::MsgAppend, 0);
+    m.set_term(3);
+    m.set_log_term(1);
+    m.set_index(2);
+    m.set_entries(vec![empty_entry(3, 3)]);
+    sm.handle_append_entries(&m);
+    assert_eq!(sm.raft_log.last_index(), 3);
+    assert_eq!(sm.raft_log.last_term(), 3);
+    assert_eq!(sm.append_conflicts(), 1);
+}
+
+// test_overwritten_entries ensures the ranges of entries truncated by a new leader are
+// recorded until taken.
+#[test]
+fn test_overwritten_entries() {
+    setup_for_test();
+    let new_raft = |record| {
+        let mut config = new_test_config(1, 10, 1);
+        config.record_overwritten_entries = record;
+        let store = MemStorage::new_with_conf_state((vec![1], vec![]));
+        store
+            .wl()
+            .append(&[empty_entry(1, 2), empty_entry(1, 3), empty_entry(1, 4)])
+            .unwrap();
+        let mut sm = new_test_raft_with_config(&config, store);
+        sm.become_follower(2, INVALID_ID);
+        sm
+    };
+    let overwrite = |sm: &mut Interface| {
+        let mut m = new_message(2, 1, MessageType::MsgAppend, 0);
+        m.set_term(2);
+        m.set_log_term(1);
+        m.set_index(2);
+        m.set_entries(vec![empty_entry(1, 3), empty_entry(2, 4)]);
+        sm.handle_append_entries(&m);
+    };
+
+    // Nothing is recorded unless enabled.
+    let mut sm = new_raft(false);
+    overwrite(&mut sm);
+    assert_eq!(sm.append_conflicts(), 1);
+    assert!(sm.take_overwritten_entries().is_empty());
+
+    let mut sm = new_raft(true);
+
+    // Appending new entries overwrites nothing.
+    let mut m = new_message(2, 1, MessageType::MsgAppend, 0);
+    m.set_term(2);
+    m.set_log_term(1);
+    m.set_index(4);
+    m.set_entries(vec![empty_entry(1, 5)]);
+    sm.handle_append_entries(&m);
+    assert!(sm.take_overwritten_entries().is_empty());
+
+    overwrite(&mut sm);
+    assert_eq!(sm.raft_log.last_index(), 4);
+    assert_eq!(sm.take_overwritten_entries(), vec![4..6]);
+    assert!(sm.take_overwritten_entries().is_empty());
+}
+
 // test_handle_heartbeat ensures that the follower commits to the commit in the message.
 #[test]
 fn test_handle_heartbeat() {
@@ -1315,6 +1402,84 @@ fn test_handle_heartbeat_resp() {
     assert!(msgs.is_empty());
 }
 
+// test_heartbeat_advances_follower_commit ensures that a caught up follower learns
+// the leader's commit from heartbeats alone when commits aren't broadcast.
+#[test]
+fn test_heartbeat_advances_follower_commit() {
+    setup_for_test();
+    let mut cfg = new_test_config(1, 10, 1);
+    cfg.skip_bcast_commit = true;
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let r1 = new_test_raft_with_config(&cfg, storage);
+    let r2 = new_test_raft(2, vec![1, 2, 3], 10, 1, new_storage());
+    let r3 = new_test_raft(3, vec![1, 2, 3], 10, 1, new_storage());
+    let mut nt = Network::new(vec![Some(r1), Some(r2), Some(r3)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    let committed = nt.peers[&1].raft_log.committed;
+    assert_eq!(nt.peers[&2].raft_log.committed, committed - 1);
+    assert_eq!(nt.peers[&3].raft_log.committed, committed - 1);
+
+    nt.peers
+        .get_mut(&1)
+        .unwrap()
+        .step(new_message(1, 1, MessageType::MsgBeat, 0))
+        .expect("");
+    let msgs = nt.peers.get_mut(&1).unwrap().read_messages();
+    assert_eq!(msgs.len(), 2);
+    for m in &msgs {
+        assert_eq!(m.get_msg_type(), MessageType::MsgHeartbeat);
+        assert_eq!(m.get_commit(), committed);
+    }
+    nt.send(msgs);
+
+    assert_eq!(nt.peers[&2].raft_log.committed, committed);
+    assert_eq!(nt.peers[&3].raft_log.committed, committed);
+}
+
+// test_min_commit_bcast_delta ensures that the leader only broadcasts commit advances
+// which are large enough.
+#[test]
+fn test_min_commit_bcast_delta() {
+    setup_for_test();
+    let mut cfg = new_test_config(1, 10, 1);
+    cfg.min_commit_bcast_delta = 3;
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut sm = new_test_raft_with_config(&cfg, storage);
+    sm.become_candidate();
+    sm.become_leader();
+    sm.read_messages();
+
+    // Committing the empty entry only advances the commit by 1.
+    let committed = sm.raft_log.committed;
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.set_term(sm.term);
+    m.set_index(sm.raft_log.last_index());
+    sm.step(m).expect("");
+    assert_eq!(sm.raft_log.committed, committed + 1);
+    assert!(sm.read_messages().is_empty());
+
+    for _ in 0..3 {
+        sm.step(new_message(1, 1, MessageType::MsgPropose, 1))
+            .expect("");
+    }
+    sm.read_messages();
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.set_term(sm.term);
+    m.set_index(sm.raft_log.last_index());
+    sm.step(m).expect("");
+    assert_eq!(sm.raft_log.committed, committed + 4);
+    // 3 hasn't responded to its probe yet, so it's paused.
+    let msgs = sm.read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_to(), 2);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgAppend);
+    assert_eq!(msgs[0].get_commit(), committed + 4);
+}
+
 // test_raft_frees_read_only_mem ensures raft will free read request from
 // ReadOnly read_index_queue and pending_read_index map.
 // related issue: https://github.com/coreos/etcd/issues/7571
@@ -1741,6 +1906,30 @@ fn test_leader_stepdown_when_quorum_active() {
     assert_eq!(sm.state, StateRole::Leader);
 }
 
+// A freshly elected leader in a cluster with unreachable peers must count itself as active
+// on its first check quorum round.
+#[test]
+fn test_new_leader_survives_first_check_quorum() {
+    setup_for_test();
+    let mut sm = new_test_raft(1, vec![1, 2, 3, 4, 5], 5, 1, new_storage());
+    sm.check_quorum = true;
+    sm.become_candidate();
+    sm.become_leader();
+    assert!(sm.prs().get(1).unwrap().recent_active);
+    assert!(!sm.prs().get(4).unwrap().recent_active);
+
+    for _ in 0..=sm.get_election_timeout() {
+        for id in 2..4 {
+            let mut m = new_message(id, 1, MessageType::MsgHeartbeatResponse, 0);
+            m.set_term(sm.term);
+            sm.step(m).expect("");
+        }
+        sm.tick();
+    }
+
+    assert_eq!(sm.state, StateRole::Leader);
+}
+
 #[test]
 fn test_leader_stepdown_when_quorum_lost() {
     setup_for_test();
@@ -2189,6 +2378,135 @@ fn test_read_only_option_safe() {
     }
 }
 
+// test_read_index_seq ensures that the sequence number given with a read request is
+// echoed back in the read state, both on the leader and on a follower.
+#[test]
+fn test_read_index_seq() {
+    setup_for_test();
+    for option in vec![ReadOnlyOption::Safe, ReadOnlyOption::LeaseBased] {
+        let mut peers = vec![];
+        for id in 1..=3 {
+            let mut cfg = new_test_config(id, 10, 1);
+            cfg.check_quorum = true;
+            cfg.read_only_option = option;
+            let storage = new_storage();
+            storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+            peers.push(Some(new_test_raft_with_config(&cfg, storage)));
+        }
+        let mut nt = Network::new(peers);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+        for (id, seq) in vec![(1, 7), (2, 8)] {
+            let mut m = new_message_with_entries(
+                id,
+                id,
+                MessageType::MsgReadIndex,
+                vec![new_entry(0, 0, Some("ctx"))],
+            );
+            m.set_read_seq(seq);
+            nt.send(vec![m]);
+
+            let read_states = &nt.peers[&id].read_states;
+            assert_eq!(read_states.len(), 1, "{:?} {}", option, id);
+            assert_eq!(read_states[0].seq, seq, "{:?} {}", option, id);
+            assert_eq!(read_states[0].request_ctx, b"ctx".to_vec());
+        }
+    }
+}
+
+// test_read_index_min_index ensures a read which tolerates any index at or above an already
+// committed one is served by a leader in lease without a heartbeat round.
+#[test]
+fn test_read_index_min_index() {
+    setup_for_test();
+    let mut peers = vec![];
+    for id in 1..=3 {
+        let mut cfg = new_test_config(id, 10, 1);
+        cfg.check_quorum = true;
+        let storage = new_storage();
+        storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+        peers.push(Some(new_test_raft_with_config(&cfg, storage)));
+    }
+    let mut nt = Network::new(peers);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    let committed = nt.peers[&1].raft_log.committed;
+    nt.isolate(1);
+
+    for (min_index, served) in vec![(0, false), (committed + 1, false), (committed, true)] {
+        let mut m = new_message_with_entries(
+            1,
+            1,
+            MessageType::MsgReadIndex,
+            vec![new_entry(0, 0, Some("ctx"))],
+        );
+        m.set_index(min_index);
+        nt.send(vec![m]);
+        let leader = nt.peers.get_mut(&1).unwrap();
+        assert_eq!(leader.read_states.len(), served as usize, "{}", min_index);
+        if served {
+            assert_eq!(leader.read_states[0].index, committed);
+        }
+    }
+
+    // Followers forward the hint to the leader.
+    nt.recover();
+    let mut m = new_message_with_entries(
+        2,
+        2,
+        MessageType::MsgReadIndex,
+        vec![new_entry(0, 0, Some("ctx2"))],
+    );
+    m.set_index(committed);
+    nt.peers.get_mut(&2).unwrap().step(m).expect("");
+    let msgs = nt.peers.get_mut(&2).unwrap().read_messages();
+    assert_eq!(msgs[0].get_index(), committed);
+    nt.peers
+        .get_mut(&1)
+        .unwrap()
+        .step(msgs[0].clone())
+        .expect("");
+    let resps = nt.peers.get_mut(&1).unwrap().read_messages();
+    assert_eq!(resps.len(), 1);
+    assert_eq!(resps[0].get_msg_type(), MessageType::MsgReadIndexResp);
+}
+
+// test_read_state_callback ensures read states are handed to the configured callback instead
+// of being queued, both when confirmed by heartbeats and on a single node.
+#[test]
+fn test_read_state_callback() {
+    setup_for_test();
+    for voters in vec![vec![1, 2, 3], vec![1]] {
+        let delivered = Arc::new(Mutex::new(vec![]));
+        let mut peers = vec![];
+        for &id in &voters {
+            let mut cfg = new_test_config(id, 10, 1);
+            let sink = delivered.clone();
+            cfg.read_state_callback = Some(Arc::new(move |rs| sink.lock().unwrap().push(rs)));
+            let storage = new_storage();
+            storage.initialize_with_conf_state((voters.clone(), vec![]));
+            peers.push(Some(new_test_raft_with_config(&cfg, storage)));
+        }
+        let mut nt = Network::new(peers);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+        let mut m = new_message_with_entries(
+            1,
+            1,
+            MessageType::MsgReadIndex,
+            vec![new_entry(0, 0, Some("ctx"))],
+        );
+        m.set_read_seq(3);
+        nt.send(vec![m]);
+
+        assert!(nt.peers[&1].read_states.is_empty());
+        let delivered = delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 1, "{:?}", voters);
+        assert_eq!(delivered[0].index, nt.peers[&1].raft_log.committed);
+        assert_eq!(delivered[0].request_ctx, b"ctx".to_vec());
+        assert_eq!(delivered[0].seq, 3);
+    }
+}
+
 #[test]
 fn test_read_only_option_lease() {
     setup_for_test();
@@ -2266,6 +2584,33 @@ fn test_read_only_option_lease() {
     }
 }
 
+// test_lease_remaining_ticks ensures that the remaining lease is only reported by a leader
+// with check quorum, and shrinks as ticks elapse until the next quorum check.
+#[test]
+fn test_lease_remaining_ticks() {
+    setup_for_test();
+    let mut sm = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    sm.check_quorum = true;
+    assert_eq!(sm.lease_remaining_ticks(), None);
+
+    sm.become_candidate();
+    sm.become_leader();
+    assert_eq!(sm.lease_remaining_ticks(), Some(10));
+    for i in 1..10 {
+        sm.tick();
+        assert_eq!(sm.lease_remaining_ticks(), Some(10 - i));
+    }
+    // The quorum isn't active, so the leader steps down at the end of the lease.
+    sm.tick();
+    assert_eq!(sm.state, StateRole::Follower);
+    assert_eq!(sm.lease_remaining_ticks(), None);
+
+    sm.check_quorum = false;
+    sm.become_candidate();
+    sm.become_leader();
+    assert_eq!(sm.lease_remaining_ticks(), None);
+}
+
 #[test]
 fn test_read_only_option_lease_without_check_quorum() {
     setup_for_test();
@@ -2465,8 +2810,8 @@ fn test_bcast_beat() {
 
     sm.become_candidate();
     sm.become_leader();
-    for i in 0..10 {
-        sm.append_entry(&mut [empty_entry(0, i as u64 + 1)]);
+    for _ in 0..10 {
+        sm.append_entry(&mut [empty_entry(0, 0)]).unwrap();
     }
     // slow follower
     let mut_pr = |sm: &mut Interface, n, matched, next_idx| {
@@ -2566,6 +2911,45 @@ fn test_recv_msg_beat() {
     }
 }
 
+// test_message_count ensures the pending messages are counted until they're read.
+#[test]
+fn test_message_count() {
+    setup_for_test();
+    let mut sm = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    assert!(!sm.has_messages());
+    assert_eq!(sm.message_count(), 0);
+
+    sm.become_candidate();
+    sm.become_leader();
+    sm.read_messages();
+    sm.step(new_message(1, 1, MessageType::MsgBeat, 0))
+        .expect("");
+    assert!(sm.has_messages());
+    assert_eq!(sm.message_count(), 2);
+
+    sm.read_messages();
+    assert!(!sm.has_messages());
+    assert_eq!(sm.message_count(), 0);
+}
+
+// tests that a follower drops a stale MsgCheckQuorum without any side effect
+#[test]
+fn test_follower_ignores_msg_check_quorum() {
+    setup_for_test();
+    let mut sm = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    sm.check_quorum = true;
+    sm.become_follower(2, 2);
+    sm.election_elapsed = 3;
+
+    sm.step(new_message(1, 1, MessageType::MsgCheckQuorum, 0))
+        .expect("");
+    assert_eq!(sm.state, StateRole::Follower);
+    assert_eq!(sm.term, 2);
+    assert_eq!(sm.leader_id, 2);
+    assert_eq!(sm.election_elapsed, 3);
+    assert!(!sm.has_messages());
+}
+
 #[test]
 fn test_leader_increase_next() {
     setup_for_test();
@@ -2619,7 +3003,7 @@ fn test_send_append_for_progress_probe() {
             // we expect that raft will only send out one msgAPP on the first
             // loop. After that, the follower is paused until a heartbeat response is
             // received.
-            r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]);
+            r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]).unwrap();
             do_send_append(&mut r, 2);
             let msg = r.read_messages();
             assert_eq!(msg.len(), 1);
@@ -2628,7 +3012,7 @@ fn test_send_append_for_progress_probe() {
 
         assert!(r.prs().get(2).unwrap().paused);
         for _ in 0..10 {
-            r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]);
+            r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]).unwrap();
             do_send_append(&mut r, 2);
             assert_eq!(r.read_messages().len(), 0);
         }
@@ -2668,7 +3052,7 @@ fn test_send_append_for_progress_replicate() {
     r.mut_prs().get_mut(2).unwrap().become_replicate();
 
     for _ in 0..10 {
-        r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]);
+        r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]).unwrap();
         do_send_append(&mut r, 2);
         assert_eq!(r.read_messages().len(), 1);
     }
@@ -2684,7 +3068,7 @@ fn test_send_append_for_progress_snapshot() {
     r.mut_prs().get_mut(2).unwrap().become_snapshot(10);
 
     for _ in 0..10 {
-        r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]);
+        r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]).unwrap();
         do_send_append(&mut r, 2);
         assert_eq!(r.read_messages().len(), 0);
     }
@@ -2899,41 +3283,87 @@ fn test_step_ignore_config() {
     r.step(m.clone()).expect("");
     assert!(r.has_pending_conf());
     let index = r.raft_log.last_index();
-    let pending_conf_index = r.pending_conf_index;
+    let pending_conf_index = r.pending_conf_index();
     r.step(m.clone()).expect("");
     let mut we = empty_entry(2, 4);
     we.set_entry_type(EntryType::EntryNormal);
     let wents = vec![we];
     let entries = r.raft_log.entries(index + 1, None).expect("");
     assert_eq!(entries, wents);
-    assert_eq!(r.pending_conf_index, pending_conf_index);
+    assert_eq!(r.pending_conf_index(), pending_conf_index);
 }
 
 // test_new_leader_pending_config tests that new leader sets its pending_conf_index
-// based on uncommitted entries.
+// based on the unapplied config changes.
 #[test]
 fn test_new_leader_pending_config() {
     setup_for_test();
-    let mut tests = vec![(false, 1), (true, 2)];
-    for (i, (add_entry, wpending_index)) in tests.drain(..).enumerate() {
+    let mut tests = vec![
+        (None, 1, false),
+        (Some(EntryType::EntryNormal), 1, false),
+        (Some(EntryType::EntryConfChange), 2, true),
+    ];
+    for (i, (entry_type, wpending_index, wpending)) in tests.drain(..).enumerate() {
         let mut r = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
-        let mut e = Entry::new_();
-        if add_entry {
-            e.set_entry_type(EntryType::EntryNormal);
-            r.append_entry(&mut [e]);
+        if let Some(entry_type) = entry_type {
+            let mut e = Entry::new_();
+            e.set_entry_type(entry_type);
+            r.append_entry_unchecked(&mut [e]);
         }
         r.become_candidate();
         r.become_leader();
-        if r.pending_conf_index != wpending_index {
+        if r.pending_conf_index() != wpending_index {
             panic!(
                 "#{}: pending_conf_index = {}, want {}",
-                i, r.pending_conf_index, wpending_index
+                i,
+                r.pending_conf_index(),
+                wpending_index
             );
         }
-        assert_eq!(r.has_pending_conf(), add_entry, "#{}: ", i);
+        assert_eq!(r.has_pending_conf(), wpending, "#{}: ", i);
+        if wpending {
+            continue;
+        }
+
+        // With no config change left unapplied, a new one is accepted right away.
+        let mut m = new_message(1, 1, MessageType::MsgPropose, 0);
+        let mut e = Entry::new_();
+        e.set_entry_type(EntryType::EntryConfChange);
+        m.mut_entries().push(e);
+        r.step(m).expect("");
+        let last_index = r.raft_log.last_index();
+        assert_eq!(r.pending_conf_index(), last_index, "#{}: ", i);
+        let ents = r.raft_log.entries(last_index, None).expect("");
+        assert_eq!(
+            ents[0].get_entry_type(),
+            EntryType::EntryConfChange,
+            "#{}: ",
+            i
+        );
     }
 }
 
+// test_new_leader_pending_config_chunked ensures the unapplied config change is found when
+// the unapplied entries are scanned in several chunks.
+#[test]
+fn test_new_leader_pending_config_chunked() {
+    setup_for_test();
+    let mut config = new_test_config(1, 10, 1);
+    config.max_size_per_msg = 1;
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2], vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    let mut ents = vec![Entry::new_(), Entry::new_(), Entry::new_(), Entry::new_()];
+    ents[1].set_entry_type(EntryType::EntryConfChange);
+    r.append_entry_unchecked(&mut ents);
+    let conf_index = ents[1].get_index();
+
+    r.become_candidate();
+    r.become_leader();
+    assert_eq!(r.pending_conf_index(), conf_index);
+    assert!(r.has_pending_conf());
+}
+
 // test_add_node tests that add_node could update nodes correctly.
 #[test]
 fn test_add_node() -> Result<()> {
@@ -3213,6 +3643,31 @@ fn test_leader_transfer_to_slow_follower() {
     check_leader_transfer_state(&nt.peers[&1], StateRole::Follower, 3);
 }
 
+// test_lagging_follower_ignores_timeout_now ensures that a transfer target which lags
+// behind the leader's commit doesn't start a futile election.
+#[test]
+fn test_lagging_follower_ignores_timeout_now() {
+    setup_for_test();
+    let mut nt = Network::new(vec![None, None, None]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.isolate(3);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    nt.recover();
+
+    let mut m = new_message(1, 3, MessageType::MsgTimeoutNow, 0);
+    m.set_term(nt.peers[&1].term);
+    m.set_commit(nt.peers[&1].raft_log.committed);
+    let mut follower = nt.peers.remove(&3).unwrap();
+    follower.step(m.clone()).expect("");
+    assert_eq!(follower.state, StateRole::Follower);
+
+    // Within the allowed lag the target campaigns.
+    follower.set_timeout_now_max_lag(2);
+    follower.step(m).expect("");
+    assert_eq!(follower.state, StateRole::Candidate);
+}
+
 #[test]
 fn test_leader_transfer_after_snapshot() {
     setup_for_test();
@@ -3691,6 +4146,75 @@ fn test_learner_log_replication() {
     assert_eq!(matched, network.peers[&2].raft_log.committed);
 }
 
+// test_learner_read_index ensures that a learner forwards read requests to the leader
+// no matter which read only option it's configured with.
+#[test]
+fn test_learner_read_index() {
+    setup_for_test();
+    for option in vec![ReadOnlyOption::Safe, ReadOnlyOption::LeaseBased] {
+        let mut peers = vec![];
+        for id in 1..=3 {
+            let storage = new_storage();
+            storage.initialize_with_conf_state((vec![1, 2], vec![3]));
+            let mut cfg = new_test_config(id, 10, 1);
+            cfg.check_quorum = true;
+            cfg.read_only_option = option;
+            peers.push(Some(new_test_raft_with_config(&cfg, storage)));
+        }
+        let mut nt = Network::new(peers);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        assert_eq!(nt.peers[&1].state, StateRole::Leader);
+        assert!(nt.peers[&3].is_learner);
+
+        let ctx = "ctx";
+        nt.send(vec![new_message_with_entries(
+            3,
+            3,
+            MessageType::MsgReadIndex,
+            vec![new_entry(0, 0, Some(ctx))],
+        )]);
+
+        let read_states = &nt.peers[&3].read_states;
+        assert_eq!(read_states.len(), 1, "{:?}", option);
+        assert_eq!(read_states[0].index, nt.peers[&1].raft_log.committed);
+        assert_eq!(read_states[0].request_ctx, ctx.as_bytes().to_vec());
+    }
+}
+
+// test_learner_forward_proposal ensures that a learner learns the leader and forwards
+// proposals to it, which get committed by the voters.
+#[test]
+fn test_learner_forward_proposal() {
+    setup_for_test();
+    let mut peers = vec![];
+    for id in 1..=3 {
+        let storage = new_storage();
+        storage.initialize_with_conf_state((vec![1, 2], vec![3]));
+        peers.push(Some(new_test_raft_with_config(
+            &new_test_config(id, 10, 1),
+            storage,
+        )));
+    }
+    let mut nt = Network::new(peers);
+    assert!(!nt.peers[&3].can_forward_proposals());
+
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&3].leader_id, 1);
+    assert!(nt.peers[&3].can_forward_proposals());
+    assert!(!nt.peers[&1].can_forward_proposals());
+
+    let committed = nt.peers[&1].raft_log.committed;
+    nt.send(vec![new_message(3, 3, MessageType::MsgPropose, 1)]);
+    for id in 1..=3 {
+        assert_eq!(
+            nt.peers[&id].raft_log.committed,
+            committed + 1,
+            "peer {}",
+            id
+        );
+    }
+}
+
 // TestRestoreWithLearner restores a snapshot which contains learners.
 #[test]
 fn test_restore_with_learner() {
@@ -3756,7 +4280,7 @@ fn test_learner_receive_snapshot() {
 
     n1.restore(s);
     let committed = n1.raft_log.committed;
-    n1.commit_apply(committed);
+    n1.commit_apply(committed).unwrap();
 
     let mut network = Network::new(vec![Some(n1), Some(n2)]);
 
@@ -3832,6 +4356,22 @@ fn test_remove_learner() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_remove_learner_refuses_voter() -> Result<()> {
+    setup_for_test();
+
+    let mut n1 = new_test_learner_raft(1, vec![1, 2], vec![3], 10, 1, new_storage());
+    assert_eq!(n1.remove_learner(2), Err(Error::NotLearner(2)));
+    assert!(n1.prs().voter_ids().contains(&2));
+    assert!(n1.remove_learner(4).is_err());
+
+    n1.remove_learner(3)?;
+    assert!(n1.prs().learner_ids().is_empty());
+    assert_eq!(n1.prs().voter_ids().len(), 2);
+
+    Ok(())
+}
+
 // simulate rolling update a cluster for Pre-Vote. cluster has 3 nodes [n1, n2, n3].
 // n1 is leader with term 2
 // n2 is follower with term 2
@@ -4009,33 +4549,141 @@ fn test_election_tick_range() {
     }
 }
 
-// TestPreVoteWithSplitVote verifies that after split vote, cluster can complete
-// election in next round.
+// test_set_timeouts ensures that the timeouts can be changed at runtime, and that invalid
+// timeouts are refused without changing the current ones.
 #[test]
-fn test_prevote_with_split_vote() {
+fn test_set_timeouts() {
     setup_for_test();
-    let peers = (1..=3).map(|id| {
-        let mut raft = new_test_raft_with_prevote(id, vec![1, 2, 3], 10, 1, new_storage(), true);
-        raft.become_follower(1, INVALID_ID);
-        Some(raft)
-    });
-    let mut network = Network::new(peers.collect());
-    network.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    let mut sm = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
 
-    // simulate leader down. followers start split vote.
-    network.isolate(1);
-    network.send(vec![
-        new_message(2, 2, MessageType::MsgHup, 0),
-        new_message(3, 3, MessageType::MsgHup, 0),
-    ]);
+    sm.set_timeouts(5, 50, 0, 0).unwrap();
+    assert_eq!(sm.get_heartbeat_timeout(), 5);
+    assert_eq!(sm.get_election_timeout(), 50);
+    let timeout = sm.get_randomized_election_timeout();
+    assert!(50 <= timeout && timeout < 100, "timeout {}", timeout);
 
-    // check whether the term values are expected
-    assert_eq!(network.peers[&2].term, 3, "peer 2 term",);
-    assert_eq!(network.peers[&3].term, 3, "peer 3 term",);
+    sm.set_timeouts(2, 20, 30, 31).unwrap();
+    assert_eq!(sm.get_randomized_election_timeout(), 30);
 
-    // check state
-    assert_eq!(
-        network.peers[&2].state,
+    for (heartbeat, election, min_election, max_election) in
+        vec![(0, 20, 0, 0), (2, 3, 0, 0), (2, 20, 10, 0), (2, 20, 30, 30)]
+    {
+        sm.set_timeouts(heartbeat, election, min_election, max_election)
+            .unwrap_err();
+        assert_eq!(sm.get_heartbeat_timeout(), 2);
+        assert_eq!(sm.get_election_timeout(), 20);
+        assert_eq!(sm.get_randomized_election_timeout(), 30);
+    }
+}
+
+// test_prevote_backoff ensures that the election timeout backs off exponentially after
+// consecutive failed pre-votes, up to the cap, and is restored once a leader is heard from.
+#[test]
+fn test_prevote_backoff() {
+    setup_for_test();
+    let mut cfg = new_test_config(1, 10, 1);
+    cfg.pre_vote = true;
+    cfg.prevote_backoff_cap_ticks = 100;
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut sm = new_test_raft_with_config(&cfg, storage);
+
+    for (failures, low, high) in vec![(1, 20, 40), (2, 40, 80), (3, 80, 100), (4, 100, 100)] {
+        sm.step(new_message(1, 1, MessageType::MsgHup, 0))
+            .expect("");
+        assert_eq!(sm.state, StateRole::PreCandidate);
+        sm.read_messages();
+        for id in 2..=3 {
+            let mut m = new_message(id, 1, MessageType::MsgRequestPreVoteResponse, 0);
+            m.set_term(sm.term);
+            m.set_reject(true);
+            sm.step(m).expect("");
+        }
+        assert_eq!(sm.state, StateRole::Follower);
+        let timeout = sm.get_randomized_election_timeout();
+        assert!(
+            low <= timeout && timeout <= high,
+            "#{}: timeout {} not in [{}, {}]",
+            failures,
+            timeout,
+            low,
+            high
+        );
+    }
+
+    let mut m = new_message(2, 1, MessageType::MsgHeartbeat, 0);
+    m.set_term(sm.term);
+    sm.step(m).expect("");
+    assert!(sm.get_randomized_election_timeout() < 20);
+}
+
+// test_prevote_max_lag ensures that a pre-vote is refused to a candidate lagging too far
+// behind the voter's committed index, even if its last term is newer.
+#[test]
+fn test_prevote_max_lag() {
+    setup_for_test();
+    let mut tests = vec![
+        // (max lag, candidate index, rejected)
+        (0, 5, false),
+        (3, 5, true),
+        (3, 7, false),
+        (3, 8, false),
+    ];
+    for (i, (max_lag, index, w_reject)) in tests.drain(..).enumerate() {
+        let mut cfg = new_test_config(1, 10, 1);
+        cfg.pre_vote = true;
+        cfg.prevote_max_lag = max_lag;
+        let store = MemStorage::new_with_conf_state((vec![1, 2, 3], vec![]));
+        let ents: Vec<Entry> = (2..=10).map(|index| empty_entry(1, index)).collect();
+        store.wl().append(&ents).unwrap();
+        let mut sm = new_test_raft_with_config(&cfg, store);
+        sm.become_follower(1, INVALID_ID);
+        sm.raft_log.commit_to(10);
+
+        let mut m = new_message(2, 1, MessageType::MsgRequestPreVote, 0);
+        m.set_term(2);
+        m.set_log_term(2);
+        m.set_index(index);
+        sm.step(m).expect("");
+        let msgs = sm.read_messages();
+        assert_eq!(msgs.len(), 1, "#{}", i);
+        assert_eq!(
+            msgs[0].get_msg_type(),
+            MessageType::MsgRequestPreVoteResponse,
+            "#{}",
+            i
+        );
+        assert_eq!(msgs[0].get_reject(), w_reject, "#{}", i);
+    }
+}
+
+// TestPreVoteWithSplitVote verifies that after split vote, cluster can complete
+// election in next round.
+#[test]
+fn test_prevote_with_split_vote() {
+    setup_for_test();
+    let peers = (1..=3).map(|id| {
+        let mut raft = new_test_raft_with_prevote(id, vec![1, 2, 3], 10, 1, new_storage(), true);
+        raft.become_follower(1, INVALID_ID);
+        Some(raft)
+    });
+    let mut network = Network::new(peers.collect());
+    network.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+
+    // simulate leader down. followers start split vote.
+    network.isolate(1);
+    network.send(vec![
+        new_message(2, 2, MessageType::MsgHup, 0),
+        new_message(3, 3, MessageType::MsgHup, 0),
+    ]);
+
+    // check whether the term values are expected
+    assert_eq!(network.peers[&2].term, 3, "peer 2 term",);
+    assert_eq!(network.peers[&3].term, 3, "peer 3 term",);
+
+    // check state
+    assert_eq!(
+        network.peers[&2].state,
         StateRole::Candidate,
         "peer 2 state",
     );
@@ -4135,6 +4783,26 @@ fn test_new_raft_with_bad_config_errors() {
     assert!(raft.is_err())
 }
 
+// ensure the election tick must be at least twice the heartbeat tick
+#[test]
+fn test_heartbeat_ratio_validation() {
+    setup_for_test();
+    for (election_tick, heartbeat_tick, valid) in vec![
+        (10, 5, true),
+        (9, 5, false),
+        (6, 5, false),
+        (5, 5, false),
+        (2, 1, true),
+    ] {
+        let cfg = new_test_config(1, election_tick, heartbeat_tick);
+        match cfg.validate() {
+            Ok(()) => assert!(valid, "{} {}", election_tick, heartbeat_tick),
+            Err(Error::ConfigInvalid(_)) => assert!(!valid, "{} {}", election_tick, heartbeat_tick),
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+    }
+}
+
 // tests whether MsgAppend are batched
 #[test]
 fn test_batch_msg_append() {
@@ -4161,3 +4829,1988 @@ fn test_batch_msg_append() {
     assert!(raft.step(reject_msg).is_ok());
     assert_eq!(raft.msgs.len(), 3);
 }
+
+// test_reconcile_persisted_state ensures that inconsistencies between the hard state,
+// the applied index and the persisted log are detected.
+#[test]
+fn test_reconcile_persisted_state() {
+    setup_for_test();
+    let new_raft = |commit: u64, applied: u64, compact: u64| {
+        let s = MemStorage::new_with_conf_state((vec![1, 2], vec![]));
+        s.wl()
+            .append(&[empty_entry(1, 2), empty_entry(2, 3), empty_entry(2, 4)])
+            .unwrap();
+        s.wl().mut_hard_state().set_commit(4);
+        s.wl().compact(compact).unwrap();
+        let mut sm = new_test_raft(1, vec![1, 2], 10, 1, s);
+        sm.raft_log.committed = commit;
+        sm.raft_log.applied = applied;
+        sm
+    };
+    let mismatch = |r: Result<()>| match r {
+        Err(Error::PersistedStateMismatch(_)) => true,
+        _ => false,
+    };
+
+    assert_eq!(new_raft(3, 2, 1).reconcile_persisted_state(), Ok(()));
+    assert_eq!(new_raft(4, 4, 1).reconcile_persisted_state(), Ok(()));
+
+    // The committed index is moved up to the snapshot.
+    let mut sm = new_raft(1, 2, 3);
+    assert_eq!(sm.reconcile_persisted_state(), Ok(()));
+    assert_eq!(sm.raft_log.committed, 2);
+
+    // Applied beyond committed.
+    assert!(mismatch(new_raft(2, 3, 1).reconcile_persisted_state()));
+    // Committed beyond the persisted log.
+    assert!(mismatch(new_raft(5, 2, 1).reconcile_persisted_state()));
+    // Unapplied entries have been compacted.
+    assert!(mismatch(new_raft(4, 1, 3).reconcile_persisted_state()));
+}
+
+#[cfg(feature = "testexport")]
+#[test]
+fn test_check_invariants() {
+    setup_for_test();
+    let mut nt = Network::new(vec![None, None, None]);
+    assert_eq!(nt.peers[&1].check_invariants(), Ok(()));
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    for id in 1..=3 {
+        assert_eq!(nt.peers[&id].check_invariants(), Ok(()), "peer {}", id);
+    }
+
+    let leader = nt.peers.get_mut(&1).unwrap();
+    leader.raft_log.applied = leader.raft_log.committed + 1;
+    match leader.check_invariants() {
+        Err(Error::InvariantViolated(_)) => (),
+        res => panic!("expect invariant violation, got {:?}", res),
+    }
+    leader.raft_log.applied = leader.raft_log.committed;
+    leader.vote = 2;
+    assert!(leader.check_invariants().is_err());
+
+    // A persisted commit index ahead of the log.
+    let committed = nt.peers[&2].raft_log.committed;
+    nt.storage[&2]
+        .wl()
+        .mut_hard_state()
+        .set_commit(committed + 1);
+    assert_eq!(
+        nt.peers[&2].check_invariants(),
+        Err(Error::InvariantViolated(format!(
+            "persisted hard state commit {} > committed {}",
+            committed + 1,
+            committed
+        )))
+    );
+}
+
+#[cfg(feature = "testexport")]
+#[test]
+fn test_debug_state() {
+    setup_for_test();
+    let run = || {
+        let mut nt = Network::new(vec![None, None, None]);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+        // The election timeout is the only randomized part.
+        for id in 1..=3 {
+            nt.peers
+                .get_mut(&id)
+                .unwrap()
+                .set_randomized_election_timeout(10);
+        }
+        nt
+    };
+    let (mut nt1, nt2) = (run(), run());
+    for id in 1..=3 {
+        assert_eq!(nt1.peers[&id].debug_state(), nt2.peers[&id].debug_state());
+        assert_eq!(
+            nt1.peers[&id].debug_state().encode(),
+            nt2.peers[&id].debug_state().encode()
+        );
+    }
+    let state = nt1.peers[&1].debug_state();
+    assert_eq!(state.state, StateRole::Leader);
+    let encoded = state.encode();
+    assert!(
+        encoded.starts_with("{\"id\":1,\"term\":1,\"vote\":1,\"state\":\"Leader\","),
+        "{}",
+        encoded
+    );
+    assert!(
+        encoded.ends_with(",\"votes\":[],\"pending_reads\":[]}"),
+        "{}",
+        encoded
+    );
+    assert_eq!(state.committed, state.last_index);
+    assert_eq!(
+        state.progress.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+
+    nt1.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_ne!(nt1.peers[&1].debug_state(), nt2.peers[&1].debug_state());
+    assert_ne!(
+        nt1.peers[&1].debug_state().encode(),
+        nt2.peers[&1].debug_state().encode()
+    );
+}
+
+// test_max_term_gap ensures messages with a term too far ahead of the local term are dropped
+// without the term being adopted.
+#[test]
+fn test_max_term_gap() {
+    setup_for_test();
+    let mut cfg = new_test_config(1, 10, 1);
+    cfg.max_term_gap = 5;
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut sm = new_test_raft_with_config(&cfg, storage);
+    sm.become_follower(2, INVALID_ID);
+
+    let mut m = new_message(2, 1, MessageType::MsgHeartbeat, 0);
+    m.set_term(8);
+    sm.step(m).expect("");
+    assert_eq!(sm.term, 2);
+    assert_eq!(sm.leader_id, INVALID_ID);
+    assert!(sm.read_messages().is_empty());
+
+    let mut m = new_message(2, 1, MessageType::MsgHeartbeat, 0);
+    m.set_term(7);
+    sm.step(m).expect("");
+    assert_eq!(sm.term, 7);
+    assert_eq!(sm.leader_id, 2);
+}
+
+// test_progress_committed ensures the leader learns the commit index of its followers from
+// their append responses.
+#[test]
+fn test_progress_committed() {
+    setup_for_test();
+    let mut nt = Network::new(vec![None, None, None]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+
+    let committed = nt.peers[&1].raft_log.committed;
+    for id in 2..=3 {
+        assert_eq!(nt.peers[&1].prs().get(id).unwrap().committed, committed);
+    }
+
+    // A response which doesn't carry the commit index leaves it untouched.
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.set_term(nt.peers[&1].term);
+    m.set_index(committed);
+    nt.peers.get_mut(&1).unwrap().step(m).expect("");
+    assert_eq!(nt.peers[&1].prs().get(2).unwrap().committed, committed);
+}
+
+// test_strict_message_routing ensures a misrouted message is refused when strict routing is
+// enabled, while local messages are still accepted.
+#[test]
+fn test_strict_message_routing() {
+    setup_for_test();
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut sm = new_test_raft(1, vec![1, 2, 3], 10, 1, storage);
+    let mut m = new_message(2, 3, MessageType::MsgHeartbeat, 0);
+    m.set_term(5);
+    sm.step(m.clone()).expect("");
+    assert_eq!(sm.term, 5);
+
+    let mut cfg = new_test_config(1, 10, 1);
+    cfg.strict_message_routing = true;
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut sm = new_test_raft_with_config(&cfg, storage);
+    let term = sm.term;
+    assert_eq!(sm.step(m), Err(Error::WrongDestination(1, 3)));
+    assert_eq!(sm.term, term);
+
+    let mut hup = new_message(0, 0, MessageType::MsgHup, 0);
+    hup.set_from(1);
+    sm.step(hup).expect("");
+    assert_eq!(sm.state, StateRole::Candidate);
+}
+
+// A run-length codec, which counts how many entries it compressed.
+#[derive(Default)]
+struct RleCodec {
+    compressed: AtomicUsize,
+}
+
+impl EntryCodec for RleCodec {
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        self.compressed.fetch_add(1, Ordering::SeqCst);
+        let mut out = vec![];
+        for &b in data {
+            match out.len() {
+                l if l >= 2 && out[l - 1] == b && out[l - 2] < u8::max_value() => out[l - 2] += 1,
+                _ => out.extend_from_slice(&[1, b]),
+            }
+        }
+        out
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut out = vec![];
+        for pair in data.chunks(2) {
+            out.extend(std::iter::repeat(pair[1]).take(pair[0] as usize));
+        }
+        Ok(out)
+    }
+}
+
+// test_entry_compression ensures that compressing the entries sent to followers doesn't change
+// the committed logs.
+#[test]
+fn test_entry_compression() {
+    setup_for_test();
+    let codec = Arc::new(RleCodec::default());
+    let mut logs = vec![];
+    for compress in vec![false, true] {
+        let mut peers = vec![];
+        for id in 1..=3 {
+            let mut cfg = new_test_config(id, 10, 1);
+            if compress {
+                cfg.entry_codec = Some(codec.clone());
+                cfg.compression_threshold = 8;
+            }
+            let storage = new_storage();
+            storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+            peers.push(Some(new_test_raft_with_config(&cfg, storage)));
+        }
+        let mut nt = Network::new(peers);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        for data in &["a", "abcdefghijkl", &"b".repeat(100)] {
+            let mut m = new_message(1, 1, MessageType::MsgPropose, 0);
+            m.set_entries(vec![new_entry(0, 0, Some(data))]);
+            nt.send(vec![m]);
+        }
+
+        let log = nt.peers[&1].raft_log.all_entries();
+        for id in 2..=3 {
+            assert_eq!(
+                nt.peers[&id].raft_log.committed,
+                log.last().unwrap().get_index()
+            );
+            assert_eq!(nt.peers[&id].raft_log.all_entries(), log);
+        }
+        assert!(log.iter().all(|e| !e.get_compressed()));
+        logs.push(log);
+    }
+    assert_eq!(logs[0], logs[1]);
+    // The long entries were compressed for both followers.
+    assert!(codec.compressed.load(Ordering::SeqCst) >= 4);
+}
+
+// test_no_double_vote ensures a node which voted in a term refuses any other candidate of the
+// same term, and never overwrites its vote.
+#[test]
+fn test_no_double_vote() {
+    setup_for_test();
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut sm = new_test_raft(1, vec![1, 2, 3], 10, 1, storage);
+    let vote_req = |from, msg_type, term, log_term, index| {
+        let mut m = new_message(from, 1, msg_type, 0);
+        m.set_term(term);
+        m.set_log_term(log_term);
+        m.set_index(index);
+        m
+    };
+
+    sm.step(vote_req(2, MessageType::MsgRequestVote, 2, 1, 1))
+        .expect("");
+    assert_eq!(sm.vote, 2);
+    let msgs = sm.read_messages();
+    assert!(!msgs[0].get_reject());
+
+    // A candidate of the same term with a better log.
+    sm.step(vote_req(3, MessageType::MsgRequestVote, 2, 2, 5))
+        .expect("");
+    assert_eq!(sm.vote, 2);
+    let msgs = sm.read_messages();
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgRequestVoteResponse);
+    assert!(msgs[0].get_reject());
+
+    // A pre-vote for a future term can be granted, but isn't recorded.
+    sm.step(vote_req(3, MessageType::MsgRequestPreVote, 3, 2, 5))
+        .expect("");
+    assert_eq!(sm.vote, 2);
+    let msgs = sm.read_messages();
+    assert!(!msgs[0].get_reject());
+
+    // Repeating the vote for the same candidate is allowed.
+    sm.step(vote_req(2, MessageType::MsgRequestVote, 2, 1, 1))
+        .expect("");
+    assert_eq!(sm.vote, 2);
+    let msgs = sm.read_messages();
+    assert!(!msgs[0].get_reject());
+}
+
+// test_transfer_leader_errors ensures transfers which can't succeed are refused up front.
+#[test]
+fn test_transfer_leader_errors() {
+    setup_for_test();
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1, 2, 3], vec![4]));
+    let mut sm = new_test_raft_with_config(&new_test_config(1, 10, 1), storage);
+    assert_eq!(sm.transfer_leader(2), Err(Error::NotLeader));
+
+    sm.become_candidate();
+    sm.become_leader();
+    assert_eq!(
+        sm.transfer_leader(4),
+        Err(Error::TransferTargetIsLearner(4))
+    );
+    assert_eq!(sm.transfer_leader(5), Err(Error::TransferTargetNotVoter(5)));
+    match sm.transfer_leader(1) {
+        Err(Error::ViolatesContract(_)) => (),
+        res => panic!("expected ViolatesContract, got {:?}", res),
+    }
+    assert_eq!(sm.lead_transferee, None);
+
+    sm.transfer_leader(2).expect("");
+    assert_eq!(sm.lead_transferee, Some(2));
+}
+
+// test_log_growth ensures the entries appended by a leader are counted, both in total and
+// since the last take.
+#[test]
+fn test_log_growth() {
+    setup_for_test();
+    let mut sm = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+    sm.become_candidate();
+    sm.become_leader();
+    let noop = sm.take_log_growth();
+    assert_eq!(noop.entries, 1);
+
+    let mut m = new_message(1, 1, MessageType::MsgPropose, 0);
+    m.set_entries(vec![
+        new_entry(0, 0, Some("foo")),
+        new_entry(0, 0, Some("bar")),
+    ]);
+    sm.step(m).expect("");
+    let growth = sm.take_log_growth();
+    assert_eq!(growth.entries, 2);
+    assert!(growth.bytes >= 6);
+    assert_eq!(sm.take_log_growth(), LogGrowth::default());
+
+    let total = Status::new(&sm).appended;
+    assert_eq!(total.entries, noop.entries + growth.entries);
+    assert_eq!(total.bytes, noop.bytes + growth.bytes);
+}
+
+// test_become_follower_idempotent ensures the messages of the leader of the current term don't
+// reset the follower, nor are reported again to the become follower hook, while a new term
+// still resets it.
+#[test]
+fn test_become_follower_idempotent() {
+    setup_for_test();
+    let follows = Arc::new(Mutex::new(vec![]));
+    let sink = Arc::clone(&follows);
+    let mut config = new_test_config(1, 10, 1);
+    config.become_follower_hook = Some(Arc::new(move |term, leader| {
+        sink.lock().unwrap().push((term, leader))
+    }));
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut sm = new_test_raft_with_config(&config, store);
+    // Starting as a follower is reported too.
+    assert_eq!(
+        follows.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![(1, INVALID_ID)]
+    );
+    let heartbeat = |from, term| {
+        let mut m = new_message(from, 1, MessageType::MsgHeartbeat, 0);
+        m.set_term(term);
+        m
+    };
+
+    sm.step(heartbeat(2, 2)).expect("");
+    assert_eq!(sm.state, StateRole::Follower);
+    assert_eq!(sm.leader_id, 2);
+    assert_eq!(*follows.lock().unwrap(), vec![(2, 2)]);
+    sm.vote = 2;
+
+    for _ in 0..10 {
+        sm.set_randomized_election_timeout(15);
+        sm.tick();
+        sm.step(heartbeat(2, 2)).expect("");
+        assert_eq!(sm.get_randomized_election_timeout(), 15);
+        assert_eq!(sm.election_elapsed, 0);
+        assert_eq!(sm.vote, 2);
+    }
+    assert_eq!(*follows.lock().unwrap(), vec![(2, 2)]);
+
+    // Voting in a new term resets the follower, learning the leader of the term doesn't.
+    let mut m = new_message(3, 1, MessageType::MsgRequestVote, 0);
+    m.set_term(3);
+    m.set_index(sm.raft_log.last_index());
+    m.set_log_term(sm.raft_log.last_term());
+    sm.step(m).expect("");
+    assert_eq!(sm.vote, 3);
+    sm.set_randomized_election_timeout(15);
+    sm.step(heartbeat(3, 3)).expect("");
+    assert_eq!(sm.leader_id, 3);
+    assert_eq!(sm.vote, 3);
+    assert_eq!(sm.get_randomized_election_timeout(), 15);
+    assert_eq!(
+        *follows.lock().unwrap(),
+        vec![(2, 2), (3, INVALID_ID), (3, 3)]
+    );
+
+    sm.become_follower(4, 4);
+    assert_eq!(sm.term, 4);
+    assert_eq!(sm.leader_id, 4);
+    assert_eq!(sm.vote, INVALID_ID);
+}
+
+// test_campaign_blocked_by_unapplied_conf_change ensures the memoized count of unapplied
+// configuration changes follows the applied and committed indexes.
+#[test]
+fn test_campaign_blocked_by_unapplied_conf_change() {
+    setup_for_test();
+    let mut conf_change = empty_entry(1, 4);
+    conf_change.set_entry_type(EntryType::EntryConfChange);
+    let mut sm = new_test_raft_with_logs(
+        1,
+        vec![1, 2, 3],
+        10,
+        1,
+        new_storage(),
+        &[empty_entry(1, 2), empty_entry(1, 3), conf_change],
+    );
+    sm.raft_log.commit_to(3);
+
+    // The conf change isn't committed, so it doesn't block.
+    sm.step(new_message(1, 1, MessageType::MsgHup, 0))
+        .expect("");
+    assert_eq!(sm.state, StateRole::Candidate);
+    let term = sm.term;
+    sm.become_follower(term, INVALID_ID);
+
+    sm.raft_log.commit_to(4);
+    for _ in 0..3 {
+        sm.step(new_message(1, 1, MessageType::MsgHup, 0))
+            .expect("");
+        assert_eq!(sm.state, StateRole::Follower);
+    }
+
+    sm.commit_apply(4).unwrap();
+    sm.step(new_message(1, 1, MessageType::MsgHup, 0))
+        .expect("");
+    assert_eq!(sm.state, StateRole::Candidate);
+}
+
+#[test]
+fn test_restore_with_reason() {
+    setup_for_test();
+    let previous_ents = vec![empty_entry(1, 1), empty_entry(1, 2), empty_entry(1, 3)];
+    let mut sm = new_test_raft(1, vec![], 10, 1, new_storage());
+    sm.raft_log.append(&previous_ents);
+    sm.raft_log.commit_to(2);
+
+    let s = new_snapshot(1, 1, vec![1, 2]);
+    assert_eq!(sm.restore_with_reason(s), RestoreResult::StaleIgnored);
+    assert_eq!(sm.raft_log.committed, 2);
+
+    let s = new_snapshot(3, 1, vec![1, 2]);
+    assert_eq!(sm.restore_with_reason(s), RestoreResult::FastForwarded);
+    assert_eq!(sm.raft_log.committed, 3);
+
+    // A snapshot at the committed index with another term diverges from committed history.
+    let s = new_snapshot(3, 2, vec![1, 2]);
+    assert_eq!(
+        sm.restore_with_reason(s),
+        RestoreResult::RejectedCommittedConflict
+    );
+    assert_eq!(sm.raft_log.committed, 3);
+    assert_eq!(sm.raft_log.term(3), Ok(1));
+
+    let mut s = new_snapshot(11, 11, vec![1, 2]);
+    s.mut_metadata().mut_conf_state().mut_learners().push(3);
+    let mut sm = new_test_raft(3, vec![1, 2, 3], 10, 1, new_storage());
+    assert_eq!(
+        sm.restore_with_reason(s),
+        RestoreResult::RejectedLearnerDemotion
+    );
+
+    let s = new_snapshot(11, 11, vec![1, 2, 3]);
+    assert_eq!(sm.restore_with_reason(s), RestoreResult::Restored);
+    assert_eq!(sm.raft_log.last_index(), 11);
+}
+
+#[test]
+fn test_disable_proposal_forwarding() {
+    setup_for_test();
+    for &disabled in &[false, true] {
+        let mut cfg = new_test_config(2, 10, 1);
+        cfg.disable_proposal_forwarding = disabled;
+        let storage = new_storage();
+        storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+        let mut sm = new_test_raft_with_config(&cfg, storage);
+        let term = sm.term + 1;
+        sm.become_follower(term, 1);
+        assert_eq!(sm.can_forward_proposals(), !disabled);
+
+        let prop = new_message(2, 2, MessageType::MsgPropose, 1);
+        let mut read = new_message(2, 2, MessageType::MsgReadIndex, 0);
+        read.set_entries(vec![new_entry(0, 0, Some("ctx"))]);
+        for m in vec![prop, read] {
+            let res = sm.step(m);
+            let msgs = sm.read_messages();
+            if disabled {
+                assert_eq!(res, Err(Error::ProposalDropped));
+                assert!(msgs.is_empty(), "{:?}", msgs);
+            } else {
+                assert_eq!(res, Ok(()));
+                assert_eq!(msgs.len(), 1);
+                assert_eq!(msgs[0].get_to(), 1);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_vote_tally() {
+    setup_for_test();
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1, 2, 3, 4, 5], vec![]));
+    let mut sm = new_test_raft(1, vec![1, 2, 3, 4, 5], 10, 1, storage);
+    sm.step(new_message(1, 1, MessageType::MsgHup, 0))
+        .expect("");
+    assert_eq!(sm.current_vote_tally(), (1, 0, 3));
+
+    let term = sm.term;
+    let mut grant = new_message(2, 1, MessageType::MsgRequestVoteResponse, 0);
+    grant.set_term(term);
+    sm.step(grant).expect("");
+    let mut reject = new_message(3, 1, MessageType::MsgRequestVoteResponse, 0);
+    reject.set_term(term);
+    reject.set_reject(true);
+    sm.step(reject).expect("");
+    assert_eq!(sm.state, StateRole::Candidate);
+    assert_eq!(sm.current_vote_tally(), (2, 1, 3));
+    assert_eq!(sm.next_vote_tally(), None);
+
+    let next = ConfState::from(Configuration::new(vec![1, 2, 6], vec![]));
+    let index = sm.raft_log.last_index() + 1;
+    sm.begin_membership_change(&ConfChange::from((index, next)))
+        .expect("");
+    assert_eq!(sm.current_vote_tally(), (2, 1, 3));
+    assert_eq!(sm.next_vote_tally(), Some((2, 0, 2)));
+}
+
+#[test]
+fn test_duplicate_timeout_now() {
+    setup_for_test();
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut sm = new_test_raft(2, vec![1, 2, 3], 10, 1, storage);
+    let term = sm.term + 1;
+    sm.become_follower(term, 1);
+
+    let mut m = new_message(1, 2, MessageType::MsgTimeoutNow, 0);
+    m.set_term(term);
+    sm.step(m.clone()).expect("");
+    assert_eq!(sm.state, StateRole::Candidate);
+    assert_eq!(sm.term, term + 1);
+    assert_eq!(sm.read_messages().len(), 2);
+
+    // A retransmit doesn't start another campaign.
+    sm.step(m).expect("");
+    assert_eq!(sm.state, StateRole::Candidate);
+    assert_eq!(sm.term, term + 1);
+    assert!(sm.read_messages().is_empty());
+}
+
+// A freshly elected single-node leader commits its no-op entry right away, so it can serve
+// reads without waiting for any ticks.
+#[test]
+fn test_single_node_leader_serves_read_immediately() {
+    setup_for_test();
+    let storage = new_storage();
+    storage.initialize_with_conf_state((vec![1], vec![]));
+    let mut sm = new_test_raft(1, vec![1], 10, 1, storage);
+    sm.step(new_message(1, 1, MessageType::MsgHup, 0))
+        .expect("");
+    assert_eq!(sm.state, StateRole::Leader);
+    assert_eq!(sm.raft_log.committed, sm.raft_log.last_index());
+    assert_eq!(sm.raft_log.term(sm.raft_log.committed), Ok(sm.term));
+
+    let ctx = "ctx";
+    sm.step(new_message_with_entries(
+        1,
+        1,
+        MessageType::MsgReadIndex,
+        vec![new_entry(0, 0, Some(ctx))],
+    ))
+    .expect("");
+    assert_eq!(sm.read_states.len(), 1);
+    assert_eq!(sm.read_states[0].index, sm.raft_log.committed);
+    assert_eq!(sm.read_states[0].request_ctx, ctx.as_bytes());
+}
+
+// A read pending when a membership change begins resolves with a quorum of the joint
+// configuration, without waiting for the next heartbeat.
+#[test]
+fn test_pending_read_resolves_in_joint_consensus() {
+    setup_for_test();
+    let s = new_storage();
+    s.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, s.clone());
+    r.become_candidate();
+    r.become_leader();
+    commit_noop_entry(&mut r, &s);
+
+    let ctx = "ctx";
+    r.step(new_message_with_entries(
+        1,
+        1,
+        MessageType::MsgReadIndex,
+        vec![new_entry(0, 0, Some(ctx))],
+    ))
+    .expect("");
+    // The heartbeats carrying the read are lost.
+    r.read_messages();
+
+    let next = ConfState::from(Configuration::new(vec![1, 2, 3, 4], vec![]));
+    let index = r.raft_log.last_index() + 1;
+    r.begin_membership_change(&ConfChange::from((index, next)))
+        .expect("");
+    let mut heartbeats: Vec<_> = r
+        .read_messages()
+        .into_iter()
+        .filter(|m| m.get_msg_type() == MessageType::MsgHeartbeat)
+        .map(|m| {
+            assert_eq!(m.get_context(), ctx.as_bytes());
+            m.get_to()
+        })
+        .collect();
+    heartbeats.sort();
+    assert_eq!(heartbeats, vec![2, 3, 4]);
+
+    // 1 and 2 are a quorum of the old configuration, but not of the new one.
+    for &id in &[2, 4] {
+        let mut resp = new_message(id, 1, MessageType::MsgHeartbeatResponse, 0);
+        resp.set_term(r.term);
+        resp.set_context(ctx.as_bytes().to_vec());
+        r.step(resp).expect("");
+        assert_eq!(r.read_states.is_empty(), id == 2);
+    }
+    assert_eq!(r.read_states[0].request_ctx, ctx.as_bytes());
+    assert_eq!(r.read_states[0].index, r.raft_log.committed);
+}
+
+#[test]
+fn test_follower_read_index_with_delegated_lease() {
+    setup_for_test();
+    for &delegate in &[false, true] {
+        let mut peers = vec![];
+        for id in 1..=3 {
+            let mut cfg = new_test_config(id, 10, 1);
+            cfg.check_quorum = true;
+            cfg.delegate_read_lease = delegate;
+            let storage = new_storage();
+            storage.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+            peers.push(Some(new_test_raft_with_config(&cfg, storage)));
+        }
+        let mut nt = Network::new(peers);
+        nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+        assert_eq!(nt.peers[&2].follower_read_index(), None);
+
+        nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+        let committed = nt.peers[&1].raft_log.committed;
+        if !delegate {
+            assert_eq!(nt.peers[&2].follower_read_index(), None);
+            continue;
+        }
+        assert_eq!(nt.peers[&2].follower_read_index(), Some(committed));
+
+        // The lease is one heartbeat interval shorter than the leader's.
+        let follower = nt.peers.get_mut(&2).unwrap();
+        for _ in 0..8 {
+            follower.tick();
+        }
+        assert_eq!(follower.follower_read_index(), Some(committed));
+        follower.tick();
+        assert_eq!(follower.follower_read_index(), None);
+    }
+}
+
+// A storage whose `term` can be made to fail as unavailable, recording the batches
+// handed to `append`.
+struct FlakyStorage {
+    store: MemStorage,
+    unavailable: Cell<bool>,
+    appended: Vec<Vec<Entry>>,
+}
+
+impl FlakyStorage {
+    fn new(store: MemStorage) -> FlakyStorage {
+        FlakyStorage {
+            store,
+            unavailable: Cell::new(false),
+            appended: vec![],
+        }
+    }
+}
+
+impl Storage for FlakyStorage {
+    fn initial_state(&self) -> Result<RaftState> {
+        self.store.initial_state()
+    }
+
+    fn entries(&self, low: u64, high: u64, max_size: impl Into<Option<u64>>) -> Result<Vec<Entry>> {
+        self.store.entries(low, high, max_size)
+    }
+
+    fn term(&self, idx: u64) -> Result<u64> {
+        if self.unavailable.get() {
+            return Err(Error::Store(StorageError::Unavailable));
+        }
+        self.store.term(idx)
+    }
+
+    fn first_index(&self) -> Result<u64> {
+        self.store.first_index()
+    }
+
+    fn last_index(&self) -> Result<u64> {
+        self.store.last_index()
+    }
+
+    fn snapshot(&self) -> Result<Snapshot> {
+        self.store.snapshot()
+    }
+
+    fn append(&mut self, entries: &[Entry]) -> Result<()> {
+        if self.unavailable.get() {
+            return Err(Error::Store(StorageError::Unavailable));
+        }
+        self.appended.push(entries.to_vec());
+        Ok(())
+    }
+}
+
+// Only a compacted log makes the leader send a snapshot, other storage errors don't.
+#[test]
+fn test_send_append_storage_unavailable() {
+    setup_for_test();
+    let store = MemStorage::new_with_conf_state((vec![1, 2], vec![]));
+    store
+        .wl()
+        .append(&[empty_entry(1, 2), empty_entry(1, 3), empty_entry(1, 4)])
+        .unwrap();
+    let mut r = Raft::new(&new_test_config(1, 10, 1), FlakyStorage::new(store)).unwrap();
+    r.become_candidate();
+    r.become_leader();
+    r.msgs.clear();
+
+    r.get_store().unavailable.set(true);
+    r.mut_prs().get_mut(2).unwrap().next_idx = 3;
+    r.bcast_append();
+    assert!(r.msgs.is_empty(), "{:?}", r.msgs);
+    assert_eq!(r.prs().get(2).unwrap().state, ProgressState::Probe);
+
+    r.get_store().unavailable.set(false);
+    r.bcast_append();
+    assert_eq!(r.msgs.len(), 1);
+    assert_eq!(r.msgs[0].get_msg_type(), MessageType::MsgAppend);
+    assert_eq!(r.msgs[0].get_index(), 2);
+}
+
+// The entries becoming stable are handed to `Storage::append`.
+#[test]
+fn test_stable_to_appends_to_storage() {
+    setup_for_test();
+    let mut raft_log = RaftLog::new(FlakyStorage::new(MemStorage::new()), String::new());
+    raft_log.append(&[empty_entry(1, 1), empty_entry(1, 2), empty_entry(2, 3)]);
+
+    // A mismatched term stabilizes nothing.
+    raft_log.stable_to(2, 2).expect("");
+    assert!(raft_log.store.appended.is_empty());
+
+    // A failed append is returned, and the entries are kept unstable.
+    raft_log.store.unavailable.set(true);
+    assert_eq!(
+        raft_log.stable_to(2, 1),
+        Err(Error::Store(StorageError::Unavailable))
+    );
+    assert_eq!(raft_log.unstable.offset, 1);
+    raft_log.store.unavailable.set(false);
+
+    raft_log.stable_to(2, 1).expect("");
+    raft_log.stable_to(3, 2).expect("");
+    raft_log.stable_to(3, 2).expect("");
+    assert_eq!(
+        raft_log.store.appended,
+        vec![
+            vec![empty_entry(1, 1), empty_entry(1, 2)],
+            vec![empty_entry(2, 3)],
+        ]
+    );
+}
+
+// An append identical to the last one sent to a peer is only sent again after a heartbeat.
+#[test]
+fn test_suppress_duplicate_appends() {
+    setup_for_test();
+    for &suppress in &[false, true] {
+        let mut config = new_test_config(1, 10, 1);
+        config.suppress_duplicate_appends = suppress;
+        let store = new_storage();
+        store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+        let mut r = new_test_raft_with_config(&config, store);
+        r.become_candidate();
+        r.become_leader();
+        let last_index = r.raft_log.last_index();
+        for id in 2..=3 {
+            let pr = r.mut_prs().get_mut(id).unwrap();
+            pr.become_replicate();
+            pr.matched = last_index;
+            pr.next_idx = last_index + 1;
+        }
+        r.msgs.clear();
+
+        r.bcast_append();
+        assert_eq!(r.msgs.len(), 2);
+        r.msgs.clear();
+        r.bcast_append();
+        assert_eq!(r.msgs.len(), if suppress { 0 } else { 2 });
+        r.msgs.clear();
+
+        // A new entry changes the appends.
+        r.append_entry(&mut [empty_entry(0, 0)]).unwrap();
+        r.bcast_append();
+        assert_eq!(r.msgs.len(), 2);
+        r.msgs.clear();
+
+        r.bcast_append();
+        assert_eq!(r.msgs.len(), 2);
+        r.msgs.clear();
+        r.bcast_append();
+        assert_eq!(r.msgs.len(), if suppress { 0 } else { 2 });
+        r.msgs.clear();
+
+        r.bcast_heartbeat();
+        r.msgs.clear();
+        r.bcast_append();
+        assert_eq!(r.msgs.len(), 2);
+    }
+}
+
+#[test]
+fn test_election_countdown() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.set_randomized_election_timeout(15);
+    assert_eq!(r.election_elapsed_ticks(), 0);
+    assert_eq!(r.ticks_until_election(), 15);
+
+    for _ in 0..4 {
+        r.tick();
+    }
+    assert_eq!(r.election_elapsed_ticks(), 4);
+    assert_eq!(r.ticks_until_election(), 11);
+    let status = Status::new(&r);
+    assert_eq!(status.election_elapsed, 4);
+    assert_eq!(status.ticks_until_election, 11);
+
+    // Hearing from the leader resets the countdown.
+    r.step(new_message(2, 1, MessageType::MsgHeartbeat, 0))
+        .unwrap();
+    assert_eq!(r.election_elapsed_ticks(), 0);
+    assert_eq!(
+        r.ticks_until_election(),
+        r.get_randomized_election_timeout()
+    );
+}
+
+// Proposals received without a leader are kept up to the limit, and forwarded once a leader is
+// known.
+#[test]
+fn test_buffer_proposals_without_leader() {
+    setup_for_test();
+    let mut config = new_test_config(1, 10, 1);
+    config.buffer_proposals_without_leader = 2;
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    r.become_candidate();
+    let term = r.term;
+    for i in 0..3 {
+        let mut m = new_message(1, 1, MessageType::MsgPropose, 0);
+        m.set_entries(vec![new_entry(0, 0, Some(&format!("{}", i)))]);
+        let res = r.step(m);
+        if i < 2 {
+            assert_eq!(res, Ok(()));
+        } else {
+            assert_eq!(res, Err(Error::ProposalDropped));
+        }
+    }
+    r.msgs.clear();
+
+    let mut heartbeat = new_message(2, 1, MessageType::MsgHeartbeat, 0);
+    heartbeat.set_term(term);
+    r.step(heartbeat).unwrap();
+    assert_eq!(r.state, StateRole::Follower);
+    let forwarded: Vec<_> = r
+        .msgs
+        .iter()
+        .filter(|m| m.get_msg_type() == MessageType::MsgPropose)
+        .map(|m| (m.get_to(), m.get_entries()[0].get_data().to_vec()))
+        .collect();
+    assert_eq!(forwarded, vec![(2, b"0".to_vec()), (2, b"1".to_vec())]);
+
+    // A candidate winning the election proposes them itself.
+    let mut config = new_test_config(1, 10, 1);
+    config.buffer_proposals_without_leader = 2;
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1], vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    r.become_candidate();
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+        .unwrap();
+    let last_index = r.raft_log.last_index();
+    r.become_leader();
+    // The no-op, then the proposal.
+    let last_index = last_index + 2;
+    assert_eq!(r.raft_log.last_index(), last_index);
+    assert_eq!(r.raft_log.term(last_index), Ok(r.term));
+    assert!(!r.raft_log.entries(last_index, None).unwrap()[0]
+        .get_data()
+        .is_empty());
+
+    // Nothing is buffered if proposals can't be forwarded.
+    let mut config = new_test_config(1, 10, 1);
+    config.buffer_proposals_without_leader = 2;
+    config.disable_proposal_forwarding = true;
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    assert_eq!(
+        r.step(new_message(1, 1, MessageType::MsgPropose, 1)),
+        Err(Error::ProposalDropped)
+    );
+}
+
+// A buffered proposal dropped when stepped again is handed to the dropped proposal hook.
+#[test]
+fn test_dropped_proposal_hook() {
+    setup_for_test();
+    let dropped = Arc::new(Mutex::new(vec![]));
+    let sink = Arc::clone(&dropped);
+    let mut config = new_test_config(1, 10, 1);
+    config.buffer_proposals_without_leader = 2;
+    config.dropped_proposal_hook = Some(Arc::new(move |m| sink.lock().unwrap().push(m)));
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    r.become_candidate();
+    let m = new_message(1, 1, MessageType::MsgPropose, 1);
+    r.step(m.clone()).unwrap();
+    assert!(dropped.lock().unwrap().is_empty());
+
+    // Demoted while campaigning, the new leader drops the proposals it can't take.
+    r.mut_prs().remove(1).unwrap();
+    r.mut_prs()
+        .insert_learner(1, Progress::new(1, 256))
+        .unwrap();
+    r.become_leader();
+    assert_eq!(*dropped.lock().unwrap(), vec![m]);
+}
+
+// The progress state hook observes every transition of a peer's progress on the leader.
+#[test]
+fn test_progress_state_hook() {
+    setup_for_test();
+    let transitions = Arc::new(Mutex::new(vec![]));
+    let sink = transitions.clone();
+    let mut config = new_test_config(1, 10, 1);
+    config.progress_state_hook = Some(Arc::new(move |id, from, to| {
+        sink.lock().unwrap().push((id, from, to))
+    }));
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    r.become_candidate();
+    r.become_leader();
+    let last_index = r.raft_log.last_index();
+
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.set_term(r.term);
+    m.set_index(last_index);
+    r.step(m).unwrap();
+    r.step(new_message(2, 1, MessageType::MsgUnreachable, 0))
+        .unwrap();
+    r.set_peer_snapshot_pending(3, last_index).unwrap();
+    r.step(new_message(3, 1, MessageType::MsgSnapStatus, 0))
+        .unwrap();
+
+    assert_eq!(
+        *transitions.lock().unwrap(),
+        vec![
+            (2, ProgressState::Probe, ProgressState::Replicate),
+            (2, ProgressState::Replicate, ProgressState::Probe),
+            (3, ProgressState::Probe, ProgressState::Snapshot),
+            (3, ProgressState::Snapshot, ProgressState::Probe),
+        ]
+    );
+}
+
+#[test]
+fn test_commit_hook() {
+    setup_for_test();
+    let commits = Arc::new(Mutex::new(vec![]));
+    let new_raft = |id| {
+        let sink = commits.clone();
+        let mut config = new_test_config(id, 10, 1);
+        config.commit_hook = Some(Arc::new(move |old, new| {
+            sink.lock().unwrap().push((id, old, new))
+        }));
+        let store = new_storage();
+        store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+        new_test_raft_with_config(&config, store)
+    };
+
+    // The leader reports commits once a quorum acknowledged the entries.
+    let mut r = new_raft(1);
+    r.become_candidate();
+    r.become_leader();
+    let last_index = r.raft_log.last_index();
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.set_term(r.term);
+    m.set_index(last_index);
+    r.step(m.clone()).unwrap();
+    // Acknowledging again doesn't advance the commit index.
+    r.step(m).unwrap();
+    assert_eq!(*commits.lock().unwrap(), vec![(1, 1, last_index)]);
+    commits.lock().unwrap().clear();
+
+    // A follower reports the commit index carried by appends and heartbeats.
+    let mut r = new_raft(2);
+    r.become_follower(1, 1);
+    let mut m = new_message(1, 2, MessageType::MsgAppend, 0);
+    m.set_term(1);
+    m.set_log_term(1);
+    m.set_index(1);
+    m.set_commit(2);
+    m.set_entries(vec![empty_entry(1, 2), empty_entry(1, 3)]);
+    r.step(m).unwrap();
+    let mut m = new_message(1, 2, MessageType::MsgHeartbeat, 0);
+    m.set_term(1);
+    m.set_commit(3);
+    r.step(m.clone()).unwrap();
+    r.step(m).unwrap();
+    assert_eq!(*commits.lock().unwrap(), vec![(2, 1, 2), (2, 2, 3)]);
+}
+
+#[test]
+fn test_step_and_take() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.msgs
+        .push(new_message(1, 2, MessageType::MsgHeartbeatResponse, 0));
+
+    let mut msgs = r
+        .step_and_take(new_message(1, 1, MessageType::MsgHup, 0))
+        .unwrap();
+    msgs.sort_by_key(|m| m.get_to());
+    assert_eq!(msgs.len(), 2);
+    for (m, to) in msgs.iter().zip(2..) {
+        assert_eq!(m.get_msg_type(), MessageType::MsgRequestVote);
+        assert_eq!(m.get_to(), to);
+    }
+    // Messages queued before are left alone.
+    assert_eq!(r.msgs.len(), 1);
+    assert_eq!(r.msgs[0].get_msg_type(), MessageType::MsgHeartbeatResponse);
+
+    assert_eq!(
+        r.step_and_take(new_message(1, 1, MessageType::MsgPropose, 1)),
+        Err(Error::ProposalDropped)
+    );
+    assert_eq!(r.msgs.len(), 1);
+}
+
+#[test]
+fn test_append_entry_not_leader() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+    let last_index = r.raft_log.last_index();
+    assert_eq!(
+        r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]),
+        Err(Error::NotLeader)
+    );
+    r.become_candidate();
+    assert_eq!(
+        r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]),
+        Err(Error::NotLeader)
+    );
+    assert_eq!(r.raft_log.last_index(), last_index);
+
+    r.become_leader();
+    r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]).unwrap();
+    assert_eq!(r.raft_log.last_index(), last_index + 2);
+}
+
+#[test]
+fn test_propose_batch() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    assert_eq!(r.propose_batch(vec![b"a".to_vec()]), Err(Error::NotLeader));
+    r.become_candidate();
+    r.become_leader();
+    r.msgs.clear();
+
+    let last_index = r.raft_log.last_index();
+    let indices = r
+        .propose_batch(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()])
+        .unwrap();
+    assert_eq!(
+        indices,
+        vec![last_index + 1, last_index + 2, last_index + 3]
+    );
+    for (index, data) in indices.iter().zip(&[b"a", b"b", b"c"]) {
+        let ents = r.raft_log.entries(*index, 1).unwrap();
+        assert_eq!(ents[0].get_data(), &data[..]);
+        assert_eq!(ents[0].get_term(), r.term);
+    }
+    // A single append per follower carries the whole batch.
+    let msgs = r.read_messages();
+    assert_eq!(msgs.len(), 2);
+    for m in &msgs {
+        assert_eq!(m.get_msg_type(), MessageType::MsgAppend);
+    }
+
+    // The whole batch is rejected during a leadership transfer.
+    let last_index = r.raft_log.last_index();
+    r.step(new_message(3, 1, MessageType::MsgTransferLeader, 0))
+        .unwrap();
+    assert_eq!(
+        r.propose_batch(vec![b"d".to_vec(), b"e".to_vec()]),
+        Err(Error::ProposalDropped)
+    );
+    assert_eq!(r.raft_log.last_index(), last_index);
+}
+
+// A follower rejects a snapshot ending at its committed index with another term, and keeps
+// its committed log.
+#[test]
+fn test_snapshot_conflicting_with_committed_log() {
+    setup_for_test();
+    let mut sm = new_test_raft(2, vec![1, 2], 10, 1, new_storage());
+    sm.raft_log.append(&[empty_entry(1, 2), empty_entry(1, 3)]);
+    sm.raft_log.commit_to(3);
+    sm.become_follower(3, 1);
+
+    let mut m = new_message(1, 2, MessageType::MsgSnapshot, 0);
+    m.set_term(3);
+    m.set_snapshot(new_snapshot(3, 2, vec![1, 2]));
+    sm.step(m).unwrap();
+
+    assert_eq!(sm.raft_log.committed, 3);
+    assert_eq!(sm.raft_log.last_index(), 3);
+    assert_eq!(sm.raft_log.term(3), Ok(1));
+    let msgs = sm.read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgAppendResponse);
+    assert_eq!(msgs[0].get_index(), 3);
+}
+
+// Votes are granted against the log as truncated by a conflicting append, even though the
+// conflicting suffix is still in the storage until the truncation is persisted.
+#[test]
+fn test_vote_after_unstable_truncation() {
+    setup_for_test();
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    store
+        .wl()
+        .append(&[
+            empty_entry(2, 2),
+            empty_entry(2, 3),
+            empty_entry(2, 4),
+            empty_entry(2, 5),
+        ])
+        .unwrap();
+    let mut r = new_test_raft_with_config(&new_test_config(1, 10, 1), store);
+    r.become_follower(3, 2);
+
+    let mut m = new_message(2, 1, MessageType::MsgAppend, 0);
+    m.set_term(3);
+    m.set_index(2);
+    m.set_log_term(2);
+    m.set_entries(vec![empty_entry(3, 3)]);
+    r.step(m).unwrap();
+    assert_eq!(r.raft_log.last_index(), 3);
+    assert_eq!(r.raft_log.last_term(), 3);
+    assert_eq!(r.raft_log.get_store().last_index(), Ok(5));
+
+    // (4, 3) is more up-to-date than the truncated log, (5, 2) only than the stale one. Each
+    // vote is requested in a new term, so the first one granted doesn't decide the second.
+    for &(term, index, log_term, reject) in &[(4, 4, 3, false), (5, 5, 2, true)] {
+        let mut m = new_message(3, 1, MessageType::MsgRequestVote, 0);
+        m.set_term(term);
+        m.set_index(index);
+        m.set_log_term(log_term);
+        r.step(m).unwrap();
+        let msgs = r.read_messages();
+        let resp = msgs
+            .iter()
+            .find(|m| m.get_msg_type() == MessageType::MsgRequestVoteResponse)
+            .unwrap();
+        assert_eq!(
+            resp.get_reject(),
+            reject,
+            "vote for ({}, {})",
+            index,
+            log_term
+        );
+    }
+}
+
+#[test]
+fn test_config_is_learner() {
+    setup_for_test();
+    let mut config = new_test_config(4, 10, 1);
+    config.is_learner = true;
+
+    // Not in the conf state yet.
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    assert!(r.is_learner);
+    for _ in 0..20 {
+        r.tick();
+    }
+    assert_eq!(r.state, StateRole::Follower);
+    assert!(r.read_messages().is_empty());
+
+    // Already a learner in the conf state.
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![4]));
+    assert!(new_test_raft_with_config(&config, store).is_learner);
+
+    // A voter can't be started as a learner.
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3, 4], vec![]));
+    match Raft::new(&config, store) {
+        Err(Error::ConfigInvalid(_)) => {}
+        res => panic!("expected ConfigInvalid, got {:?}", res.map(|r| r.state)),
+    }
+}
+
+#[test]
+fn test_unresponsive_peers() {
+    setup_for_test();
+    let mut config = new_test_config(1, 10, 1);
+    config.heartbeat_response_timeout_ticks = 3;
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    r.become_candidate();
+    r.become_leader();
+
+    let term = r.term;
+    let heartbeat_resp = |from| {
+        let mut m = new_message(from, 1, MessageType::MsgHeartbeatResponse, 0);
+        m.set_term(term);
+        m
+    };
+    for _ in 0..3 {
+        r.tick();
+        r.step(heartbeat_resp(2)).unwrap();
+        assert_eq!(r.unresponsive_peers(), Vec::<u64>::new());
+    }
+    // 3 ticks since its first unanswered heartbeat.
+    r.tick();
+    r.step(heartbeat_resp(2)).unwrap();
+    assert_eq!(r.unresponsive_peers(), vec![3]);
+    assert_eq!(Status::new(&r).unresponsive_peers, vec![3]);
+
+    r.step(heartbeat_resp(3)).unwrap();
+    assert_eq!(r.unresponsive_peers(), Vec::<u64>::new());
+}
+
+// A read whose first heartbeats are lost resolves on the round sent by `flush_read_index`.
+#[test]
+fn test_flush_read_index() {
+    setup_for_test();
+    let s = new_storage();
+    s.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, s.clone());
+    r.become_candidate();
+    r.become_leader();
+    commit_noop_entry(&mut r, &s);
+
+    // Nothing to flush.
+    r.flush_read_index();
+    assert!(r.read_messages().is_empty());
+
+    for ctx in &["ctx1", "ctx2"] {
+        r.step(new_message_with_entries(
+            1,
+            1,
+            MessageType::MsgReadIndex,
+            vec![new_entry(0, 0, Some(ctx))],
+        ))
+        .unwrap();
+    }
+    assert_eq!(
+        r.read_only.pending_contexts(),
+        vec![b"ctx1".to_vec(), b"ctx2".to_vec()]
+    );
+    // The heartbeats carrying the reads are lost.
+    r.read_messages();
+
+    r.flush_read_index();
+    let msgs = r.read_messages();
+    assert_eq!(msgs.len(), 2);
+    for m in msgs {
+        assert_eq!(m.get_msg_type(), MessageType::MsgHeartbeat);
+        assert_eq!(m.get_context(), b"ctx2");
+        let mut resp = new_message(m.get_to(), 1, MessageType::MsgHeartbeatResponse, 0);
+        resp.set_term(r.term);
+        resp.set_context(m.get_context().to_vec());
+        r.step(resp).unwrap();
+    }
+    let ctxs: Vec<_> = r
+        .read_states
+        .iter()
+        .map(|rs| rs.request_ctx.clone())
+        .collect();
+    assert_eq!(ctxs, vec![b"ctx1".to_vec(), b"ctx2".to_vec()]);
+    assert!(r.read_only.pending_contexts().is_empty());
+}
+
+#[test]
+fn test_force_commit_with_quorum_override() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2, 3, 4, 5], 10, 1, new_storage());
+    assert_eq!(
+        r.force_commit_with_quorum_override(2),
+        Err(Error::NotLeader)
+    );
+    r.become_candidate();
+    r.become_leader();
+    let last_index = r.raft_log.last_index();
+    let committed = r.raft_log.committed;
+    assert!(committed < last_index);
+    // Only 2 of 5 voters survive.
+    r.mut_prs().get_mut(2).unwrap().maybe_update(last_index);
+    assert!(!r.maybe_commit());
+
+    for &quorum in &[0, 6] {
+        match r.force_commit_with_quorum_override(quorum) {
+            Err(Error::ViolatesContract(_)) => {}
+            res => panic!("quorum {}: {:?}", quorum, res),
+        }
+    }
+    // 3 voters haven't acknowledged the no-op.
+    assert_eq!(r.force_commit_with_quorum_override(3), Ok(false));
+    r.read_messages();
+    assert_eq!(r.force_commit_with_quorum_override(2), Ok(true));
+    assert_eq!(r.raft_log.committed, last_index);
+    // The new commit index is broadcast.
+    assert_eq!(r.read_messages().len(), 4);
+    // It's not sticky.
+    r.append_entry(&mut [new_entry(0, 0, SOME_DATA)]).unwrap();
+    r.mut_prs().get_mut(2).unwrap().maybe_update(last_index + 1);
+    assert!(!r.maybe_commit());
+}
+
+#[test]
+fn test_commit_apply_out_of_range() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+    r.raft_log.append(&[empty_entry(1, 2), empty_entry(1, 3)]);
+    r.raft_log.commit_to(2);
+    r.commit_apply(2).unwrap();
+
+    assert_eq!(r.commit_apply(3), Err(Error::AppliedOutOfRange(3, 2, 2)));
+    assert_eq!(r.commit_apply(1), Err(Error::AppliedOutOfRange(1, 2, 2)));
+    assert_eq!(r.raft_log.applied, 2);
+
+    let mut config = new_test_config(1, 10, 1);
+    config.applied = 5;
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2], vec![]));
+    assert_eq!(
+        Raft::new(&config, store).err(),
+        Some(Error::AppliedOutOfRange(5, 1, 1))
+    );
+}
+
+#[test]
+fn test_message_metrics() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).unwrap();
+    let mut resp = new_message(2, 1, MessageType::MsgRequestVoteResponse, 0);
+    resp.set_term(r.term);
+    r.step(resp).unwrap();
+    assert_eq!(r.state, StateRole::Leader);
+
+    let metrics = r.message_metrics();
+    assert_eq!(metrics.received(MessageType::MsgHup), 1);
+    assert_eq!(metrics.received(MessageType::MsgRequestVoteResponse), 1);
+    assert_eq!(metrics.sent(MessageType::MsgRequestVote), 2);
+    // The no-op of the new leader.
+    assert_eq!(metrics.sent(MessageType::MsgAppend), 2);
+    assert_eq!(metrics.sent(MessageType::MsgSnapshot), 0);
+
+    assert_eq!(r.take_message_metrics(), metrics);
+    assert_eq!(r.message_metrics(), MessageMetrics::default());
+    r.ping();
+    assert_eq!(r.message_metrics().sent(MessageType::MsgHeartbeat), 2);
+}
+
+// A corrupted conf state fails creating the raft instead of panicking.
+#[test]
+fn test_new_raft_with_inconsistent_conf_state() {
+    setup_for_test();
+    let tests = vec![
+        (vec![1, 2], vec![2], Error::Exists(2, "voters")),
+        (vec![1, 2, 2], vec![], Error::Exists(2, "voters")),
+        (vec![1], vec![3, 3], Error::Exists(3, "learners")),
+    ];
+    for (i, (voters, learners, err)) in tests.into_iter().enumerate() {
+        let store = new_storage();
+        store.initialize_with_conf_state((vec![1], vec![]));
+        let mut cs = ConfState::default();
+        cs.set_nodes(voters);
+        cs.set_learners(learners);
+        store.wl().set_conf_state(cs, None);
+        match Raft::new(&new_test_config(1, 10, 1), store) {
+            Err(e) => assert_eq!(e, err, "#{}", i),
+            Ok(_) => panic!("#{}: expect an error", i),
+        }
+    }
+}
+
+#[test]
+fn test_panic_on_applied_regression() {
+    setup_for_test();
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2], vec![]));
+    store
+        .wl()
+        .append(&[empty_entry(1, 2), empty_entry(1, 3), empty_entry(1, 4)])
+        .unwrap();
+    store.wl().mut_hard_state().set_commit(4);
+    // An unset applied index isn't checked.
+    for &applied in &[0, 2, 4] {
+        let mut config = new_test_config(1, 10, 1);
+        config.applied = applied;
+        config.panic_on_applied_regression = true;
+        assert!(Raft::new(&config, store.clone()).is_ok(), "{}", applied);
+    }
+
+    // Behind the snapshot.
+    let store = new_storage();
+    store
+        .wl()
+        .apply_snapshot(new_snapshot(5, 1, vec![1, 2]))
+        .unwrap();
+    let mut config = new_test_config(1, 10, 1);
+    config.applied = 3;
+    config.panic_on_applied_regression = true;
+    assert_eq!(
+        Raft::new(&config, store.clone()).err(),
+        Some(Error::ConfigInvalid(
+            "applied 3 regressed, snapshot index: 5".to_owned()
+        ))
+    );
+    // Only warned about by default, resuming from the snapshot.
+    config.panic_on_applied_regression = false;
+    let r = Raft::new(&config, store).unwrap();
+    assert_eq!(r.raft_log.applied, 5);
+}
+
+// A leader stepping down campaigns later, and the transfer target retries sooner.
+#[test]
+fn test_transfer_election_bias() {
+    setup_for_test();
+    for _ in 0..20 {
+        let mut config = new_test_config(1, 10, 1);
+        config.transfer_election_bias_ticks = 5;
+        let store = new_storage();
+        store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+        let mut r = new_test_raft_with_config(&config, store);
+        r.become_candidate();
+        r.become_leader();
+        let term = r.term;
+        r.become_follower(term + 1, 2);
+        let timeout = r.get_randomized_election_timeout();
+        assert!(timeout >= 15 && timeout < 25, "timeout {}", timeout);
+
+        let store = new_storage();
+        store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+        let mut r = new_test_raft_with_config(&config, store);
+        r.become_follower(1, 2);
+        let mut m = new_message(2, 1, MessageType::MsgTimeoutNow, 0);
+        m.set_term(1);
+        r.step(m).unwrap();
+        assert_eq!(r.state, StateRole::Candidate);
+        let timeout = r.get_randomized_election_timeout();
+        assert!(timeout >= 5 && timeout < 15, "timeout {}", timeout);
+    }
+
+    // Disabled, the timeout isn't biased.
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    let term = r.term;
+    r.become_follower(term + 1, 2);
+    assert!(r.get_randomized_election_timeout() < 20);
+}
+
+#[test]
+fn test_append_entry_index_gap() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    let li = r.raft_log.last_index();
+    // Unset or contiguous indices are fine.
+    r.append_entry(&mut [new_entry(0, 0, SOME_DATA), new_entry(0, li + 2, SOME_DATA)])
+        .unwrap();
+    assert_eq!(r.raft_log.last_index(), li + 2);
+
+    let li = r.raft_log.last_index();
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        r.append_entry(&mut [
+            new_entry(0, li + 1, SOME_DATA),
+            new_entry(0, li + 3, SOME_DATA),
+        ])
+    }));
+    assert_eq!(res.is_err(), cfg!(debug_assertions));
+}
+
+#[test]
+fn test_peer_progress() {
+    setup_for_test();
+    let mut r = new_test_learner_raft(1, vec![1, 2], vec![3], 10, 1, new_storage());
+    assert_eq!(r.peer_progress(2), None);
+    r.become_candidate();
+    r.become_leader();
+    let li = r.raft_log.last_index();
+    {
+        let pr = r.mut_prs().get_mut(2).unwrap();
+        pr.matched = li;
+        pr.become_replicate();
+    }
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+        .unwrap();
+
+    let pr = r.peer_progress(2).unwrap();
+    assert_eq!(pr.state, ProgressState::Replicate);
+    assert_eq!(pr.next_idx, li + 2);
+    assert_eq!(pr.inflight_count, 1);
+    assert!(!pr.is_learner);
+    assert!(r.peer_progress(3).unwrap().is_learner);
+    assert_eq!(r.peer_progress(4), None);
+
+    let status = Status::new(&r);
+    assert_eq!(status.peer_progress(2), r.peer_progress(2));
+    assert_eq!(status.peer_progress(3), r.peer_progress(3));
+    assert_eq!(status.peer_progress(4), None);
+}
+
+#[test]
+fn test_deterministic_broadcast_order() {
+    setup_for_test();
+    let peers = vec![1, 2, 3, 4, 5, 6, 7];
+    let mut config = new_test_config(1, 10, 1);
+    config.deterministic_broadcast_order = true;
+    let store = new_storage();
+    store.initialize_with_conf_state((peers.clone(), vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    let to = |msgs: Vec<Message>| msgs.iter().map(|m| m.get_to()).collect::<Vec<u64>>();
+
+    r.campaign(b"CampaignElection");
+    assert_eq!(to(r.read_messages()), peers[1..].to_vec());
+
+    for &id in &peers[1..] {
+        let mut m = new_message(id, 1, MessageType::MsgRequestVoteResponse, 0);
+        m.set_term(r.term);
+        r.step(m).unwrap();
+    }
+    assert_eq!(r.state, StateRole::Leader);
+    assert_eq!(to(r.read_messages()), peers[1..].to_vec());
+
+    r.bcast_heartbeat();
+    assert_eq!(to(r.read_messages()), peers[1..].to_vec());
+}
+
+#[test]
+fn test_advance_persisted() {
+    setup_for_test();
+    let mut config = new_test_config(1, 10, 1);
+    config.async_storage_writes = true;
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1], vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    let li = r.raft_log.last_index();
+    assert_eq!(r.persisted(), li);
+
+    // A single node cluster can't commit its no-op before persisting it.
+    r.become_candidate();
+    r.become_leader();
+    assert_eq!(r.raft_log.last_index(), li + 1);
+    assert_eq!(r.raft_log.committed, li);
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+        .unwrap();
+    assert_eq!(r.raft_log.committed, li);
+
+    // The entry persisted was overwritten since.
+    let term = r.term;
+    r.advance_persisted(li + 1, term - 1);
+    assert_eq!(r.persisted(), li);
+    assert_eq!(r.raft_log.committed, li);
+
+    r.advance_persisted(li + 1, term);
+    assert_eq!(r.persisted(), li + 1);
+    assert_eq!(r.raft_log.committed, li + 1);
+    // Beyond the log, or going backwards.
+    r.advance_persisted(li + 3, term);
+    r.advance_persisted(li, 0);
+    assert_eq!(r.persisted(), li + 1);
+    r.advance_persisted(li + 2, term);
+    assert_eq!(r.raft_log.committed, li + 2);
+
+    // The quorum of a larger cluster may commit without the leader having persisted.
+    let mut config = new_test_config(1, 10, 1);
+    config.async_storage_writes = true;
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut r = new_test_raft_with_config(&config, store);
+    r.become_candidate();
+    r.become_leader();
+    let li = r.raft_log.last_index();
+    for id in 2..4 {
+        let mut m = new_message(id, 1, MessageType::MsgAppendResponse, 0);
+        m.set_term(r.term);
+        m.set_index(li);
+        r.step(m).unwrap();
+    }
+    assert_eq!(r.raft_log.committed, li);
+    assert_eq!(r.prs().get(1).unwrap().matched, li - 1);
+}
+
+#[test]
+fn test_term_of() {
+    setup_for_test();
+    let store = new_storage();
+    store
+        .wl()
+        .apply_snapshot(new_snapshot(3, 1, vec![1, 2]))
+        .unwrap();
+    store
+        .wl()
+        .append(&[empty_entry(2, 4), empty_entry(3, 5)])
+        .unwrap();
+    let mut r = new_test_raft(1, vec![1, 2], 10, 1, store);
+    r.become_candidate();
+    r.become_leader();
+    let term = r.term;
+
+    // Compacted away.
+    assert_eq!(r.term_of(2), None);
+    // The last compacted entry, from the snapshot.
+    assert_eq!(r.term_of(3), Some(1));
+    assert_eq!(r.term_of(4), Some(2));
+    assert_eq!(r.term_of(5), Some(3));
+    // The unstable no-op of the leader.
+    assert_eq!(r.term_of(6), Some(term));
+    assert_eq!(r.term_of(7), None);
+    assert_eq!(r.term_of(u64::MAX), None);
+}
+
+#[test]
+fn test_election_timeout_strategy() {
+    setup_for_test();
+    // Lower ids time out sooner, and a call counter shows the strategy is used.
+    struct ById(usize);
+    impl ElectionTimeoutStrategy for ById {
+        fn next_election_timeout(&mut self, min: usize, _: usize, node_id: u64) -> usize {
+            self.0 += 1;
+            min + node_id as usize * 3
+        }
+    }
+    let strategy = Arc::new(Mutex::new(ById(0)));
+    let tests = vec![(1, 13), (2, 16), (3, 19), (4, 19)];
+    for (id, timeout) in tests {
+        let mut config = new_test_config(id, 10, 1);
+        config.election_timeout_strategy = Some(strategy.clone());
+        let store = new_storage();
+        store.initialize_with_conf_state((vec![1, 2, 3, 4], vec![]));
+        let mut r = new_test_raft_with_config(&config, store);
+        // Values past the range are clamped.
+        assert_eq!(r.get_randomized_election_timeout(), timeout, "#{}", id);
+        r.reset_randomized_election_timeout();
+        assert_eq!(r.get_randomized_election_timeout(), timeout, "#{}", id);
+    }
+    assert_eq!(strategy.lock().unwrap().0, 8);
+}
+
+#[test]
+fn test_next_entries() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    let applied = r.raft_log.applied;
+    let noop = r.raft_log.committed;
+    for _ in 0..3 {
+        r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+            .unwrap();
+    }
+    let committed = r.raft_log.committed;
+    assert_eq!(committed, noop + 3);
+
+    let all = r.next_entries(None).unwrap();
+    assert_eq!(all.len() as u64, committed - applied);
+    assert_eq!(all[0].get_index(), applied + 1);
+    assert_eq!(all.last().unwrap().get_index(), committed);
+
+    // Bounded batches, holding at least one entry.
+    let size = all[1].encoded_len() as u64 + all[2].encoded_len() as u64;
+    r.commit_apply(noop).unwrap();
+    let batch = r.next_entries(Some(size)).unwrap();
+    assert_eq!(batch, all[(noop - applied) as usize..][..2].to_vec());
+    let batch = r.next_entries(Some(0)).unwrap();
+    assert_eq!(batch.len(), 1);
+
+    r.commit_apply(committed).unwrap();
+    assert_eq!(r.next_entries(None), Ok(vec![]));
+}
+
+// A non-member, e.g. a proxy, gets the read index back at its return address, and doesn't
+// count towards the quorum confirming it.
+#[test]
+fn test_read_index_return_address() {
+    setup_for_test();
+    let store = new_storage();
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, store.clone());
+    r.become_candidate();
+    r.become_leader();
+    commit_noop_entry(&mut r, &store);
+    let committed = r.raft_log.committed;
+
+    for &from in &[INVALID_ID, 100] {
+        let mut m = new_message_with_entries(
+            from,
+            1,
+            MessageType::MsgReadIndex,
+            vec![new_entry(0, 0, Some("ctx"))],
+        );
+        m.set_return_address(b"proxy".to_vec());
+        r.step(m).unwrap();
+        r.read_messages();
+
+        let mut ack = new_message(from, 1, MessageType::MsgHeartbeatResponse, 0);
+        ack.set_term(r.term);
+        ack.set_context(b"ctx".to_vec());
+        r.step(ack).unwrap();
+        assert!(r.read_messages().is_empty(), "{}", from);
+
+        let mut ack = new_message(2, 1, MessageType::MsgHeartbeatResponse, 0);
+        ack.set_term(r.term);
+        ack.set_context(b"ctx".to_vec());
+        r.step(ack).unwrap();
+        let msgs = r.read_messages();
+        let resp: Vec<_> = msgs
+            .iter()
+            .filter(|m| m.get_msg_type() == MessageType::MsgReadIndexResp)
+            .collect();
+        assert_eq!(resp.len(), 1, "{}", from);
+        assert_eq!(resp[0].get_to(), from);
+        assert_eq!(resp[0].get_return_address(), b"proxy");
+        assert_eq!(resp[0].get_index(), committed);
+        assert_eq!(resp[0].get_entries()[0].get_data(), b"ctx");
+        assert!(r.read_states.is_empty());
+    }
+}
+
+// A read sent by a non-member to a follower is forwarded to the leader, and the follower relays
+// the response back to the return address instead of keeping it.
+#[test]
+fn test_read_index_return_address_through_follower() {
+    setup_for_test();
+    let mut nt = Network::new(vec![None, None, None]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    let committed = nt.peers[&1].raft_log.committed;
+
+    let mut m = new_message_with_entries(
+        100,
+        2,
+        MessageType::MsgReadIndex,
+        vec![new_entry(0, 0, Some("ctx"))],
+    );
+    m.set_return_address(b"proxy".to_vec());
+    // Route by hand, keeping what's sent to the proxy.
+    let mut msgs = vec![m];
+    let mut relayed = vec![];
+    while !msgs.is_empty() {
+        let mut next = vec![];
+        for m in msgs.drain(..) {
+            if m.get_to() == INVALID_ID {
+                relayed.push(m);
+                continue;
+            }
+            let p = nt.peers.get_mut(&m.get_to()).unwrap();
+            p.step(m).unwrap();
+            next.extend(p.read_messages());
+        }
+        msgs = next;
+    }
+
+    assert_eq!(relayed.len(), 1);
+    assert_eq!(relayed[0].get_msg_type(), MessageType::MsgReadIndexResp);
+    assert_eq!(relayed[0].get_from(), 2);
+    assert_eq!(relayed[0].get_term(), nt.peers[&2].term);
+    assert_eq!(relayed[0].get_return_address(), b"proxy");
+    assert_eq!(relayed[0].get_index(), committed);
+    assert_eq!(relayed[0].get_entries()[0].get_data(), b"ctx");
+    assert!(nt.peers[&2].read_states.is_empty());
+}
+
+#[test]
+fn test_reset_election_timer() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.set_randomized_election_timeout(15);
+    for _ in 0..14 {
+        r.tick();
+    }
+    r.reset_election_timer();
+    assert_eq!(r.election_elapsed_ticks(), 0);
+    // The election is put off by a whole timeout.
+    for _ in 0..14 {
+        r.tick();
+    }
+    assert_eq!(r.state, StateRole::Follower);
+    r.tick();
+    assert_eq!(r.state, StateRole::Candidate);
+
+    // Leaders keep their timer for the quorum check.
+    r.become_leader();
+    r.tick();
+    r.reset_election_timer();
+    assert_eq!(r.election_elapsed_ticks(), 1);
+}
+
+#[test]
+fn test_leader_epoch() {
+    setup_for_test();
+    let mut nt = Network::new(vec![None, None, None]);
+    assert_eq!(nt.peers[&1].leader_epoch(), 0);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].leader_epoch(), 1);
+
+    // Losing and regaining leadership moves to a new epoch, never shared with another leader.
+    nt.send(vec![new_message(2, 2, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Follower);
+    assert_eq!(nt.peers[&1].leader_epoch(), 1);
+    assert_eq!(nt.peers[&2].leader_epoch(), 2);
+
+    // A failed election advances the term but not the epoch.
+    nt.isolate(1);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Candidate);
+    assert_eq!(nt.peers[&1].term, 3);
+    assert_eq!(nt.peers[&1].leader_epoch(), 1);
+
+    nt.recover();
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert_eq!(nt.peers[&1].leader_epoch(), 4);
+}
+
+#[test]
+fn test_count_learners_in_read_quorum() {
+    setup_for_test();
+    for &count_learners in &[false, true] {
+        let mut config = new_test_config(1, 10, 1);
+        config.count_learners_in_read_quorum = count_learners;
+        let store = new_storage();
+        store.initialize_with_conf_state((vec![1, 2, 3], vec![4]));
+        let mut r = new_test_raft_with_config(&config, store.clone());
+        r.become_candidate();
+        r.become_leader();
+        commit_noop_entry(&mut r, &store);
+
+        r.step(new_message_with_entries(
+            1,
+            1,
+            MessageType::MsgReadIndex,
+            vec![new_entry(0, 0, Some("ctx"))],
+        ))
+        .unwrap();
+        let mut ack = new_message(4, 1, MessageType::MsgHeartbeatResponse, 0);
+        ack.set_term(r.term);
+        ack.set_context(b"ctx".to_vec());
+        r.step(ack).unwrap();
+        assert_eq!(r.read_states.len(), count_learners as usize);
+
+        // A voter ack confirms the read either way.
+        let mut ack = new_message(2, 1, MessageType::MsgHeartbeatResponse, 0);
+        ack.set_term(r.term);
+        ack.set_context(b"ctx".to_vec());
+        r.step(ack).unwrap();
+        assert_eq!(r.read_states.len(), 1);
+    }
+}
+
+// A message echoed back to its sender is dropped rather than taken as a peer's.
+#[test]
+fn test_drop_self_addressed_messages() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    let last_index = r.raft_log.last_index();
+    r.mut_prs().get_mut(1).unwrap().matched = last_index - 1;
+    let before = r.prs().get(1).unwrap().clone();
+    r.read_messages();
+
+    let mut m = new_message(1, 1, MessageType::MsgAppendResponse, 0);
+    m.set_term(r.term);
+    m.set_index(last_index);
+    r.step(m).unwrap();
+    assert_eq!(*r.prs().get(1).unwrap(), before);
+    assert!(r.read_messages().is_empty());
+
+    // Local messages naming this node as sender are still handled.
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+        .unwrap();
+    assert_eq!(r.raft_log.last_index(), last_index + 1);
+}
+
+#[test]
+fn test_log_entries() {
+    setup_for_test();
+    let store = MemStorage::new_with_conf_state((vec![1, 2], vec![]));
+    let ents: Vec<Entry> = (2..=5).map(|index| empty_entry(1, index)).collect();
+    store.wl().append(&ents).unwrap();
+    store.wl().compact(3).unwrap();
+    let mut r = new_test_raft(1, vec![1, 2], 10, 1, store);
+    r.append_entry_unchecked(&mut [Entry::default()]);
+
+    // The range spans persisted and unstable entries, and is clamped to the log.
+    let indexes = |ents: Vec<Entry>| ents.iter().map(Entry::get_index).collect::<Vec<_>>();
+    assert_eq!(indexes(r.log_entries(4, 100).unwrap()), vec![4, 5, 6]);
+    assert_eq!(indexes(r.log_entries(5, 6).unwrap()), vec![5]);
+    assert_eq!(r.log_entries(7, 10), Ok(vec![]));
+    assert_eq!(r.log_entries(5, 5), Ok(vec![]));
+    assert_eq!(
+        r.log_entries(3, 5),
+        Err(Error::Store(StorageError::Compacted))
+    );
+}
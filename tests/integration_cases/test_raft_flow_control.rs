@@ -202,3 +202,41 @@ fn test_msg_app_flow_control_recv_heartbeat() {
         r.read_messages();
     }
 }
+
+// test_set_peer_max_inflight ensures the window of a single peer can be resized while
+// messages are in flight.
+#[test]
+fn test_set_peer_max_inflight() {
+    setup_for_test();
+    let mut r = new_test_raft(1, vec![1, 2, 3], 5, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    progress_become_replicate(&mut r, 2, 2);
+    progress_become_replicate(&mut r, 3, 2);
+
+    assert!(r.set_peer_max_inflight(4, 2).is_err());
+    assert!(r.set_peer_max_inflight(2, 0).is_err());
+    r.set_peer_max_inflight(2, 2).expect("");
+
+    let mut sent_to_2 = 0;
+    for _ in 0..4 {
+        r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+            .expect("");
+        sent_to_2 += r.read_messages().iter().filter(|m| m.get_to() == 2).count();
+    }
+    assert_eq!(sent_to_2, 2);
+    assert!(r.prs().get(2).unwrap().ins.full());
+    assert!(!r.prs().get(3).unwrap().ins.full());
+
+    // Growing the window keeps the inflights and makes room for more.
+    r.set_peer_max_inflight(2, 4).expect("");
+    assert!(!r.prs().get(2).unwrap().ins.full());
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+        .expect("");
+    let ms = r.read_messages();
+    assert_eq!(ms.iter().filter(|m| m.get_to() == 2).count(), 1);
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+        .expect("");
+    r.read_messages();
+    assert!(r.prs().get(2).unwrap().ins.full());
+}
@@ -48,9 +48,9 @@ pub fn commit_noop_entry(r: &mut Interface, s: &MemStorage) {
         .append(r.raft_log.unstable_entries().unwrap_or(&[]))
         .expect("");
     let committed = r.raft_log.committed;
-    r.commit_apply(committed);
+    r.commit_apply(committed).unwrap();
     let (last_index, last_term) = (r.raft_log.last_index(), r.raft_log.last_term());
-    r.raft_log.stable_to(last_index, last_term);
+    r.raft_log.stable_to(last_index, last_term).expect("");
 }
 
 fn accept_and_reply(m: &Message) -> Message {
@@ -125,8 +125,8 @@ fn test_leader_bcast_beat() {
     let mut r = new_test_raft(1, vec![1, 2, 3], 10, hi, new_storage());
     r.become_candidate();
     r.become_leader();
-    for i in 0..10 {
-        r.append_entry(&mut [empty_entry(0, i as u64 + 1)]);
+    for _ in 0..10 {
+        r.append_entry(&mut [empty_entry(0, 0)]).unwrap();
     }
 
     for _ in 0..hi {
@@ -275,17 +275,18 @@ fn test_follower_vote() {
     ];
 
     for (i, (vote, nvote, wreject)) in tests.drain(..).enumerate() {
-        let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+        // Node 3 votes, since a node drops the vote requests it appears to send itself.
+        let mut r = new_test_raft(3, vec![1, 2, 3], 10, 1, new_storage());
         r.load_state(&hard_state(1, 1, vote));
 
-        let mut m = new_message(nvote, 1, MessageType::MsgRequestVote, 0);
+        let mut m = new_message(nvote, 3, MessageType::MsgRequestVote, 0);
         m.set_term(1);
         m.set_log_term(1);
         m.set_index(1);
         r.step(m).expect("");
 
         let msgs = r.read_messages();
-        let mut m = new_message(1, nvote, MessageType::MsgRequestVoteResponse, 0);
+        let mut m = new_message(3, nvote, MessageType::MsgRequestVoteResponse, 0);
         m.set_term(1);
         m.set_reject(wreject);
         let expect_msgs = vec![m];
@@ -698,6 +699,7 @@ fn test_follower_check_msg_append() {
         let mut wm = new_message(1, 2, MessageType::MsgAppendResponse, 0);
         wm.set_term(2);
         wm.set_index(windex);
+        wm.set_commit(1);
         if wreject {
             wm.set_reject(wreject);
             wm.set_reject_hint(wreject_hint);
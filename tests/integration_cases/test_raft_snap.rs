@@ -25,9 +25,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::{Arc, Mutex};
+
 use crate::test_util::*;
 use harness::{setup_for_test, Network};
+use prost::Message as ProstMsg;
 use raft::eraftpb::*;
+use raft::storage::MemStorage;
+use raft::{Error, ProgressState, Storage, StorageError};
 
 fn testing_snap() -> Snapshot {
     new_snapshot(11, 11, vec![1, 2])
@@ -56,6 +61,70 @@ fn test_sending_snapshot_set_pending_snapshot() {
     assert_eq!(sm.prs().get(2).unwrap().pending_snapshot, 11);
 }
 
+#[test]
+fn test_peers_receiving_snapshot() {
+    setup_for_test();
+    let mut sm = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    sm.restore(new_snapshot(11, 11, vec![1, 2, 3]));
+
+    sm.become_candidate();
+    sm.become_leader();
+    assert!(sm.peers_receiving_snapshot().is_empty());
+
+    sm.mut_prs().get_mut(3).unwrap().become_snapshot(11);
+    assert_eq!(sm.peers_receiving_snapshot(), vec![3]);
+
+    let mut m = new_message(3, 1, MessageType::MsgSnapStatus, 0);
+    m.set_reject(true);
+    sm.step(m).expect("");
+    assert!(sm.peers_receiving_snapshot().is_empty());
+}
+
+#[test]
+fn test_set_peer_snapshot_pending() {
+    setup_for_test();
+    let mut sm = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+    sm.restore(testing_snap());
+    assert_eq!(sm.set_peer_snapshot_pending(2, 11), Err(Error::NotLeader));
+
+    sm.become_candidate();
+    sm.become_leader();
+    sm.mut_prs().get_mut(2).unwrap().matched = 5;
+    sm.read_messages();
+    match sm.set_peer_snapshot_pending(3, 11) {
+        Err(Error::NotExists(3, _)) => (),
+        res => panic!("expected NotExists, got {:?}", res),
+    }
+    match sm.set_peer_snapshot_pending(1, 11) {
+        Err(Error::ViolatesContract(_)) => (),
+        res => panic!("expected ViolatesContract, got {:?}", res),
+    }
+
+    // A peer that isn't recently active isn't sent a snapshot.
+    sm.set_peer_snapshot_pending(2, 11).expect("");
+    assert_eq!(sm.prs().get(2).unwrap().state, ProgressState::Probe);
+    assert!(sm.read_messages().is_empty());
+
+    sm.mut_prs().get_mut(2).unwrap().recent_active = true;
+    sm.set_peer_snapshot_pending(2, 11).expect("");
+    {
+        let pr = sm.prs().get(2).unwrap();
+        assert_eq!(pr.state, ProgressState::Snapshot);
+        assert_eq!(pr.pending_snapshot, 11);
+        assert_eq!(pr.matched, 5);
+        assert!(pr.is_paused());
+    }
+    let msgs = sm.read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgSnapshot);
+    assert_eq!(msgs[0].get_to(), 2);
+    assert_eq!(msgs[0].get_snapshot().get_metadata().get_index(), 11);
+
+    sm.step(new_message(1, 1, MessageType::MsgPropose, 1))
+        .expect("");
+    assert!(sm.read_messages().is_empty());
+}
+
 #[test]
 fn test_pending_snapshot_pause_replication() {
     setup_for_test();
@@ -114,6 +183,32 @@ fn test_snapshot_succeed() {
     assert!(voter_2.paused);
 }
 
+#[test]
+fn test_report_snapshot() {
+    setup_for_test();
+    for (success, next_idx) in vec![(false, 1), (true, 12)] {
+        let mut sm = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+        sm.restore(testing_snap());
+
+        sm.become_candidate();
+        sm.become_leader();
+
+        sm.mut_prs().get_mut(2).unwrap().next_idx = 1;
+        sm.mut_prs().get_mut(2).unwrap().become_snapshot(11);
+
+        // Unknown peers are ignored.
+        sm.report_snapshot(3, success);
+        assert_eq!(sm.prs().get(2).unwrap().state, ProgressState::Snapshot);
+
+        sm.report_snapshot(2, success);
+        let voter_2 = sm.prs().get(2).unwrap();
+        assert_eq!(voter_2.state, ProgressState::Probe);
+        assert_eq!(voter_2.pending_snapshot, 0);
+        assert_eq!(voter_2.next_idx, next_idx);
+        assert!(voter_2.paused);
+    }
+}
+
 #[test]
 fn test_snapshot_abort() {
     setup_for_test();
@@ -151,3 +246,238 @@ fn test_snapshot_with_min_term() {
     do_test(true);
     do_test(false);
 }
+
+fn new_snapshot_chunk(offset: u64, data: &[u8], more: bool) -> Message {
+    let mut m = new_message(1, 2, MessageType::MsgSnapshot, 0);
+    m.set_term(2);
+    m.set_snapshot(testing_snap());
+    m.mut_snapshot().set_data(data.to_vec());
+    m.set_snapshot_chunk_offset(offset);
+    m.set_snapshot_chunk_more(more);
+    m
+}
+
+#[test]
+fn test_restore_chunked_snapshot() {
+    setup_for_test();
+    let mut sm = new_test_raft(2, vec![1, 2], 10, 1, new_storage());
+    let committed = sm.raft_log.committed;
+
+    sm.step(new_snapshot_chunk(0, b"abc", true)).expect("");
+    assert_eq!(sm.raft_log.committed, committed);
+    assert!(sm.raft_log.get_unstable().snapshot.is_none());
+
+    sm.step(new_snapshot_chunk(3, b"de", true)).expect("");
+    assert_eq!(sm.raft_log.committed, committed);
+
+    sm.step(new_snapshot_chunk(5, b"f", false)).expect("");
+    assert_eq!(sm.raft_log.committed, 11);
+    assert_eq!(sm.raft_log.last_index(), 11);
+}
+
+#[test]
+fn test_chunked_snapshot_out_of_order() {
+    setup_for_test();
+    let mut sm = new_test_raft(2, vec![1, 2], 10, 1, new_storage());
+    let committed = sm.raft_log.committed;
+
+    sm.step(new_snapshot_chunk(0, b"abc", true)).expect("");
+    // A gap in the stream discards the partially received snapshot.
+    sm.step(new_snapshot_chunk(4, b"de", true)).expect("");
+    sm.step(new_snapshot_chunk(5, b"f", false)).expect("");
+    assert_eq!(sm.raft_log.committed, committed);
+    assert!(sm.raft_log.get_unstable().snapshot.is_none());
+
+    // The snapshot can be received again from the beginning.
+    sm.step(new_snapshot_chunk(0, b"abcdef", false)).expect("");
+    assert_eq!(sm.raft_log.committed, 11);
+}
+
+#[test]
+fn test_create_snapshot() {
+    setup_for_test();
+    let store = MemStorage::new_with_conf_state((vec![1, 2], vec![]));
+    store
+        .wl()
+        .append(&[empty_entry(1, 2), empty_entry(2, 3), empty_entry(2, 4)])
+        .unwrap();
+    let mut sm = new_test_raft(1, vec![1, 2], 10, 1, store);
+    sm.raft_log.commit_to(4);
+    sm.commit_apply(3).unwrap();
+
+    let mut cs = ConfState::new_();
+    cs.set_nodes(vec![1, 2]);
+    let snap = sm.create_snapshot(3, cs.clone(), vec![7, 8]).unwrap();
+    assert_eq!(snap.get_metadata().get_index(), 3);
+    assert_eq!(snap.get_metadata().get_term(), 2);
+    assert_eq!(snap.get_metadata().get_conf_state(), &cs);
+    assert_eq!(snap.get_metadata().get_pending_membership_change_index(), 0);
+    assert_eq!(snap.get_data(), &[7, 8]);
+    assert_eq!(sm.raft_log.store.snapshot(), Ok(snap));
+
+    // Unapplied entries can't be included in a snapshot.
+    assert!(sm.create_snapshot(4, cs.clone(), vec![]).is_err());
+    // Nor can an older snapshot replace the saved one.
+    assert_eq!(
+        sm.create_snapshot(2, cs, vec![]),
+        Err(Error::Store(StorageError::SnapshotOutOfDate))
+    );
+}
+
+#[test]
+fn test_pending_snapshot_covered_by_follower_commit() {
+    setup_for_test();
+    let mut sm = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+    sm.restore(testing_snap());
+
+    sm.become_candidate();
+    sm.become_leader();
+    sm.set_peer_snapshot_pending(2, 11).expect("");
+
+    // A stale rejection from before the follower caught up keeps the snapshot pending.
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.set_term(sm.term);
+    m.set_index(5);
+    m.set_reject(true);
+    m.set_commit(10);
+    sm.step(m.clone()).expect("");
+    assert_eq!(sm.prs().get(2).unwrap().state, ProgressState::Snapshot);
+    assert!(sm.read_messages().is_empty());
+
+    // Once the follower reports having committed the snapshot index, e.g. after restoring a
+    // snapshot from the previous leader, replication resumes without another snapshot.
+    m.set_commit(11);
+    sm.step(m).expect("");
+    {
+        let pr = sm.prs().get(2).unwrap();
+        assert_eq!(pr.state, ProgressState::Probe);
+        assert_eq!(pr.matched, 11);
+        assert_eq!(pr.next_idx, 12);
+    }
+    let msgs = sm.read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgAppend);
+    assert_eq!(msgs[0].get_index(), 11);
+}
+
+#[test]
+fn test_log_since_snapshot() {
+    setup_for_test();
+    let mut sm = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+    sm.restore(testing_snap());
+    assert_eq!(sm.entries_since_snapshot(), 0);
+    assert_eq!(sm.bytes_since_snapshot(), Ok(0));
+
+    sm.become_candidate();
+    sm.become_leader();
+    sm.step(new_message(1, 1, MessageType::MsgPropose, 2))
+        .expect("");
+    assert_eq!(sm.entries_since_snapshot(), 3);
+    let ents = sm.raft_log.entries(12, None).unwrap();
+    let bytes = ents.iter().map(|e| e.encoded_len() as u64).sum();
+    assert_eq!(sm.bytes_since_snapshot(), Ok(bytes));
+    assert!(bytes > 0);
+}
+
+// A follower flapping in and out of connectivity isn't sent the same snapshot over and over.
+#[test]
+fn test_snapshot_cooldown() {
+    setup_for_test();
+    let mut config = new_test_config(1, 10, 1);
+    config.snapshot_cooldown_ticks = 5;
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2], vec![]));
+    let mut sm = new_test_raft_with_config(&config, store);
+    sm.restore(testing_snap());
+    sm.become_candidate();
+    sm.become_leader();
+    sm.read_messages();
+    sm.mut_prs().get_mut(2).unwrap().next_idx = 1;
+
+    let heartbeat_resp = || new_message(2, 1, MessageType::MsgHeartbeatResponse, 0);
+    let msg_types = |msgs: Vec<Message>| {
+        msgs.iter()
+            .map(|m| m.get_msg_type())
+            .collect::<Vec<MessageType>>()
+    };
+    sm.step(heartbeat_resp()).expect("");
+    assert_eq!(
+        msg_types(sm.read_messages()),
+        vec![MessageType::MsgSnapshot]
+    );
+
+    // The follower drops off before applying the snapshot, then comes back.
+    sm.report_snapshot(2, false);
+    sm.step(heartbeat_resp()).expect("");
+    assert!(sm.read_messages().is_empty());
+    assert_eq!(sm.prs().get(2).unwrap().next_idx, 12);
+
+    // It's probed from the snapshot instead.
+    sm.step(heartbeat_resp()).expect("");
+    let msgs = sm.read_messages();
+    assert_eq!(msg_types(msgs.clone()), vec![MessageType::MsgAppend]);
+    assert_eq!(msgs[0].get_index(), 11);
+
+    // It didn't retain it, but the cooldown isn't over yet.
+    let reject = || {
+        let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+        m.set_index(11);
+        m.set_reject(true);
+        m.set_reject_hint(0);
+        m
+    };
+    sm.step(reject()).expect("");
+    assert!(sm.read_messages().is_empty());
+
+    for _ in 0..5 {
+        sm.tick();
+    }
+    sm.read_messages();
+    sm.step(reject()).expect("");
+    assert_eq!(
+        msg_types(sm.read_messages()),
+        vec![MessageType::MsgSnapshot]
+    );
+}
+
+#[test]
+fn test_snapshot_abort_hook() {
+    setup_for_test();
+    let aborted = Arc::new(Mutex::new(vec![]));
+    let sink = aborted.clone();
+    let mut config = new_test_config(1, 10, 1);
+    config.snapshot_abort_hook = Some(Arc::new(move |id, index| {
+        sink.lock().unwrap().push((id, index))
+    }));
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1, 2, 3], vec![]));
+    let mut sm = new_test_raft_with_config(&config, store);
+    sm.restore(new_snapshot(11, 11, vec![1, 2, 3]));
+    sm.become_candidate();
+    sm.become_leader();
+
+    sm.mut_prs().get_mut(2).unwrap().become_snapshot(11);
+    sm.mut_prs().get_mut(3).unwrap().become_snapshot(11);
+
+    // Not caught up yet.
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.set_index(10);
+    sm.step(m).expect("");
+    // The snapshot reported as applied isn't aborted.
+    sm.report_snapshot(3, true);
+    assert!(aborted.lock().unwrap().is_empty());
+
+    let mut m = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    m.set_index(11);
+    sm.step(m).expect("");
+    assert_eq!(*aborted.lock().unwrap(), vec![(2, 11)]);
+
+    // A follower reporting it already committed the snapshot index aborts it too.
+    sm.mut_prs().get_mut(3).unwrap().become_snapshot(11);
+    let mut m = new_message(3, 1, MessageType::MsgAppendResponse, 0);
+    m.set_index(5);
+    m.set_reject(true);
+    m.set_commit(11);
+    sm.step(m).expect("");
+    assert_eq!(*aborted.lock().unwrap(), vec![(2, 11), (3, 11)]);
+}
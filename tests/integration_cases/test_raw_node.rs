@@ -197,7 +197,7 @@ fn test_raw_node_propose_and_conf_change() {
 
             proposed = true;
         }
-        raw_node.advance(rd);
+        raw_node.advance(rd).expect("");
 
         // Exit when we have 3 entries: one initial configuration, one no-op for the election
         // and proposed ConfChange.
@@ -226,10 +226,10 @@ fn test_raw_node_propose_add_duplicate_node() {
         let rd = raw_node.ready();
         s.wl().append(rd.entries()).expect("");
         if rd.ss().is_some() && rd.ss().unwrap().leader_id == raw_node.raft.id {
-            raw_node.advance(rd);
+            raw_node.advance(rd).expect("");
             break;
         }
-        raw_node.advance(rd);
+        raw_node.advance(rd).expect("");
     }
 
     let mut propose_conf_change_and_apply = |cc| {
@@ -242,7 +242,7 @@ fn test_raw_node_propose_add_duplicate_node() {
                 raw_node.apply_conf_change(&conf_change).ok();
             }
         }
-        raw_node.advance(rd);
+        raw_node.advance(rd).expect("");
     };
 
     let cc1 = conf_change(ConfChangeType::AddNode, 1);
@@ -275,17 +275,17 @@ fn test_raw_node_propose_add_learner_node() -> Result<()> {
     let mut raw_node = new_raw_node(1, vec![1], 10, 1, s.clone());
     let rd = raw_node.ready();
     s.wl().append(rd.entries()).expect("");
-    raw_node.advance(rd);
+    raw_node.advance(rd).expect("");
 
     raw_node.campaign().expect("");
     loop {
         let rd = raw_node.ready();
         s.wl().append(rd.entries()).expect("");
         if rd.ss().is_some() && rd.ss().unwrap().leader_id == raw_node.raft.id {
-            raw_node.advance(rd);
+            raw_node.advance(rd).expect("");
             break;
         }
-        raw_node.advance(rd);
+        raw_node.advance(rd).expect("");
     }
 
     // propose add learner node and check apply state
@@ -318,6 +318,7 @@ fn test_raw_node_read_index() {
     let wrs = vec![ReadState {
         index: 2u64,
         request_ctx: wrequest_ctx.clone(),
+        seq: 0,
     }];
 
     let s = new_storage();
@@ -327,13 +328,13 @@ fn test_raw_node_read_index() {
         let rd = raw_node.ready();
         s.wl().append(rd.entries()).expect("");
         if rd.ss().map_or(false, |ss| ss.leader_id == raw_node.raft.id) {
-            raw_node.advance(rd);
+            raw_node.advance(rd).expect("");
 
             // Once we are the leader, issue a read index request
             raw_node.read_index(wrequest_ctx.clone());
             break;
         }
-        raw_node.advance(rd);
+        raw_node.advance(rd).expect("");
     }
 
     // ensure the read_states can be read out
@@ -342,13 +343,65 @@ fn test_raw_node_read_index() {
     let rd = raw_node.ready();
     assert_eq!(rd.read_states(), wrs.as_slice());
     s.wl().append(&rd.entries()).expect("");
-    raw_node.advance(rd);
+    raw_node.advance(rd).expect("");
 
     // ensure raft.read_states is reset after advance
     assert!(!raw_node.has_ready());
     assert!(raw_node.raft.read_states.is_empty());
 }
 
+// test_raw_node_ping ensures that RawNode.ping broadcasts heartbeats only on the leader.
+#[test]
+fn test_raw_node_ping() {
+    setup_for_test();
+    let mut raw_node = new_raw_node(1, vec![1, 2, 3], 10, 1, new_storage());
+    raw_node.ping();
+    assert!(raw_node.raft.msgs.is_empty());
+
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+    raw_node.raft.msgs.clear();
+    raw_node.ping();
+    let msgs: Vec<_> = raw_node.raft.msgs.drain(..).collect();
+    assert_eq!(msgs.len(), 2);
+    for m in msgs {
+        assert_eq!(m.get_msg_type(), MessageType::MsgHeartbeat);
+    }
+}
+
+// test_raw_node_report_status ensures that RawNode.report_snapshot and
+// RawNode.report_unreachable update the progress of the peer on the leader.
+#[test]
+fn test_raw_node_report_status() {
+    setup_for_test();
+    let mut raw_node = new_raw_node(1, vec![1, 2], 10, 1, new_storage());
+    raw_node.raft.become_candidate();
+    raw_node.raft.become_leader();
+
+    raw_node
+        .raft
+        .mut_prs()
+        .get_mut(2)
+        .unwrap()
+        .become_snapshot(5);
+    raw_node.report_snapshot(2, SnapshotStatus::Failure);
+    let pr = raw_node.raft.prs().get(2).unwrap();
+    assert_eq!(pr.state, ProgressState::Probe);
+    assert_eq!(pr.pending_snapshot, 0);
+
+    raw_node
+        .raft
+        .mut_prs()
+        .get_mut(2)
+        .unwrap()
+        .become_replicate();
+    raw_node.report_unreachable(2);
+    assert_eq!(
+        raw_node.raft.prs().get(2).unwrap().state,
+        ProgressState::Probe
+    );
+}
+
 // test_raw_node_start ensures that a node can be started correctly.
 #[test]
 fn test_raw_node_start() {
@@ -360,12 +413,12 @@ fn test_raw_node_start() {
     must_cmp_ready(&rd, &None, &None, &[], vec![], false);
 
     store.wl().append(rd.entries()).unwrap();
-    raw_node.advance(rd);
+    raw_node.advance(rd).expect("");
 
     raw_node.campaign().expect("");
     let rd = raw_node.ready();
     store.wl().append(rd.entries()).expect("");
-    raw_node.advance(rd);
+    raw_node.advance(rd).expect("");
 
     raw_node.propose(vec![], b"foo".to_vec()).expect("");
     let rd = raw_node.ready();
@@ -378,10 +431,42 @@ fn test_raw_node_start() {
         false,
     );
     store.wl().append(rd.entries()).expect("");
-    raw_node.advance(rd);
+    raw_node.advance(rd).expect("");
     assert!(!raw_node.has_ready());
 }
 
+// test_raw_node_on_persist_ready ensures that with async storage writes, advancing a ready
+// doesn't count its entries as persisted until reported so.
+#[test]
+fn test_raw_node_on_persist_ready() {
+    setup_for_test();
+    let mut config = new_test_config(1, 10, 1);
+    config.async_storage_writes = true;
+    let store = new_storage();
+    store.initialize_with_conf_state((vec![1], vec![]));
+    let mut raw_node = RawNode::new(&config, store.clone()).unwrap();
+
+    raw_node.campaign().expect("");
+    let rd = raw_node.ready();
+    let (last_index, last_term) = {
+        let e = rd.entries().last().unwrap();
+        (e.get_index(), e.get_term())
+    };
+    store.wl().append(rd.entries()).expect("");
+    raw_node.advance(rd).expect("");
+    assert_eq!(raw_node.raft.persisted(), last_index - 1);
+    assert_eq!(raw_node.raft.raft_log.committed, last_index - 1);
+
+    // A report for an entry of another term is stale.
+    raw_node.on_persist_ready(last_index, last_term + 1);
+    assert_eq!(raw_node.raft.persisted(), last_index - 1);
+    assert_eq!(raw_node.raft.raft_log.committed, last_index - 1);
+
+    raw_node.on_persist_ready(last_index, last_term);
+    assert_eq!(raw_node.raft.persisted(), last_index);
+    assert_eq!(raw_node.raft.raft_log.committed, last_index);
+}
+
 #[test]
 fn test_raw_node_restart() {
     setup_for_test();
@@ -396,7 +481,7 @@ fn test_raw_node_restart() {
 
     let rd = raw_node.ready();
     must_cmp_ready(&rd, &None, &None, &[], entries[..1].to_vec(), false);
-    raw_node.advance(rd);
+    raw_node.advance(rd).expect("");
     assert!(!raw_node.has_ready());
 }
 
@@ -417,7 +502,7 @@ fn test_raw_node_restart_from_snapshot() {
 
     let rd = raw_node.ready();
     must_cmp_ready(&rd, &None, &None, &[], entries.clone(), false);
-    raw_node.advance(rd);
+    raw_node.advance(rd).expect("");
     assert!(!raw_node.has_ready());
 }
 